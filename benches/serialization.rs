@@ -0,0 +1,84 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use csc371_remake::{Wallet, WalletBuilder};
+use std::{env, fs, hint::black_box, path::PathBuf, process};
+
+const ENTRY_NAMES: [&str; 10] = [
+    "username",
+    "password",
+    "url",
+    "email",
+    "pin",
+    "notes",
+    "account number",
+    "sort code",
+    "security question",
+    "security answer",
+];
+
+/// 50 categories of 100 items with 10 entries each.
+fn generate_wallet() -> Wallet {
+    let mut builder: WalletBuilder = Wallet::builder();
+    for category in 0..50 {
+        for item in 0..100 {
+            for (entry, name) in ENTRY_NAMES.iter().enumerate() {
+                let seed: u64 = (category * 100 + item) * 10 + entry as u64;
+                builder = builder.entry(
+                    &format!("Category {}", category),
+                    &format!("Item {}", item),
+                    name,
+                    &format!("{:016x}", seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+                );
+            }
+        }
+    }
+    builder.build()
+}
+
+/// Saves and loads the wallet through `Wallet::save` and `Wallet::load`, so each
+/// format is written and read the way the application does it, file included.
+fn serialization(c: &mut Criterion) {
+    let wallet: Wallet = generate_wallet();
+    let directory: PathBuf = env::temp_dir().join(format!("371pass-bench-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let files: Vec<(&str, String)> = ["json", "msgpack"]
+        .iter()
+        .map(|format| {
+            let path: PathBuf = directory.join(format!("wallet.{}", format));
+            (*format, path.to_string_lossy().to_string())
+        })
+        .collect();
+
+    for (format, filename) in &files {
+        wallet.save(filename).unwrap();
+        println!(
+            "50k entries as {}: {} bytes",
+            format,
+            fs::metadata(filename).unwrap().len()
+        );
+    }
+
+    let mut save = c.benchmark_group("save");
+    for (format, filename) in &files {
+        save.bench_function(*format, |b| {
+            b.iter(|| black_box(&wallet).save(filename).unwrap())
+        });
+    }
+    save.finish();
+
+    let mut load = c.benchmark_group("load");
+    for (format, filename) in &files {
+        load.bench_function(*format, |b| {
+            b.iter(|| {
+                let mut loaded: Wallet = Wallet::new();
+                loaded.load(black_box(filename)).unwrap();
+                loaded
+            })
+        });
+    }
+    load.finish();
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
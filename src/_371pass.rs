@@ -1,9 +1,32 @@
 pub mod app {
-    use crate::{category::Category, item::Item, wallet::Wallet};
-    use clap::{arg, Parser};
-    use std::io::{Error, ErrorKind};
-
-    const STUDENT_NUMBER: &str = "851784";
+    use crate::{
+        audit::AuditLog,
+        category::Category,
+        diff::{self, Difference},
+        entry::{self, EntryKind, Totp},
+        finance,
+        format::FileFormat,
+        integrity::Integrity,
+        item::{self, Item, WithoutIds},
+        merge, patch, reference,
+        shamir::{self, Share},
+        share::{self, Identity},
+        store::{self, FileStore, Location, Lock, WalletStore},
+        sync,
+        template::{Problem, Template},
+        transfer::{self, Collision},
+        wallet::Wallet,
+    };
+    use clap::Parser;
+    use serde_json::Value;
+    use std::{
+        collections::BTreeMap,
+        fs,
+        io::{self, Error, ErrorKind},
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+    use uuid::Uuid;
 
     #[derive(Debug, PartialEq)]
     pub(crate) enum Action {
@@ -11,6 +34,23 @@ pub mod app {
         Read,
         Update,
         Delete,
+        Split,
+        Unlock,
+        Share,
+        Log,
+        Merge,
+        Diff,
+        Apply,
+        Migrate,
+        Sync,
+        Totp,
+        Match,
+        Template,
+        Validate,
+        Audit,
+        Mv,
+        Cp,
+        Ids,
     }
 
     #[derive(Parser, Default, Debug, Clone)]
@@ -19,46 +59,217 @@ pub mod app {
     about = "A remake of the CSC371 Module Assignment from the Department of Computer Science at Swansea University written in Rust",
     long_about = None)]
     pub struct Args {
-        /// Path of the database file
+        /// Path of the database file, or a file://, sqlite://, dir:// or mem:// URI
         #[arg(short, long)]
         pub(crate) database: String,
 
+        /// Format of the database file (json, yaml, toml or msgpack) if neither its contents nor its extension say
+        #[arg(long)]
+        pub(crate) db_format: Option<String>,
+
         /// Action to be performed
         #[arg(short, long)]
         pub(crate) action: Option<String>,
 
-        /// Name of the category if present
+        /// Path of the category if present, e.g. Work/AWS for the AWS sub-category of Work, where any name can be @ and the start of the category's ID
         #[arg(short, long)]
         pub(crate) category: Option<String>,
 
-        /// Name of the item if present
+        /// Name of the item if present, or @ and the start of its ID
         #[arg(short, long)]
         pub(crate) item: Option<String>,
 
         /// Name of the entry if present
         #[arg(short, long)]
         pub(crate) entry: Option<String>,
+
+        /// Number of shares needed to recover the key when splitting
+        #[arg(long)]
+        pub(crate) threshold: Option<u8>,
+
+        /// Number of shares to produce when splitting
+        #[arg(long)]
+        pub(crate) parts: Option<u8>,
+
+        /// Shares to recombine when unlocking
+        #[arg(long, num_args = 1..)]
+        pub(crate) shares: Vec<String>,
+
+        /// Public key of the recipient when sharing, or what to migrate to: sqlite, dir, or a json, yaml, toml or msgpack file
+        #[arg(long)]
+        pub(crate) to: Option<String>,
+
+        /// Path of the identity file holding your secret key
+        #[arg(long)]
+        pub(crate) identity: Option<String>,
+
+        /// Path of the key file used to check the wallet's integrity
+        #[arg(long)]
+        pub(crate) mac_key: Option<String>,
+
+        /// Load the wallet even if its integrity check fails, and merge in changes that cannot be checked when syncing
+        #[arg(long)]
+        pub(crate) accept_tampered: bool,
+
+        /// Type of the entry being created or updated: secret, login, url, email, note, date, number, totp, phone, sort-code, account-number, iban, card or expiry
+        #[arg(long)]
+        pub(crate) kind: Option<String>,
+
+        /// Show secret entry values when reading, and all entry values when diffing
+        #[arg(long)]
+        pub(crate) reveal: bool,
+
+        /// Show entry references such as {ref:Websites/Google/password} as written, instead of the values they refer to
+        #[arg(long)]
+        pub(crate) raw: bool,
+
+        /// Print machine-readable JSON instead of text
+        #[arg(long)]
+        pub(crate) json: bool,
+
+        /// What mv and cp do when the destination is taken: fail (the default), overwrite, merge or suffix
+        #[arg(long)]
+        pub(crate) on_collision: Option<String>,
+
+        /// Show what would change without saving
+        #[arg(long)]
+        pub(crate) dry_run: bool,
+
+        /// Change the wallet even if references to what is deleted, renamed or moved would break
+        #[arg(long)]
+        pub(crate) force: bool,
+
+        /// Operation, paths or remote for actions that take them
+        pub(crate) operands: Vec<String>,
     }
 
     pub fn run(args: &Args) -> Result<(), Error> {
+        let action: Action = parse_action_argument(args)?;
+
+        //Actions that need no wallet run here, and the rest are run once it is loaded.
+        let execute: fn(&Args, &mut Wallet) -> Result<(), Error> = match action {
+            Action::Split => return execute_split_action(args),
+            Action::Unlock => return execute_unlock_action(args),
+            Action::Diff => return execute_diff_action(args),
+            Action::Read => |args, w_obj| execute_read_action(args, w_obj),
+            Action::Create => execute_create_action,
+            Action::Update => execute_update_action,
+            Action::Delete => execute_delete_action,
+            Action::Share => execute_share_action,
+            Action::Log => |args, w_obj| execute_log_action(args, w_obj),
+            Action::Merge => execute_merge_action,
+            Action::Apply => execute_apply_action,
+            Action::Migrate => |args, w_obj| execute_migrate_action(args, w_obj),
+            Action::Sync => execute_sync_action,
+            Action::Totp => |args, w_obj| execute_totp_action(args, w_obj),
+            Action::Match => |args, w_obj| execute_match_action(args, w_obj),
+            Action::Template => execute_template_action,
+            Action::Validate => |args, w_obj| execute_validate_action(args, w_obj),
+            Action::Audit => |args, w_obj| execute_audit_action(args, w_obj),
+            Action::Mv => |args, w_obj| execute_transfer_action(args, w_obj, false),
+            Action::Cp => |args, w_obj| execute_transfer_action(args, w_obj, true),
+            Action::Ids => |args, w_obj| execute_ids_action(args, w_obj),
+        };
+
         let db_filename = args.database.clone();
+        let db_format: Option<FileFormat> =
+            args.db_format.as_deref().map(str::parse).transpose()?;
+        let mut store: Box<dyn WalletStore> = store::open(&db_filename, false, db_format)?;
+        let _lock: Lock = store.lock()?;
+
         let mut w_obj: Wallet = Wallet::new();
-        w_obj.load(&db_filename);
+        if let Some(mac_key) = &args.mac_key {
+            w_obj.set_integrity(Integrity::load(mac_key)?.accept_tampered(args.accept_tampered));
+        }
+        //Actions on one item of a SQLite database read and write only that item's rows.
+        let mut scope: Option<(String, String)> = item_scope(args, &action);
+        match &scope {
+            Some((cat_path, item_ident)) => store.load_item(&mut w_obj, cat_path, item_ident)?,
+            None => store.load(&mut w_obj)?,
+        }
+        //References can point anywhere in the wallet, so resolving them needs all of it.
+        if scope.is_some()
+            && action == Action::Read
+            && !args.raw
+            && w_obj
+                .walk()
+                .any(|(.., entry_val)| reference::contains_reference(entry_val))
+        {
+            scope = None;
+            store.load(&mut w_obj)?;
+        }
+        for warning in w_obj.warnings() {
+            eprintln!("{}", warning);
+        }
+        let before: Wallet = w_obj.clone();
+
+        execute(args, &mut w_obj)?;
+
+        if w_obj != before && !args.force {
+            reference::check_not_broken(&before, &w_obj)?;
+        }
 
-        let action: Action = parse_action_argument(args).unwrap();
+        //Only actions that change the wallet or its IDs write it, along with any IDs
+        //given to categories and items the store had none for. Listing the IDs or
+        //migrating in place keeps those alone, so that they stay the same next time.
+        let keeps_new_ids: bool = action == Action::Ids && w_obj.has_new_ids();
+        if w_obj != before
+            || w_obj.ids() != before.ids()
+            || keeps_new_ids
+            || migrates_in_place(args, &action)
+        {
+            //The changes are logged before they are saved, so that none are saved
+            //unlogged. In-memory wallets have nowhere to keep an audit log.
+            if let Some(path) = Location::parse(&db_filename)?.local_path() {
+                let action_name: String = args.action.clone().unwrap_or_default();
+                AuditLog::for_database(path, w_obj.get_integrity()).record_changes(
+                    &action_name,
+                    &before,
+                    &w_obj,
+                )?;
+            }
+            match &scope {
+                Some((cat_path, item_ident)) => store.save_item(&w_obj, cat_path, item_ident)?,
+                None => store.save(&w_obj)?,
+            }
+        }
+        Ok(())
+    }
 
-        match action {
-            Action::Read => execute_read_action(args, &mut w_obj),
-            Action::Create => execute_create_action(args, &mut w_obj),
-            Action::Update => execute_update_action(args, &mut w_obj),
-            Action::Delete => execute_delete_action(args, &mut w_obj),
+    /// The category and item that `action` alone reads or changes, when it is a
+    /// read, create, update or forced delete of one item, so that the rest of the
+    /// wallet need not be loaded. Other deletes and renames look for references to
+    /// what goes.
+    fn item_scope(args: &Args, action: &Action) -> Option<(String, String)> {
+        let scoped: bool = match action {
+            Action::Read | Action::Create | Action::Update => true,
+            Action::Delete => args.force,
+            _ => false,
+        };
+        if !scoped {
+            return None;
         }
+        let category: &String = args.category.as_ref()?;
+        let item: &String = args.item.as_ref()?;
+
+        //Renames and `@` IDs are resolved against the rest of the wallet.
+        let entry: &str = args.entry.as_deref().unwrap_or_default();
+        let renames: bool = *action == Action::Update
+            && (category.contains(':') || item.contains(':') || entry.contains(':'));
+        let by_id: bool = category
+            .split('/')
+            .chain([item.as_str()])
+            .any(|segment| segment.starts_with('@'));
+        if renames || by_id {
+            return None;
+        }
+        Some((category.clone(), item.clone()))
     }
 
     pub(crate) fn parse_action_argument(args: &Args) -> Result<Action, Error> {
-        let action: String = args.action.clone().unwrap().to_uppercase();
+        let action: Option<String> = args.action.as_deref().map(str::to_uppercase);
 
-        match Some(action.as_str()) {
+        match action.as_deref() {
             None => Err(Error::new(
                 ErrorKind::InvalidInput,
                 "No action argument provided.",
@@ -67,6 +278,24 @@ pub mod app {
             Some("READ") => Ok(Action::Read),
             Some("UPDATE") => Ok(Action::Update),
             Some("DELETE") => Ok(Action::Delete),
+            Some("SPLIT") => Ok(Action::Split),
+            Some("UNLOCK") => Ok(Action::Unlock),
+            Some("SHARE") => Ok(Action::Share),
+            Some("LOG") => Ok(Action::Log),
+            Some("MERGE") => Ok(Action::Merge),
+            Some("DIFF") => Ok(Action::Diff),
+            Some("APPLY") => Ok(Action::Apply),
+            //Converting is migrating to another single-file format.
+            Some("MIGRATE") | Some("CONVERT") => Ok(Action::Migrate),
+            Some("SYNC") => Ok(Action::Sync),
+            Some("TOTP") => Ok(Action::Totp),
+            Some("MATCH") => Ok(Action::Match),
+            Some("TEMPLATE") => Ok(Action::Template),
+            Some("VALIDATE") => Ok(Action::Validate),
+            Some("AUDIT") => Ok(Action::Audit),
+            Some("MV") => Ok(Action::Mv),
+            Some("CP") => Ok(Action::Cp),
+            Some("IDS") => Ok(Action::Ids),
             Some(_) => Err(Error::new(
                 ErrorKind::InvalidInput,
                 "Invalid action argument.",
@@ -76,6 +305,7 @@ pub mod app {
 
     fn execute_create_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
         let args = args.clone();
+        let kind: Option<EntryKind> = get_kind_argument(&args)?;
 
         if args.category.is_none() && (args.item.is_some() || args.entry.is_some()) {
             return Err(Error::new(
@@ -100,37 +330,80 @@ pub mod app {
             return Ok(());
         }
 
+        let template: Option<Template> = new_category.template().cloned();
         let new_item: &mut Item = new_category.new_item(&args.item.unwrap());
 
-        if args.entry.is_none() {
-            return Ok(());
-        }
+        if let Some(entry_input) = args.entry {
+            let entry_delimiter: String = String::from(",");
 
-        let entry_input: String = args.entry.unwrap();
-        let entry_delimiter: String = String::from(",");
+            if entry_input.contains(&entry_delimiter) {
+                let entry_identifier: String =
+                    entry_input.split(&entry_delimiter).collect::<Vec<&str>>()[0].to_string();
+                let entry_value: String =
+                    entry_input.split(&entry_delimiter).collect::<Vec<&str>>()[1].to_string();
 
-        if entry_input.contains(&entry_delimiter) {
-            let entry_identifier: String =
-                entry_input.split(&entry_delimiter).collect::<Vec<&str>>()[0].to_string();
-            let entry_value: String =
-                entry_input.split(&entry_delimiter).collect::<Vec<&str>>()[1].to_string();
+                let kind: Option<EntryKind> = kind.or(new_item.kind(&entry_identifier));
+                set_entry(new_item, &entry_identifier, &entry_value, kind)?;
+            } else {
+                let kind: Option<EntryKind> = kind.or(new_item.kind(&entry_input));
+                set_entry(new_item, &entry_input, "", kind)?;
+            }
+        }
 
-            new_item.add_entry(&entry_identifier, &entry_value);
-        } else {
-            new_item.add_entry(&entry_input, &String::from(""));
+        if let Some(template) = template {
+            for violation in template.violations(new_item) {
+                if violation.problem == Problem::Missing {
+                    eprintln!("Warning: {}.", violation);
+                }
+            }
         }
-        w_obj.save(&args.database);
         Ok(())
     }
 
-    pub(crate) fn execute_read_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
-        let result = generate_wallet_string(args, w_obj).unwrap();
+    pub(crate) fn execute_read_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let result = generate_wallet_string(args, w_obj)?;
         println!("{:?}", result);
         Ok(())
     }
 
-    pub(crate) fn generate_wallet_string(args: &Args, w_obj: &mut Wallet) -> Result<String, Error> {
+    pub(crate) fn generate_wallet_string(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
         let args = args.clone();
+        let masked: Wallet;
+        let w_obj: &Wallet = if args.reveal {
+            w_obj
+        } else {
+            let mut unmasked: Wallet = w_obj.clone();
+            unmasked.mask_sensitive();
+            masked = unmasked;
+            &masked
+        };
+        //Resolved after masking, so a reference to a secret shows its mask unless revealed.
+        //Only the entries being printed are, so a broken reference elsewhere does not matter.
+        let resolved: Wallet;
+        let w_obj: &Wallet = if args.raw {
+            w_obj
+        } else {
+            let cat_path: Option<String> = args.category.as_ref().map(|c| w_obj.resolve_path(c));
+            let item_name: Option<String> = match (&cat_path, &args.item) {
+                (Some(c), Some(i)) => w_obj
+                    .get(c)
+                    .and_then(|category| category.item_name(i))
+                    .map(String::from),
+                _ => None,
+            };
+            resolved = reference::resolve_selected(w_obj, |category, item, key| {
+                let Some(cat_path) = &cat_path else {
+                    return true;
+                };
+                if args.item.is_none() {
+                    return category == cat_path || category.starts_with(&format!("{}/", cat_path));
+                }
+                category == cat_path
+                    && Some(item) == item_name.as_deref()
+                    && args.entry.as_ref().is_none_or(|entry| entry == key)
+            })?;
+            &resolved
+        };
 
         if args.category.is_none() && (args.item.is_some() || args.entry.is_some()) {
             return Err(Error::new(
@@ -186,7 +459,7 @@ pub mod app {
         let key_delimiter: char = ':';
         let cat_input: String = args.clone().category.unwrap();
 
-        let cur_cat_ident: String = if cat_input.contains(",") {
+        let cur_cat_ident: String = if cat_input.contains(key_delimiter) {
             cat_input.split(key_delimiter).collect::<Vec<&str>>()[0].to_string()
         } else {
             cat_input.clone()
@@ -199,26 +472,25 @@ pub mod app {
             ));
         }
 
-        let cur_cat: &mut Category = w_obj.get_category(&cur_cat_ident).unwrap();
-        let item_input: String = args.clone().item.unwrap();
-        let cur_item_ident: String = if item_input.contains(key_delimiter) {
-            item_input.split(key_delimiter).collect::<Vec<&str>>()[0].to_string()
-        } else {
-            item_input.clone()
-        };
+        let cur_cat: &mut Category = w_obj
+            .get_mut(&cur_cat_ident)
+            .ok_or_else(category_not_found)?;
 
-        if args.entry.is_some() {
-            process_entry_update(&args, key_delimiter, cur_cat, &cur_item_ident)
-                .expect("TODO: panic message");
-        }
+        if let Some(item_input) = args.item.clone() {
+            let cur_item_ident: String = if item_input.contains(key_delimiter) {
+                item_input.split(key_delimiter).collect::<Vec<&str>>()[0].to_string()
+            } else {
+                item_input.clone()
+            };
+
+            if args.entry.is_some() {
+                process_entry_update(args, key_delimiter, cur_cat, &cur_item_ident)?;
+            }
 
-        if args.item.is_some() {
-            process_item_update(key_delimiter, cur_cat, &item_input, &cur_item_ident)
-                .expect("TODO: panic message");
+            process_item_update(key_delimiter, cur_cat, &item_input, &cur_item_ident)?;
         }
 
-        process_category_update(w_obj, key_delimiter, &cat_input, &cur_cat_ident)
-            .expect("TODO: panic message");
+        process_category_update(w_obj, key_delimiter, &cat_input, &cur_cat_ident)?;
 
         Ok(())
     }
@@ -226,14 +498,13 @@ pub mod app {
     fn process_category_update(
         w_obj: &mut Wallet,
         key_delimiter: char,
-        cat_input: &String,
-        cur_cat_ident: &String,
+        cat_input: &str,
+        cur_cat_ident: &str,
     ) -> Result<(), Error> {
         if cat_input.contains(key_delimiter) {
             let new_cat_ident: String =
                 cat_input.split(key_delimiter).collect::<Vec<&str>>()[1].to_string();
-
-            let cur_cat: &mut Category = w_obj.get_category(&cur_cat_ident).unwrap();
+            let cur_cat_ident: &str = &w_obj.resolve_path(cur_cat_ident);
 
             if new_cat_ident.is_empty() {
                 return Err(Error::new(
@@ -241,11 +512,16 @@ pub mod app {
                     "Error: new category identifier cannot be empty.",
                 ));
             }
+            if new_cat_ident.starts_with(&format!("{}/", cur_cat_ident)) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Error: a category cannot be moved into one of its own sub-categories.",
+                ));
+            }
 
-            cur_cat.set_ident(&new_cat_ident);
-            let new_cat = cur_cat.clone();
-            w_obj.add_category(new_cat);
-            w_obj.delete_category(&cur_cat_ident);
+            //The new identifier is a path, so renaming can also move the category.
+            let new_cat: Category = w_obj.remove_category(cur_cat_ident).unwrap();
+            w_obj.insert_category(&new_cat_ident, new_cat);
         }
         Ok(())
     }
@@ -253,13 +529,13 @@ pub mod app {
     fn process_item_update(
         key_delimiter: char,
         cur_cat: &mut Category,
-        item_input: &String,
-        cur_item_ident: &String,
+        item_input: &str,
+        cur_item_ident: &str,
     ) -> Result<(), Error> {
         if item_input.contains(key_delimiter) {
             let new_item_ident: String =
                 item_input.split(key_delimiter).collect::<Vec<&str>>()[1].to_string();
-            let cur_item: &mut Item = cur_cat.get_item(&cur_item_ident).unwrap();
+            cur_cat.get(cur_item_ident).ok_or_else(item_not_found)?;
 
             if new_item_ident.is_empty() {
                 return Err(Error::new(
@@ -267,10 +543,7 @@ pub mod app {
                     "Error: new item identifier cannot be empty.",
                 ));
             }
-            cur_item.set_ident(&new_item_ident);
-            let new_item = cur_item.clone();
-            cur_cat.add_item(&new_item);
-            cur_cat.delete_item(&cur_item_ident);
+            cur_cat.rename_item(cur_item_ident, &new_item_ident);
         }
         Ok(())
     }
@@ -279,9 +552,9 @@ pub mod app {
         args: &Args,
         key_delimiter: char,
         cur_cat: &mut Category,
-        cur_item_ident: &String,
+        cur_item_ident: &str,
     ) -> Result<(), Error> {
-        let cur_item: &mut Item = cur_cat.get_item(&cur_item_ident).unwrap();
+        let cur_item: &mut Item = cur_cat.get_mut(cur_item_ident).ok_or_else(item_not_found)?;
         let entry_input: String = args.clone().entry.unwrap();
         let value_delimiter: char = ',';
 
@@ -293,12 +566,14 @@ pub mod app {
 
         // if !entry_input.contains([key_delimiter, value_delimiter]) {}
 
+        let kind: Option<EntryKind> = get_kind_argument(args)?.or(cur_item.kind(&entry_ident));
+
         if entry_input.contains(key_delimiter) && entry_input.contains(value_delimiter) {
             let new_entry_ident = input_vec[1].to_string();
             let new_entry_val = input_vec[2].to_string();
 
-            cur_item.add_entry(&new_entry_ident, &new_entry_val);
             cur_item.delete_entry(&entry_ident);
+            set_entry(cur_item, &new_entry_ident, &new_entry_val, kind)?;
         } else if entry_input.contains(key_delimiter) {
             let new_entry_ident: String = input_vec[1].to_string();
 
@@ -309,18 +584,53 @@ pub mod app {
                 ));
             }
 
-            let new_entry_val = cur_item.get_entry(&entry_ident).unwrap().clone();
-            cur_item.add_entry(&new_entry_ident, &new_entry_val);
+            let new_entry_val: String = cur_item
+                .get(&entry_ident)
+                .ok_or_else(entry_not_found)?
+                .to_string();
             cur_item.delete_entry(&entry_ident);
+            set_entry(cur_item, &new_entry_ident, &new_entry_val, kind)?;
         } else if entry_input.contains(value_delimiter) {
             let new_entry_val = input_vec[1].to_string();
             cur_item.delete_entry(&entry_ident);
-            cur_item.add_entry(&entry_ident, &new_entry_val);
+            set_entry(cur_item, &entry_ident, &new_entry_val, kind)?;
+        } else if args.kind.is_some() {
+            let entry_val: String = cur_item
+                .get(&entry_ident)
+                .ok_or_else(entry_not_found)?
+                .to_string();
+            set_entry(cur_item, &entry_ident, &entry_val, kind)?;
         }
 
         Ok(())
     }
 
+    /// Sets an entry after checking its value against its kind. What a reference
+    /// refers to can change, so values with one are not checked.
+    fn set_entry(
+        item: &mut Item,
+        key: &str,
+        value: &str,
+        kind: Option<EntryKind>,
+    ) -> Result<(), Error> {
+        if key == item::ID_KEY {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: @id is reserved for the item's ID and cannot name an entry.",
+            ));
+        }
+        if let Some(kind) = kind.filter(|_| !reference::contains_reference(value)) {
+            kind.validate(value)?;
+        }
+        item.add_entry(key, value);
+        item.set_kind(key, kind);
+        Ok(())
+    }
+
+    fn get_kind_argument(args: &Args) -> Result<Option<EntryKind>, Error> {
+        Ok(args.kind.as_deref().map(str::parse).transpose()?)
+    }
+
     fn execute_delete_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
         if args.category.is_none() {
             return Err(Error::new(
@@ -346,191 +656,1828 @@ pub mod app {
         let item_str = args.item.clone().unwrap();
 
         if args.entry.is_none() {
-            w_obj.get_category(&cat_str).unwrap().delete_item(&item_str);
+            w_obj
+                .get_mut(&cat_str)
+                .ok_or_else(category_not_found)?
+                .delete_item(&item_str);
             return Ok(());
         }
 
         w_obj
-            .get_category(&cat_str)
-            .unwrap()
-            .get_item(&item_str)
-            .unwrap()
+            .get_mut(&cat_str)
+            .ok_or_else(category_not_found)?
+            .get_mut(&item_str)
+            .ok_or_else(item_not_found)?
             .delete_entry(&args.entry.clone().unwrap());
 
         Ok(())
     }
 
-    fn get_wallet_json(w: &Wallet) -> String {
-        serde_json::to_string(w).unwrap()
+    /// Moves or copies the item given by `-i`, or else the whole category given by
+    /// `-c`, to the category path or new category path in the first operand.
+    fn execute_transfer_action(args: &Args, w_obj: &mut Wallet, copy: bool) -> Result<(), Error> {
+        let cat_path: &String = args
+            .category
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No category argument provided."))?;
+        let destination: &String = args.operands.first().ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "No destination argument provided.")
+        })?;
+        let collision: Collision = args
+            .on_collision
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+
+        let path: String = match &args.item {
+            Some(item_ident) => {
+                transfer::transfer_item(w_obj, cat_path, item_ident, destination, collision, copy)?
+            }
+            None => transfer::transfer_category(w_obj, cat_path, destination, collision, copy)?,
+        };
+        println!("{} to {}.", if copy { "Copied" } else { "Moved" }, path);
+        Ok(())
     }
 
-    fn get_category_json(w: &mut Wallet, c: &String) -> String {
-        serde_json::to_string(w.get_category(c).unwrap()).unwrap()
+    fn execute_ids_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let ids: String = generate_ids_string(args, w_obj)?;
+        if !ids.is_empty() {
+            println!("{}", ids);
+        }
+        Ok(())
     }
 
-    fn get_item_json(w: &mut Wallet, c: &String, i: &String) -> String {
-        serde_json::to_string(w.get_category(c).unwrap().get_item(i).unwrap()).unwrap()
-    }
+    /// Lists the IDs of every category and item, or of the category argument and
+    /// everything in it, so that they can be addressed as `@<id>` whatever their
+    /// names. Category paths end in `/`.
+    pub(crate) fn generate_ids_string(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
+        let ids: BTreeMap<String, Uuid> = match &args.category {
+            Some(cat_path) => {
+                let cat_path: String = w_obj.resolve_path(cat_path);
+                w_obj.get(&cat_path).ok_or_else(category_not_found)?;
+                let prefix: String = format!("{}/", cat_path);
+                w_obj
+                    .ids()
+                    .into_iter()
+                    .filter(|(path, _)| path.starts_with(&prefix))
+                    .collect()
+            }
+            None => w_obj.ids(),
+        };
 
-    fn get_entry_json(w: &mut Wallet, c: &String, i: &String, e: &String) -> String {
-        serde_json::to_string(
-            w.get_category(c)
-                .unwrap()
-                .get_item(i)
-                .unwrap()
-                .get_entry(e)
-                .unwrap(),
-        )
-        .unwrap()
+        if args.json {
+            let ids: Vec<Value> = ids
+                .iter()
+                .map(|(path, id)| serde_json::json!({"path": path, "id": id}))
+                .collect();
+            return Ok(Value::Array(ids).to_string());
+        }
+        Ok(ids
+            .iter()
+            .map(|(path, id)| format!("{} {}", id, path))
+            .collect::<Vec<String>>()
+            .join("\n"))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{_371pass::app, wallet::Wallet};
-    use std::{
-        fs,
-        io::Write,
-        io::{Error, ErrorKind},
-        path::Path,
-    };
+    fn execute_split_action(args: &Args) -> Result<(), Error> {
+        let mut recovery_key: String = String::new();
+        io::stdin().read_line(&mut recovery_key)?;
 
-    #[test]
-    fn test_args_parsing() {
-        let mut args = app::Args {
-            database: String::from("test"),
-            action: Some(String::from("invalid")),
-            category: None,
-            item: None,
-            entry: None,
-        };
+        for share in split_recovery_key(args, recovery_key.trim())? {
+            println!("{}", share);
+        }
+        Ok(())
+    }
 
-        let expected_error = Error::new(ErrorKind::InvalidInput, "Invalid action argument.");
-        let result = app::parse_action_argument(&args);
+    pub(crate) fn split_recovery_key(
+        args: &Args,
+        recovery_key: &str,
+    ) -> Result<Vec<String>, Error> {
+        if args.threshold.is_none() || args.parts.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: missing threshold or parts argument(s).",
+            ));
+        }
 
-        assert!(result.is_err());
-        assert_eq!(result.as_ref().unwrap_err().kind(), expected_error.kind());
-        assert_eq!(
-            result.as_ref().unwrap_err().to_string(),
-            expected_error.to_string()
-        );
+        let shares: Vec<Share> = shamir::split(
+            recovery_key.as_bytes(),
+            args.threshold.unwrap(),
+            args.parts.unwrap(),
+        )?;
+        Ok(shares.iter().map(Share::to_string).collect())
+    }
 
-        args.action = Some(String::from("create"));
-        let result = app::parse_action_argument(&args);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), app::Action::Create);
+    fn execute_unlock_action(args: &Args) -> Result<(), Error> {
+        println!("{}", unlock_recovery_key(args)?);
+        Ok(())
+    }
 
-        args.action = Some(String::from("read"));
-        let result = app::parse_action_argument(&args);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), app::Action::Read);
+    pub(crate) fn unlock_recovery_key(args: &Args) -> Result<String, Error> {
+        if args.shares.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "No shares argument provided.",
+            ));
+        }
 
-        args.action = Some(String::from("update"));
-        let result = app::parse_action_argument(&args);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), app::Action::Update);
+        let shares: Vec<Share> = args
+            .shares
+            .iter()
+            .map(|share| share.parse::<Share>())
+            .collect::<Result<Vec<Share>, Error>>()?;
+        let recovery_key: Vec<u8> = shamir::combine(&shares)?;
+
+        String::from_utf8(recovery_key).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Error: recovered key is not valid text, check the shares are from the same split.",
+            )
+        })
+    }
 
-        args.action = Some(String::from("delete"));
-        let result = app::parse_action_argument(&args);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), app::Action::Delete);
+    fn execute_share_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
+        match args.operands.first().map(String::as_str) {
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "No share operation provided.",
+            )),
+            Some("keygen") => {
+                let identity: Identity = Identity::generate();
+                identity.save(&get_identity_argument(args)?)?;
+                println!("{}", identity.public_key());
+                Ok(())
+            }
+            Some("export") => {
+                println!("{}", export_shared_bundle(args, w_obj)?);
+                Ok(())
+            }
+            Some("import") => {
+                let bundle_filename: &String = args.operands.get(1).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidInput, "No bundle file provided.")
+                })?;
+                let bundle: String = fs::read_to_string(bundle_filename)?;
+                import_shared_bundle(args, w_obj, &bundle)?;
+                Ok(())
+            }
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid share operation.",
+            )),
+        }
     }
 
-    #[test]
-    fn test_create_action() {
-        let file_path: String = String::from("./tests/testcreate.json");
-        assert!(Path::new(&file_path).exists());
-        let data = String::from(
-            r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"12-34-56"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"},"Twitter":{"password":"r43rfsffdsfdsf","url":"https://www.twitter.com/","username":"example@gmail.com"}}}"#,
-        );
-        let mut file: fs::File = fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&file_path)
-            .expect("Unable to open file");
-        assert!(file.write(data.as_bytes()).is_ok());
+    pub(crate) fn export_shared_bundle(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
+        let path: &String = args
+            .operands
+            .get(1)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No path argument provided."))?;
+        let recipient: &String = args
+            .to
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No recipient provided."))?;
+
+        //The path is a category, or a category followed by one of its items.
+        let (cat_path, item_ident) = match (w_obj.get(path), path.rsplit_once('/')) {
+            (None, Some((cat_path, item_ident))) => (cat_path, Some(item_ident)),
+            _ => (path.as_str(), None),
+        };
 
-        let test_category_ident: String = String::from("Test Category");
-        let test_item_ident: String = String::from("Test Item");
-        let test_entry_key: String = String::from("Test Entry Key");
-        let test_entry_value: String = String::from("Test Entry Value");
-        let test_entry_arg: String = format!("{},{}", test_entry_key, test_entry_value);
+        let not_found = || Error::new(ErrorKind::NotFound, "Error: path not found.");
+        let category: &Category = w_obj.get(cat_path).ok_or_else(not_found)?;
 
-        let args = app::Args {
-            database: file_path.clone(),
-            action: Some(String::from("create")),
-            category: Some(test_category_ident.clone()),
-            item: Some(test_item_ident.clone()),
-            entry: None,
-        };
+        let mut shared: Wallet = Wallet::new();
+        match item_ident {
+            Some(item_ident) => {
+                let item: &Item = category.get(item_ident).ok_or_else(not_found)?;
+                let shared_category: &mut Category = shared.new_category(cat_path);
+                shared_category.set_id(category.id());
+                shared_category.add_item(item);
+            }
+            None => {
+                shared.insert_category(cat_path, category.clone());
+            }
+        }
+        //Unlike read output, the bundle keeps the IDs.
+        share::seal(serde_json::to_string(&shared)?.as_bytes(), recipient)
+    }
 
-        assert!(app::run(&args).is_ok());
-        let mut w_obj1 = Wallet::new();
-        assert!(w_obj1.empty());
-        assert!(w_obj1.load(&file_path));
+    pub(crate) fn import_shared_bundle(
+        args: &Args,
+        w_obj: &mut Wallet,
+        bundle: &str,
+    ) -> Result<(), Error> {
+        let identity: Identity = Identity::load(&get_identity_argument(args)?)?;
+        let contents: Vec<u8> = share::open(bundle, &identity)?;
+        let contents: String = String::from_utf8(contents)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Error: malformed share bundle."))?;
+
+        let mut shared: Wallet = Wallet::new();
+        shared.load_json(&contents)?;
+        w_obj.merge_categories(&mut shared);
+        Ok(())
+    }
 
-        assert!(w_obj1.get_category(&test_category_ident).is_some());
-        assert_eq!(w_obj1.get_category(&test_category_ident).unwrap().size(), 1);
-        assert!(w_obj1
-            .get_category(&test_category_ident)
-            .unwrap()
-            .get_item(&test_item_ident)
-            .is_some());
-        assert_eq!(
-            w_obj1
-                .get_category(&test_category_ident)
-                .unwrap()
-                .get_item(&test_item_ident)
-                .unwrap()
-                .size(),
-            0
-        );
+    fn execute_log_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let location: Location = Location::parse(&args.database)?;
+        let path: &str = location.local_path().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Error: in-memory wallets have no audit log.",
+            )
+        })?;
+        let log: AuditLog = AuditLog::for_database(path, w_obj.get_integrity());
+
+        match args.operands.first().map(String::as_str) {
+            None => {
+                for record in log.records()? {
+                    println!("{}", record);
+                }
+                Ok(())
+            }
+            Some("verify") => {
+                let records: usize = log.verify()?;
+                if log.is_keyed() {
+                    println!("Audit log verified: {} records.", records);
+                } else {
+                    println!(
+                        "Audit log checked: {} records. Without --mac-key it is not signed, so it could have been rewritten.",
+                        records
+                    );
+                }
+                Ok(())
+            }
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid log operation.",
+            )),
+        }
+    }
 
-        let args = app::Args {
-            database: file_path.clone(),
-            action: Some(String::from("create")),
-            category: Some(test_category_ident.clone()),
-            item: Some(test_item_ident.clone()),
-            entry: Some(test_entry_arg.clone()),
-        };
+    fn execute_merge_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
+        for conflict in merge_wallet_files(args, w_obj)? {
+            eprintln!("{}", conflict);
+        }
+        Ok(())
+    }
 
-        assert!(app::run(&args).is_ok());
-        let mut w_obj3 = Wallet::new();
-        assert!(w_obj3.empty());
-        assert!(w_obj3.load(&file_path));
+    /// Replaces `w_obj` with the three-way merge of the files named by the operands.
+    pub(crate) fn merge_wallet_files(
+        args: &Args,
+        w_obj: &mut Wallet,
+    ) -> Result<Vec<merge::Conflict>, Error> {
+        if args.operands.len() != 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: merge requires base, ours and theirs file arguments.",
+            ));
+        }
 
-        assert!(w_obj3.get_category(&test_category_ident).is_some());
-        assert_eq!(w_obj3.get_category(&test_category_ident).unwrap().size(), 1);
-        assert!(w_obj3
-            .get_category(&test_category_ident)
-            .unwrap()
-            .get_item(&test_item_ident)
-            .is_some());
-        assert_eq!(
-            w_obj3
-                .get_category(&test_category_ident)
-                .unwrap()
-                .get_item(&test_item_ident)
-                .unwrap()
-                .size(),
-            1
-        );
-        assert_eq!(
-            w_obj3
-                .get_category(&test_category_ident)
-                .unwrap()
-                .get_item(&test_item_ident)
-                .unwrap()
-                .get_entry(&test_entry_key)
-                .unwrap(),
-            &test_entry_value
-        );
+        let mut wallets: Vec<Wallet> = Vec::new();
+        for filename in args.operands.iter() {
+            let mut wallet: Wallet = Wallet::new();
+            wallet.load(filename)?;
+            wallets.push(wallet);
+        }
+
+        let (merged, conflicts) = merge::three_way(&wallets[0], &wallets[1], &wallets[2]);
+        *w_obj = merged;
+        Ok(conflicts)
     }
 
-    #[test]
-    fn test_read_action() {
-        let file_path: String = String::from("./tests/testdatabase.json");
-        assert!(Path::new(&file_path).exists());
+    fn execute_diff_action(args: &Args) -> Result<(), Error> {
+        println!("{}", generate_diff_string(args)?);
+        Ok(())
+    }
+
+    pub(crate) fn generate_diff_string(args: &Args) -> Result<String, Error> {
+        if args.operands.len() != 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: diff requires two file arguments.",
+            ));
+        }
+
+        let mut a: Wallet = Wallet::new();
+        a.load(&args.operands[0])?;
+        let mut b: Wallet = Wallet::new();
+        b.load(&args.operands[1])?;
+        let differences: Vec<Difference> = diff::diff(&a, &b);
+
+        if args.json {
+            return Ok(diff::to_json_patch(&differences, args.reveal).to_string());
+        }
+        Ok(differences
+            .iter()
+            .map(|difference| {
+                diff::Display {
+                    difference,
+                    reveal: args.reveal,
+                }
+                .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+
+    fn execute_apply_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
+        let filename: &String = args
+            .operands
+            .first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No change file provided."))?;
+        let patched: Wallet = apply_change_file(args, w_obj, &fs::read_to_string(filename)?)?;
+
+        if args.dry_run {
+            for difference in diff::diff(w_obj, &patched).iter() {
+                println!(
+                    "{}",
+                    diff::Display {
+                        difference,
+                        reveal: args.reveal,
+                    }
+                );
+            }
+            return Ok(());
+        }
+
+        *w_obj = patched;
+        Ok(())
+    }
+
+    /// Applies a JSON Patch or a batch script to a copy of `w_obj`.
+    ///
+    /// Each batch script line is an action followed by the same options as the
+    /// command line, e.g. `update -c Websites -i Google -e "username,new@gmail.com"`.
+    /// Blank lines and lines starting with `#` are ignored. If any line fails, the
+    /// error is returned and nothing is applied.
+    pub(crate) fn apply_change_file(
+        args: &Args,
+        w_obj: &Wallet,
+        contents: &str,
+    ) -> Result<Wallet, Error> {
+        if contents.trim_start().starts_with('[') {
+            let patch: Value = serde_json::from_str(contents)
+                .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?;
+            return patch::apply_json_patch(w_obj, &patch);
+        }
+
+        let mut patched: Wallet = w_obj.clone();
+        for (number, line) in contents.lines().enumerate() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            apply_batch_line(args, &mut patched, line).map_err(|error| {
+                Error::new(
+                    error.kind(),
+                    format!("Error: line {}: {}", number + 1, error),
+                )
+            })?;
+        }
+        Ok(patched)
+    }
+
+    fn apply_batch_line(args: &Args, w_obj: &mut Wallet, line: &str) -> Result<(), Error> {
+        let words: Vec<String> = split_batch_line(line)?;
+        let mut argv: Vec<String> = vec![
+            String::from("371pass"),
+            String::from("--database"),
+            args.database.clone(),
+            String::from("--action"),
+        ];
+        argv.extend(words);
+
+        let line_args: Args = Args::try_parse_from(argv)
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error.to_string()))?;
+
+        match parse_action_argument(&line_args)? {
+            Action::Create => execute_create_action(&line_args, w_obj),
+            Action::Update => execute_update_action(&line_args, w_obj),
+            Action::Delete => execute_delete_action(&line_args, w_obj),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: only create, update and delete are allowed in a batch script.",
+            )),
+        }
+    }
+
+    /// Splits a line on whitespace, keeping double-quoted words together.
+    fn split_batch_line(line: &str) -> Result<Vec<String>, Error> {
+        let mut words: Vec<String> = Vec::new();
+        let mut word: Option<String> = None;
+        let mut quoted: bool = false;
+
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    word.get_or_insert_with(String::new);
+                }
+                c if c.is_whitespace() && !quoted => words.extend(word.take()),
+                c => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+        if quoted {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: unterminated quote.",
+            ));
+        }
+        words.extend(word);
+        Ok(words)
+    }
+
+    /// Whether the action is a migrate with neither `--to` nor a target, which
+    /// rewrites the database itself in the current format, keeping any new IDs.
+    fn migrates_in_place(args: &Args, action: &Action) -> bool {
+        *action == Action::Migrate && args.to.is_none() && args.operands.is_empty()
+    }
+
+    fn execute_migrate_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        if migrates_in_place(args, &Action::Migrate) {
+            //Saved by `run` once the action is done.
+            println!("Migrated {} in place.", args.database);
+            return Ok(());
+        }
+        let target: String = migrate_wallet_file(args, w_obj)?;
+        println!("Migrated {} to {}.", args.database, target);
+        Ok(())
+    }
+
+    /// Writes `w_obj` to a new database given by `--to`: a SQLite database, a
+    /// directory, or a single file in any of the file formats. Without `--to`, the
+    /// target's extension picks the file format.
+    ///
+    /// The target defaults to the database path with the format's extension,
+    /// and an existing file is never overwritten.
+    pub(crate) fn migrate_wallet_file(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
+        let (scheme, format): (&str, Option<FileFormat>) =
+            match args.to.as_deref() {
+                Some("sqlite") => ("sqlite", None),
+                Some("dir") => ("dir", None),
+                Some(to) => match to.parse() {
+                    Ok(format) => ("file", Some(format)),
+                    Err(_) => return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Error: migrate supports --to sqlite, dir, json, yaml, toml or msgpack.",
+                    )),
+                },
+                None => ("file", None),
+            };
+        let extension: &str = match (scheme, format) {
+            ("sqlite", _) => "sqlite",
+            (_, Some(format)) => format.extension(),
+            _ => "",
+        };
+
+        let location: Location = Location::parse(&args.database)?;
+        let target: String = match (args.operands.first(), &args.to, location.local_path()) {
+            (Some(target), _, _) => target.clone(),
+            (None, Some(_), Some(path)) => Path::new(path)
+                .with_extension(extension)
+                .to_string_lossy()
+                .to_string(),
+            (None, None, _) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "No format argument provided.",
+                ))
+            }
+            (None, Some(_), None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "No target argument provided.",
+                ))
+            }
+        };
+        if Path::new(&target).exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Error: {} already exists.", target),
+            ));
+        }
+
+        match scheme {
+            "file" => {
+                let format: FileFormat =
+                    format.unwrap_or_else(|| FileFormat::from_extension(&target));
+                FileStore::new(&target, format).save(w_obj)?;
+            }
+            scheme => w_obj.save(&format!("{}://{}", scheme, target))?,
+        }
+        Ok(target)
+    }
+
+    fn execute_totp_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let unix_time: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        println!("{}", generate_totp_string(args, w_obj, unix_time)?);
+        Ok(())
+    }
+
+    /// The current code of the item's totp entry, or of the entry named by `-e`.
+    pub(crate) fn generate_totp_string(
+        args: &Args,
+        w_obj: &Wallet,
+        unix_time: u64,
+    ) -> Result<String, Error> {
+        let (cat_ident, item_ident) = match (&args.category, &args.item) {
+            (Some(cat_ident), Some(item_ident)) => (cat_ident, item_ident),
+            (None, _) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "No category argument provided.",
+                ))
+            }
+            (Some(_), None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "No item argument provided.",
+                ))
+            }
+        };
+        let item: &Item = w_obj
+            .get(cat_ident)
+            .ok_or_else(category_not_found)?
+            .get(item_ident)
+            .ok_or_else(item_not_found)?;
+
+        let (key, secret) = match &args.entry {
+            Some(entry_ident) => (
+                entry_ident.as_str(),
+                item.get(entry_ident).ok_or_else(entry_not_found)?,
+            ),
+            None => item
+                .entries()
+                .find(|(key, _)| item.kind(key) == Some(EntryKind::Totp))
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::NotFound, "Error: the item has no totp entry.")
+                })?,
+        };
+        let entry_path: String =
+            reference::entry_path(&w_obj.resolve_path(cat_ident), item.get_ident(), key);
+        let totp: Totp = Totp::parse(&reference::resolve(w_obj, &entry_path, secret)?)?;
+        let (code, remaining) = (totp.code(unix_time), totp.remaining(unix_time));
+
+        if args.json {
+            return Ok(serde_json::json!({"code": code, "remaining": remaining}).to_string());
+        }
+        Ok(format!("{} ({}s left)", code, remaining))
+    }
+
+    fn execute_match_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let matches: String = generate_match_string(args, w_obj)?;
+        if !matches.is_empty() {
+            println!("{}", matches);
+        }
+        Ok(())
+    }
+
+    /// Lists the items with a url entry for the site at the URL operand.
+    pub(crate) fn generate_match_string(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
+        let url: &String = args.operands.first().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Error: match requires a URL argument.",
+            )
+        })?;
+
+        let mut matches: Vec<(String, &String)> = Vec::new();
+        for (cat_path, category) in w_obj.all_categories() {
+            for item in category.items() {
+                if item.entries().any(|(key, value)| {
+                    item.kind(key) == Some(EntryKind::Url) && entry::url_matches(value, url)
+                }) {
+                    matches.push((cat_path.clone(), item.get_ident()));
+                }
+            }
+        }
+
+        if args.json {
+            let matches: Vec<Value> = matches
+                .iter()
+                .map(|(category, item)| serde_json::json!({"category": category, "item": item}))
+                .collect();
+            return Ok(Value::Array(matches).to_string());
+        }
+        Ok(matches
+            .iter()
+            .map(|(category, item)| format!("{}/{}", category, item))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+
+    fn execute_template_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
+        let cat_ident: &String = args
+            .category
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No category argument provided."))?;
+
+        match args.operands.first().map(String::as_str) {
+            None | Some("show") => {
+                let template: &Template = w_obj
+                    .get(cat_ident)
+                    .ok_or_else(category_not_found)?
+                    .template()
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::NotFound, "Error: the category has no template.")
+                    })?;
+                println!("{}", serde_json::to_string_pretty(template)?);
+                Ok(())
+            }
+            Some("set") => {
+                let template_filename: &String = args.operands.get(1).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidInput, "No template file provided.")
+                })?;
+                let template: Template = serde_json::from_str(&fs::read_to_string(
+                    template_filename,
+                )?)
+                .map_err(|error| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error: malformed template: {}", error),
+                    )
+                })?;
+                w_obj.new_category(cat_ident).set_template(Some(template));
+                Ok(())
+            }
+            Some("clear") => {
+                w_obj
+                    .get_mut(cat_ident)
+                    .ok_or_else(category_not_found)?
+                    .set_template(None);
+                Ok(())
+            }
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid template operation.",
+            )),
+        }
+    }
+
+    fn execute_validate_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let violations: String = generate_validate_string(args, w_obj)?;
+        if !violations.is_empty() {
+            println!("{}", violations);
+        }
+        Ok(())
+    }
+
+    /// Lists the items that do not match their category's template, in the
+    /// category given by `-c` or in every category.
+    pub(crate) fn generate_validate_string(args: &Args, w_obj: &Wallet) -> Result<String, Error> {
+        let categories: Vec<(String, &Category)> = match &args.category {
+            Some(cat_path) => vec![(
+                cat_path.clone(),
+                w_obj.get(cat_path).ok_or_else(category_not_found)?,
+            )],
+            None => w_obj.all_categories().collect(),
+        };
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut violations: Vec<Value> = Vec::new();
+        for (cat_path, category) in categories {
+            let Some(template) = category.template() else {
+                continue;
+            };
+            for item in category.items() {
+                for violation in template.violations(item) {
+                    lines.push(format!("{}/{}: {}", cat_path, item.get_ident(), violation));
+                    violations.push(serde_json::json!({
+                        "category": cat_path,
+                        "item": item.get_ident(),
+                        "field": violation.field,
+                        "problem": violation.to_string(),
+                    }));
+                }
+            }
+        }
+
+        if args.json {
+            return Ok(Value::Array(violations).to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn execute_audit_action(args: &Args, w_obj: &Wallet) -> Result<(), Error> {
+        let findings: String = generate_audit_string(args, w_obj, entry::today())?;
+        if !findings.is_empty() {
+            println!("{}", findings);
+        }
+        Ok(())
+    }
+
+    /// Lists the cards that have expired, or will within `EXPIRY_WARNING_MONTHS`
+    /// of `today`, going by the items' expiry entries.
+    pub(crate) fn generate_audit_string(
+        args: &Args,
+        w_obj: &Wallet,
+        today: (u32, u32, u32),
+    ) -> Result<String, Error> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut findings: Vec<Value> = Vec::new();
+        for (cat_ident, item_ident, key, value) in w_obj.walk() {
+            let item: Option<&Item> = w_obj
+                .get(&cat_ident)
+                .and_then(|category| category.get(item_ident));
+            if item.and_then(|item| item.kind(key)) != Some(EntryKind::Expiry) {
+                continue;
+            }
+            let Some((year, month)) = finance::parse_expiry(value) else {
+                continue;
+            };
+            let months_left: i64 = finance::months_until_expiry((year, month), today);
+            if months_left > i64::from(finance::EXPIRY_WARNING_MONTHS) {
+                continue;
+            }
+
+            let expired: bool = months_left < 0;
+            lines.push(format!(
+                "{}/{}: card {} {:02}/{}",
+                cat_ident,
+                item_ident,
+                if expired { "expired" } else { "expires" },
+                month,
+                year
+            ));
+            findings.push(serde_json::json!({
+                "category": cat_ident,
+                "item": item_ident,
+                "entry": key,
+                "expires": format!("{}-{:02}", year, month),
+                "expired": expired,
+            }));
+        }
+
+        if args.json {
+            return Ok(Value::Array(findings).to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn execute_sync_action(args: &Args, w_obj: &mut Wallet) -> Result<(), Error> {
+        let directory: &str = match Location::parse(&args.database)? {
+            Location::Directory(directory) => directory,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Error: sync needs a dir:// database.",
+                ))
+            }
+        };
+        let remote: &str = args.operands.first().map_or("origin", String::as_str);
+
+        let merge_unchecked: bool = w_obj.get_integrity().is_none() || args.accept_tampered;
+        let synced = sync::sync(directory, remote, merge_unchecked, !args.force)?;
+        for message in synced.messages {
+            println!("{}", message);
+        }
+        for conflict in synced.conflicts {
+            eprintln!("{}", conflict);
+        }
+
+        //Whatever was merged in could not be checked, so it is read back unchecked and
+        //signed with our key when the wallet is saved, which --accept-tampered allowed.
+        let mut merged: Wallet = Wallet::new();
+        store::open(&args.database, false, None)?.load(&mut merged)?;
+        if let Some(integrity) = w_obj.get_integrity() {
+            if merged != *w_obj {
+                eprintln!(
+                    "Warning: the changes merged from {} were not checked against the MAC key. Signing them anyway.",
+                    remote
+                );
+            }
+            merged.set_integrity(integrity.clone());
+        }
+        *w_obj = merged;
+        Ok(())
+    }
+
+    fn get_identity_argument(args: &Args) -> Result<String, Error> {
+        args.identity
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No identity argument provided."))
+    }
+
+    fn category_not_found() -> Error {
+        Error::new(ErrorKind::NotFound, "Error: category not found.")
+    }
+
+    fn item_not_found() -> Error {
+        Error::new(ErrorKind::NotFound, "Error: item not found.")
+    }
+
+    fn entry_not_found() -> Error {
+        Error::new(ErrorKind::NotFound, "Error: entry not found.")
+    }
+
+    //The IDs are left out of read output, the ids action lists them.
+    fn get_wallet_json(w: &Wallet) -> String {
+        serde_json::to_string(&WithoutIds(w)).unwrap()
+    }
+
+    fn get_category_json(w: &Wallet, c: &str) -> String {
+        serde_json::to_string(&WithoutIds(w.get(c).unwrap())).unwrap()
+    }
+
+    fn get_item_json(w: &Wallet, c: &str, i: &str) -> String {
+        serde_json::to_string(&WithoutIds(w.get(c).unwrap().get(i).unwrap())).unwrap()
+    }
+
+    fn get_entry_json(w: &Wallet, c: &str, i: &str, e: &str) -> String {
+        serde_json::to_string(w.get(c).unwrap().get(i).unwrap().get(e).unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{_371pass::app, share::Identity, wallet::Wallet};
+    use std::{
+        fs,
+        io::Write,
+        io::{Error, ErrorKind},
+        path::Path,
+    };
+
+    /// A copy of the fixture `name` in tests/, for a test to change without
+    /// touching the original.
+    fn fixture_copy(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("371pass-{}-{}", std::process::id(), name));
+        fs::copy(Path::new("./tests").join(name), &path).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    /// Removes a `fixture_copy` and the audit log written next to it.
+    fn remove_copy(file_path: &str) {
+        fs::remove_file(file_path).unwrap();
+        let _ = fs::remove_file(format!("{}.audit", file_path));
+        let _ = fs::remove_file(format!("{}.audit.head", file_path));
+    }
+
+    #[test]
+    fn test_args_parsing() {
+        let mut args = app::Args {
+            database: String::from("test"),
+            action: Some(String::from("invalid")),
+            category: None,
+            item: None,
+            entry: None,
+            ..Default::default()
+        };
+
+        let expected_error = Error::new(ErrorKind::InvalidInput, "Invalid action argument.");
+        let result = app::parse_action_argument(&args);
+
+        assert!(result.is_err());
+        assert_eq!(result.as_ref().unwrap_err().kind(), expected_error.kind());
+        assert_eq!(
+            result.as_ref().unwrap_err().to_string(),
+            expected_error.to_string()
+        );
+
+        args.action = Some(String::from("create"));
+        let result = app::parse_action_argument(&args);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), app::Action::Create);
+
+        args.action = Some(String::from("read"));
+        let result = app::parse_action_argument(&args);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), app::Action::Read);
+
+        args.action = Some(String::from("update"));
+        let result = app::parse_action_argument(&args);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), app::Action::Update);
+
+        args.action = Some(String::from("delete"));
+        let result = app::parse_action_argument(&args);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), app::Action::Delete);
+
+        //Running with a bad or missing action is an error, not a panic.
+        args.action = Some(String::from("invalid"));
+        assert_eq!(
+            app::run(&args).unwrap_err().to_string(),
+            "Invalid action argument."
+        );
+        args.action = None;
+        assert_eq!(
+            app::run(&args).unwrap_err().to_string(),
+            "No action argument provided."
+        );
+    }
+
+    #[test]
+    fn test_split_and_unlock_actions() {
+        let recovery_key: String = String::from("A1B2-C3D4-E5F6-G7H8");
+
+        let mut args = app::Args {
+            database: String::from("test"),
+            action: Some(String::from("split")),
+            threshold: Some(3),
+            parts: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::parse_action_argument(&args).unwrap(),
+            app::Action::Split
+        );
+
+        let shares: Vec<String> = app::split_recovery_key(&args, &recovery_key).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        args.action = Some(String::from("unlock"));
+        assert_eq!(
+            app::parse_action_argument(&args).unwrap(),
+            app::Action::Unlock
+        );
+
+        args.shares = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(app::unlock_recovery_key(&args).unwrap(), recovery_key);
+
+        //Too few shares should be refused.
+        args.shares = vec![shares[1].clone(), shares[3].clone()];
+        assert!(app::unlock_recovery_key(&args).is_err());
+
+        //A mistyped share should be caught before combining.
+        let mut typo: String = shares[1].clone();
+        typo.insert(typo.len() - 10, 'f');
+        args.shares = vec![shares[0].clone(), typo, shares[2].clone()];
+        assert!(app::unlock_recovery_key(&args).is_err());
+
+        //Missing split arguments.
+        args.parts = None;
+        assert!(app::split_recovery_key(&args, &recovery_key).is_err());
+    }
+
+    #[test]
+    fn test_share_export_and_import() {
+        let identity_path =
+            std::env::temp_dir().join(format!("371pass-share-test-{}", std::process::id()));
+        let identity_filename: String = identity_path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&identity_path);
+        let identity: Identity = Identity::generate();
+        assert!(identity.save(&identity_filename).is_ok());
+
+        let mut sender: Wallet = Wallet::new();
+        sender
+            .load_json(
+                r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+            )
+            .unwrap();
+
+        let mut args = app::Args {
+            database: String::from("test"),
+            action: Some(String::from("share")),
+            to: Some(identity.public_key()),
+            operands: vec![String::from("export"), String::from("Websites/Google")],
+            ..Default::default()
+        };
+        let bundle: String = app::export_shared_bundle(&args, &sender).unwrap();
+        assert!(!bundle.contains("pass1234"));
+
+        //The recipient already has a Google item with a different username.
+        let mut recipient: Wallet = Wallet::new();
+        recipient
+            .load_json(r#"{"Websites":{"Google":{"username":"example@gmail.com"}}}"#)
+            .unwrap();
+
+        args.identity = Some(identity_filename.clone());
+        assert!(app::import_shared_bundle(&args, &mut recipient, &bundle).is_ok());
+
+        //The bundle keeps the IDs, so a new item is the same one in both wallets.
+        let mut new_recipient: Wallet = Wallet::new();
+        assert!(app::import_shared_bundle(&args, &mut new_recipient, &bundle).is_ok());
+        let mut shared_ids = sender.ids();
+        shared_ids.remove("Websites/Twitter");
+        assert_eq!(new_recipient.ids(), shared_ids);
+        fs::remove_file(&identity_path).unwrap();
+
+        let web: String = String::from("Websites");
+        let google: String = String::from("Google");
+        let item = recipient.get(&web).unwrap().get(&google).unwrap();
+        assert_eq!(item.size(), 3);
+        assert_eq!(
+            item.get(&String::from("password")).unwrap(),
+            &String::from("pass1234")
+        );
+        assert!(recipient
+            .get(&web)
+            .unwrap()
+            .get(&String::from("Twitter"))
+            .is_none());
+
+        //Unknown paths are rejected.
+        args.operands[1] = String::from("Websites/Missing");
+        assert!(app::export_shared_bundle(&args, &sender).is_err());
+    }
+
+    #[test]
+    fn test_diff_action() {
+        let path = std::env::temp_dir().join(format!("371pass-diff-{}.json", std::process::id()));
+        let file_path: String = path.to_string_lossy().to_string();
+        let args = app::Args {
+            database: String::from("./tests/testload.json"),
+            action: Some(String::from("diff")),
+            operands: vec![String::from("./tests/testload.json"), file_path.clone()],
+            ..Default::default()
+        };
+        let data = String::from(
+            r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"65-43-21"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"}}}"#,
+        );
+        fs::write(&file_path, data).unwrap();
+
+        assert_eq!(
+            app::generate_diff_string(&args).unwrap(),
+            "~ Bank Accounts/Starling/Sort Code: value changed\n- Websites/Twitter"
+        );
+
+        let args = app::Args {
+            reveal: true,
+            json: true,
+            ..args
+        };
+        assert_eq!(
+            app::generate_diff_string(&args).unwrap(),
+            r#"[{"op":"replace","path":"/Bank Accounts/Starling/Sort Code","value":"65-43-21"},{"op":"remove","path":"/Websites/Twitter"}]"#
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_apply_action() {
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(
+                r#"{"Websites":{"Facebook":{"username":"old@gmail.com"},"Google":{"username":"old@gmail.com"}}}"#,
+            )
+            .unwrap();
+        let args = app::Args {
+            database: String::from("test"),
+            action: Some(String::from("apply")),
+            ..Default::default()
+        };
+
+        let script: &str = r#"
+            # Rotate the username after an email change.
+            update -c Websites -i Facebook -e "username,new@gmail.com"
+            update -c Websites -i Google -e "username,new@gmail.com"
+            create -c "Bank Accounts" -i Starling -e "Sort Code,12-34-56"
+            delete -c Websites -i Facebook -e username
+        "#;
+        let patched: Wallet = app::apply_change_file(&args, &w_obj, script).unwrap();
+        let mut expected: Wallet = Wallet::new();
+        expected
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Facebook":{},"Google":{"username":"new@gmail.com"}}}"#,
+            )
+            .unwrap();
+        assert_eq!(patched, expected);
+
+        //A failing line rejects the whole script.
+        let script: &str = "update -c Websites -i Google -e \"username,new@gmail.com\"\nupdate -c Websites -i Twitter -e \"username,new@gmail.com\"";
+        let result = app::apply_change_file(&args, &w_obj, script);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("line 2"));
+
+        let script: &str = "read -c Websites";
+        assert!(app::apply_change_file(&args, &w_obj, script).is_err());
+
+        //JSON Patch documents are detected by their leading bracket.
+        let patch: &str =
+            r#"[{"op": "replace", "path": "/Websites/Google/username", "value": "new@gmail.com"}]"#;
+        let patched: Wallet = app::apply_change_file(&args, &w_obj, patch).unwrap();
+        assert_ne!(patched, w_obj);
+    }
+
+    #[test]
+    fn test_run_with_memory_store() {
+        let database: String = String::from("mem://test-run");
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#)
+            .unwrap();
+        assert!(w_obj.save(&database).is_ok());
+
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("create")),
+            category: Some(String::from("Websites")),
+            item: Some(String::from("Google")),
+            entry: Some(String::from("username,example@gmail.com")),
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(loaded.load(&database).is_ok());
+        let mut expected: Wallet = Wallet::new();
+        expected
+            .load_json(
+                r#"{"Websites":{"Google":{"password":"pass1234","username":"example@gmail.com"}}}"#,
+            )
+            .unwrap();
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn test_nested_categories() {
+        let database: String = String::from("mem://test-nested");
+        assert!(Wallet::new().save(&database).is_ok());
+        let run = |action: &str, category: &str, item: Option<&str>| {
+            app::run(&app::Args {
+                database: database.clone(),
+                action: Some(String::from(action)),
+                category: Some(String::from(category)),
+                item: item.map(String::from),
+                entry: item.map(|_| String::from("password,pass1234")),
+                ..Default::default()
+            })
+        };
+
+        assert!(run("create", "Work/AWS/Prod", Some("root-account")).is_ok());
+        assert!(run("create", "Work", Some("Slack")).is_ok());
+
+        //Renaming to a path moves the category, with its sub-categories.
+        assert!(run("update", "Work/AWS:Cloud/Amazon", None).is_ok());
+        assert!(run("update", "Cloud:Cloud/Amazon/Old", None).is_err());
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+        assert!(w_obj.get("Work/AWS").is_none());
+        assert_eq!(w_obj.get("Work").unwrap().size(), 1);
+        assert_eq!(
+            w_obj.entry_at("Cloud/Amazon/Prod/root-account/password"),
+            Some("pass1234")
+        );
+
+        assert!(run("delete", "Cloud/Amazon", None).is_ok());
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+        assert!(w_obj.get("Cloud").unwrap().empty());
+    }
+
+    #[test]
+    fn test_mv_and_cp_actions() {
+        let database: String = String::from("mem://test-transfer");
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(
+                r#"{"Websites":{"Google":{"password":"pass1234"}},"Personal":{"Google":{"username":"example@gmail.com"}}}"#,
+            )
+            .unwrap();
+        assert!(w_obj.save(&database).is_ok());
+        let transfer =
+            |action: &str, item: Option<&str>, destination: &str, collision: Option<&str>| {
+                app::run(&app::Args {
+                    database: database.clone(),
+                    action: Some(String::from(action)),
+                    category: Some(String::from("Websites")),
+                    item: item.map(String::from),
+                    on_collision: collision.map(String::from),
+                    operands: vec![String::from(destination)],
+                    ..Default::default()
+                })
+            };
+
+        let error: Error = transfer("mv", Some("Google"), "Personal", None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert!(transfer("mv", Some("Google"), "Personal", Some("rename")).is_err());
+        assert!(transfer("cp", Some("Google"), "Personal", Some("merge")).is_ok());
+        assert!(transfer("mv", None, "Archive/Websites", None).is_ok());
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(loaded.load(&database).is_ok());
+        let mut expected: Wallet = Wallet::new();
+        expected
+            .load_json(
+                r#"{"Archive":{"Websites/":{"Google":{"password":"pass1234"}}},"Personal":{"Google":{"password":"pass1234","username":"example@gmail.com"}}}"#,
+            )
+            .unwrap();
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn test_ids_action() {
+        let database: String = String::from("mem://test-ids");
+        let w_obj: Wallet = Wallet::builder()
+            .entry("Websites", "Google", "password", "pass1234")
+            .category("Websites/Social")
+            .build();
+        assert!(w_obj.save(&database).is_ok());
+
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("ids")),
+            category: Some(String::from("Websites")),
+            ..Default::default()
+        };
+        let mut loaded: Wallet = Wallet::new();
+        assert!(loaded.load(&database).is_ok());
+        let websites: String = loaded.get("Websites").unwrap().id().to_string();
+        let google: String = loaded
+            .get("Websites")
+            .unwrap()
+            .get("Google")
+            .unwrap()
+            .id()
+            .to_string();
+        let social: String = loaded.get("Websites/Social").unwrap().id().to_string();
+        assert_eq!(
+            app::generate_ids_string(&args, &loaded).unwrap(),
+            format!(
+                "{} Websites/\n{} Websites/Google\n{} Websites/Social/",
+                websites, google, social
+            )
+        );
+
+        //Renaming by ID keeps the ID, however the item and category are named.
+        args.action = Some(String::from("update"));
+        args.category = Some(format!("@{}:Archive/Social", &social[..8]));
+        assert!(app::run(&args).is_ok());
+        args.category = Some(String::from("Websites"));
+        args.item = Some(format!("@{}:Gmail", &google[..8]));
+        assert!(app::run(&args).is_ok());
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(loaded.load(&database).is_ok());
+        assert_eq!(
+            loaded.get("Archive/Social").unwrap().id().to_string(),
+            social
+        );
+        assert_eq!(
+            loaded
+                .get("Websites")
+                .unwrap()
+                .get("Gmail")
+                .unwrap()
+                .id()
+                .to_string(),
+            google
+        );
+        args.json = true;
+        args.item = None;
+        let ids: serde_json::Value =
+            serde_json::from_str(&app::generate_ids_string(&args, &loaded).unwrap()).unwrap();
+        assert_eq!(
+            ids,
+            serde_json::json!([
+                {"path": "Websites/", "id": websites},
+                {"path": "Websites/Gmail", "id": google}
+            ])
+        );
+
+        //A wallet saved without IDs keeps the ones it is given when they are listed.
+        let file_path: String = fixture_copy("testload.json");
+        let args = app::Args {
+            database: file_path.clone(),
+            action: Some(String::from("ids")),
+            ..Default::default()
+        };
+        let mut legacy: Wallet = Wallet::new();
+        assert!(legacy.load(&file_path).is_ok());
+        assert!(legacy.has_new_ids());
+        assert!(app::run(&args).is_ok());
+        let mut listed: Wallet = Wallet::new();
+        assert!(listed.load(&file_path).is_ok());
+        assert!(!listed.has_new_ids());
+        assert!(app::run(&args).is_ok());
+        let mut relisted: Wallet = Wallet::new();
+        assert!(relisted.load(&file_path).is_ok());
+        assert_eq!(relisted.ids(), listed.ids());
+        remove_copy(&file_path);
+    }
+
+    #[test]
+    fn test_references() {
+        let database: String = String::from("mem://test-references");
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":{"type":"secret","value":"pass1234"},"url":"https://www.google.com/"}}}"#,
+            )
+            .unwrap();
+        assert!(w_obj.save(&database).is_ok());
+
+        //A reference is not checked against the kind, since its target can change.
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("create")),
+            category: Some(String::from("Dashboards")),
+            item: Some(String::from("Grafana")),
+            entry: Some(String::from("url,{ref:Websites/Google/url}")),
+            kind: Some(String::from("url")),
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+        args.entry = Some(String::from("password,{ref:Websites/Google/password}"));
+        args.kind = None;
+        assert!(app::run(&args).is_ok());
+
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("read")),
+            category: Some(String::from("Dashboards")),
+            item: Some(String::from("Grafana")),
+            ..Default::default()
+        };
+        //The secret it refers to stays masked until it is revealed.
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#"{"password":"********","url":{"type":"url","value":"https://www.google.com/"}}"#
+        );
+        args.reveal = true;
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#"{"password":"pass1234","url":{"type":"url","value":"https://www.google.com/"}}"#
+        );
+        args.raw = true;
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#"{"password":"{ref:Websites/Google/password}","url":{"type":"url","value":"{ref:Websites/Google/url}"}}"#
+        );
+
+        //Renaming, moving or deleting what references point to fails unless forced,
+        //whichever action does it.
+        args.action = Some(String::from("update"));
+        args.category = Some(String::from("Websites"));
+        args.item = Some(String::from("Google"));
+        args.entry = Some(String::from("url:link"));
+        let error: Error = app::run(&args).unwrap_err();
+        assert!(error.to_string().contains("Dashboards/Grafana/url"));
+        args.item = Some(String::from("Google:Gmail"));
+        args.entry = None;
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let patch =
+            std::env::temp_dir().join(format!("371pass-references-{}.json", std::process::id()));
+        fs::write(
+            &patch,
+            r#"[{"op": "remove", "path": "/Websites/Google/url"}]"#,
+        )
+        .unwrap();
+        args.action = Some(String::from("apply"));
+        args.operands = vec![patch.to_string_lossy().to_string()];
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::InvalidInput);
+        fs::remove_file(&patch).unwrap();
+
+        args.action = Some(String::from("mv"));
+        args.category = Some(String::from("Websites"));
+        args.item = Some(String::from("Google"));
+        args.entry = None;
+        args.operands = vec![String::from("Websites/Gmail")];
+        let error: Error = app::run(&args).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("Dashboards/Grafana/password"));
+        args.action = Some(String::from("delete"));
+        args.item = None;
+        args.operands = Vec::new();
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let mut unchanged: Wallet = Wallet::new();
+        assert!(unchanged.load(&database).is_ok());
+        assert_eq!(unchanged, w_obj);
+        args.force = true;
+        assert!(app::run(&args).is_ok());
+
+        //Only reads of the broken entries fail, unless they are raw.
+        assert!(w_obj.load(&database).is_ok());
+        args.action = Some(String::from("read"));
+        args.category = None;
+        assert!(app::run(&args).is_ok());
+        args.raw = false;
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::NotFound);
+        args.category = Some(String::from("Dashboards"));
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::NotFound);
+        args.category = Some(String::from("Bank Accounts"));
+        assert!(app::run(&args).is_ok());
+        args.category = Some(String::from("Dashboards"));
+        args.item = Some(String::from("Grafana"));
+        args.entry = Some(String::from("url"));
+        assert_eq!(app::run(&args).unwrap_err().kind(), ErrorKind::NotFound);
+        args.raw = true;
+        assert!(app::run(&args).is_ok());
+    }
+
+    #[test]
+    fn test_typed_entries() {
+        let database: String = String::from("mem://test-typed");
+        assert!(Wallet::new().save(&database).is_ok());
+
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("create")),
+            category: Some(String::from("Websites")),
+            item: Some(String::from("Google")),
+            entry: Some(String::from("url,www.google")),
+            kind: Some(String::from("url")),
+            ..Default::default()
+        };
+        //A value that doesn't fit its kind is rejected.
+        assert!(app::run(&args).is_err());
+
+        args.entry = Some(String::from("url,https://www.google.com/"));
+        assert!(app::run(&args).is_ok());
+        args.entry = Some(String::from("password,pass1234"));
+        args.kind = Some(String::from("secret"));
+        assert!(app::run(&args).is_ok());
+        args.entry = Some(String::from("otp,GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+        args.kind = Some(String::from("totp"));
+        assert!(app::run(&args).is_ok());
+        args.kind = Some(String::from("colour"));
+        assert!(app::run(&args).is_err());
+
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+
+        //Secrets are masked unless revealed.
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("read")),
+            category: Some(String::from("Websites")),
+            item: Some(String::from("Google")),
+            entry: Some(String::from("password")),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#""********""#
+        );
+        let args = app::Args {
+            reveal: true,
+            ..args
+        };
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#""pass1234""#
+        );
+
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("totp")),
+            category: Some(String::from("Websites")),
+            item: Some(String::from("Google")),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_totp_string(&args, &w_obj, 59).unwrap(),
+            "287082 (1s left)"
+        );
+
+        let args = app::Args {
+            database,
+            action: Some(String::from("match")),
+            operands: vec![String::from("https://accounts.google.com/login")],
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_match_string(&args, &w_obj).unwrap(),
+            "Websites/Google"
+        );
+    }
+
+    #[test]
+    fn test_template_and_validate_actions() {
+        let database: String = String::from("mem://test-templates");
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe"}}}"#,
+            )
+            .unwrap();
+        assert!(w_obj.save(&database).is_ok());
+
+        let template_path =
+            std::env::temp_dir().join(format!("371pass-template-{}.json", std::process::id()));
+        fs::write(
+            &template_path,
+            r#"{"fields":[{"name":"Account Number","type":"number","required":true},{"name":"Sort Code","required":true},{"name":"Name"}]}"#,
+        )
+        .unwrap();
+
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("template")),
+            category: Some(String::from("Bank Accounts")),
+            operands: vec![
+                String::from("set"),
+                template_path.to_string_lossy().to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+        fs::remove_file(&template_path).unwrap();
+
+        //New items start with the template's fields.
+        args.action = Some(String::from("create"));
+        args.item = Some(String::from("Santander"));
+        args.entry = Some(String::from("Account Number,87654321"));
+        args.operands = Vec::new();
+        assert!(app::run(&args).is_ok());
+        args.entry = Some(String::from("Account Number,876-54321"));
+        assert!(app::run(&args).is_err());
+
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+        let santander = w_obj
+            .get("Bank Accounts")
+            .unwrap()
+            .get("Santander")
+            .unwrap();
+        assert_eq!(
+            santander.entries().collect::<Vec<_>>(),
+            [
+                ("Account Number", "87654321"),
+                ("Name", ""),
+                ("Sort Code", "")
+            ]
+        );
+
+        //Items made before the template are checked against it too.
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("validate")),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_validate_string(&args, &w_obj).unwrap(),
+            "Bank Accounts/Santander: Sort Code is required\n\
+             Bank Accounts/Starling: Account Number should be a number entry\n\
+             Bank Accounts/Starling: Sort Code is required"
+        );
+
+        let args = app::Args {
+            database,
+            action: Some(String::from("template")),
+            category: Some(String::from("Bank Accounts")),
+            operands: vec![String::from("clear")],
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&args.database).is_ok());
+        assert!(w_obj.get("Bank Accounts").unwrap().template().is_none());
+    }
+
+    #[test]
+    fn test_financial_entries() {
+        let database: String = String::from("mem://test-financial");
+        assert!(Wallet::new().save(&database).is_ok());
+
+        let mut args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("create")),
+            category: Some(String::from("Cards")),
+            item: Some(String::from("Visa")),
+            entry: Some(String::from("number,4111 1111 1111 1112")),
+            kind: Some(String::from("card")),
+            ..Default::default()
+        };
+        //A mistyped digit fails the Luhn check.
+        assert!(app::run(&args).is_err());
+        args.entry = Some(String::from("number,4111 1111 1111 1111"));
+        assert!(app::run(&args).is_ok());
+        args.entry = Some(String::from("expires,11/26"));
+        args.kind = Some(String::from("expiry"));
+        assert!(app::run(&args).is_ok());
+        args.item = Some(String::from("Amex"));
+        args.entry = Some(String::from("expires,03/2031"));
+        assert!(app::run(&args).is_ok());
+        args.item = Some(String::from("Old Mastercard"));
+        args.entry = Some(String::from("expires,01/26"));
+        assert!(app::run(&args).is_ok());
+
+        let mut w_obj: Wallet = Wallet::new();
+        assert!(w_obj.load(&database).is_ok());
+
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("read")),
+            category: Some(String::from("Cards")),
+            item: Some(String::from("Visa")),
+            entry: Some(String::from("number")),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_wallet_string(&args, &w_obj).unwrap(),
+            r#""Visa ending 1111""#
+        );
+
+        let args = app::Args {
+            database,
+            action: Some(String::from("audit")),
+            ..Default::default()
+        };
+        assert_eq!(
+            app::generate_audit_string(&args, &w_obj, (2026, 10, 18)).unwrap(),
+            "Cards/Old Mastercard: card expired 01/2026\nCards/Visa: card expires 11/2026"
+        );
+    }
+
+    #[test]
+    fn test_convert_action() {
+        let path =
+            std::env::temp_dir().join(format!("371pass-convert-{}.json", std::process::id()));
+        let database: String = path.to_string_lossy().to_string();
+        let yaml: String = path.with_extension("yaml").to_string_lossy().to_string();
+        let toml: String = path.with_extension("wallet").to_string_lossy().to_string();
+        let _ = fs::remove_file(&yaml);
+        let _ = fs::remove_file(&toml);
+
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"}}}"#)
+            .unwrap();
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("convert")),
+            to: Some(String::from("yaml")),
+            ..Default::default()
+        };
+        assert_eq!(app::migrate_wallet_file(&args, &w_obj).unwrap(), yaml);
+        assert!(fs::read_to_string(&yaml)
+            .unwrap()
+            .contains("url: https://www.google.com/"));
+
+        let mut converted: Wallet = Wallet::new();
+        assert!(converted.load(&yaml).is_ok());
+        assert_eq!(converted, w_obj);
+
+        //An extension that names no format needs --db-format to be read back.
+        let args = app::Args {
+            to: Some(String::from("toml")),
+            operands: vec![toml.clone()],
+            ..args
+        };
+        assert!(app::migrate_wallet_file(&args, &w_obj).is_ok());
+        assert!(Wallet::new().load(&toml).is_err());
+        let args = app::Args {
+            database: toml.clone(),
+            db_format: Some(String::from("toml")),
+            action: Some(String::from("read")),
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+
+        fs::remove_file(&yaml).unwrap();
+        fs::remove_file(&toml).unwrap();
+        let _ = fs::remove_file(format!("{}.audit", toml));
+        let _ = fs::remove_file(format!("{}.audit.head", toml));
+    }
+
+    #[test]
+    fn test_migrate_action() {
+        let path =
+            std::env::temp_dir().join(format!("371pass-migrate-{}.json", std::process::id()));
+        let database: String = path.to_string_lossy().to_string();
+        let target: String = path.with_extension("sqlite").to_string_lossy().to_string();
+        let _ = fs::remove_file(&target);
+
+        let mut w_obj: Wallet = Wallet::new();
+        w_obj
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"pass1234"}}}"#,
+            )
+            .unwrap();
+        let args = app::Args {
+            database: database.clone(),
+            action: Some(String::from("migrate")),
+            to: Some(String::from("sqlite")),
+            ..Default::default()
+        };
+        assert_eq!(app::migrate_wallet_file(&args, &w_obj).unwrap(), target);
+
+        let mut migrated: Wallet = Wallet::new();
+        assert!(migrated.load(&target).is_ok());
+        assert_eq!(migrated, w_obj);
+
+        //The target is never overwritten.
+        let result = app::migrate_wallet_file(&args, &w_obj);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+        let args = app::Args {
+            to: Some(String::from("csv")),
+            ..args
+        };
+        assert!(app::migrate_wallet_file(&args, &w_obj).is_err());
+
+        fs::remove_file(&target).unwrap();
+    }
+
+    #[test]
+    fn test_create_action() {
+        let file_path: String = fixture_copy("testcreate.json");
+        assert!(Path::new(&file_path).exists());
+        let data = String::from(
+            r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"12-34-56"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"},"Twitter":{"password":"r43rfsffdsfdsf","url":"https://www.twitter.com/","username":"example@gmail.com"}}}"#,
+        );
+        let mut file: fs::File = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&file_path)
+            .expect("Unable to open file");
+        assert!(file.write(data.as_bytes()).is_ok());
+
+        let test_category_ident: String = String::from("Test Category");
+        let test_item_ident: String = String::from("Test Item");
+        let test_entry_key: String = String::from("Test Entry Key");
+        let test_entry_value: String = String::from("Test Entry Value");
+        let test_entry_arg: String = format!("{},{}", test_entry_key, test_entry_value);
+
+        let args = app::Args {
+            database: file_path.clone(),
+            action: Some(String::from("create")),
+            category: Some(test_category_ident.clone()),
+            item: Some(test_item_ident.clone()),
+            entry: None,
+            ..Default::default()
+        };
+
+        assert!(app::run(&args).is_ok());
+        let mut w_obj1 = Wallet::new();
+        assert!(w_obj1.empty());
+        assert!(w_obj1.load(&file_path).is_ok());
+
+        assert!(w_obj1.get(&test_category_ident).is_some());
+        assert_eq!(w_obj1.get(&test_category_ident).unwrap().size(), 1);
+        assert!(w_obj1
+            .get(&test_category_ident)
+            .unwrap()
+            .get(&test_item_ident)
+            .is_some());
+        assert_eq!(
+            w_obj1
+                .get(&test_category_ident)
+                .unwrap()
+                .get(&test_item_ident)
+                .unwrap()
+                .size(),
+            0
+        );
+
+        let args = app::Args {
+            database: file_path.clone(),
+            action: Some(String::from("create")),
+            category: Some(test_category_ident.clone()),
+            item: Some(test_item_ident.clone()),
+            entry: Some(test_entry_arg.clone()),
+            ..Default::default()
+        };
+
+        assert!(app::run(&args).is_ok());
+        let mut w_obj3 = Wallet::new();
+        assert!(w_obj3.empty());
+        assert!(w_obj3.load(&file_path).is_ok());
+
+        assert!(w_obj3.get(&test_category_ident).is_some());
+        assert_eq!(w_obj3.get(&test_category_ident).unwrap().size(), 1);
+        assert!(w_obj3
+            .get(&test_category_ident)
+            .unwrap()
+            .get(&test_item_ident)
+            .is_some());
+        assert_eq!(
+            w_obj3
+                .get(&test_category_ident)
+                .unwrap()
+                .get(&test_item_ident)
+                .unwrap()
+                .size(),
+            1
+        );
+        assert_eq!(
+            w_obj3
+                .get(&test_category_ident)
+                .unwrap()
+                .get(&test_item_ident)
+                .unwrap()
+                .get(&test_entry_key)
+                .unwrap(),
+            &test_entry_value
+        );
+
+        //Nothing is saved that cannot be logged.
+        let saved: String = fs::read_to_string(&file_path).unwrap();
+        fs::write(format!("{}.audit.head", file_path), "1 0\n").unwrap();
+        let args = app::Args {
+            item: Some(String::from("Another Item")),
+            entry: None,
+            ..args
+        };
+        let error: Error = app::run(&args).unwrap_err();
+        assert!(error.to_string().contains("head does not match"));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), saved);
+
+        remove_copy(&file_path);
+    }
+
+    #[test]
+    fn test_read_action() {
+        let file_path: String = fixture_copy("testdatabase.json");
+        assert!(Path::new(&file_path).exists());
         let data = String::from(
             r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"12-34-56"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"},"Twitter":{"password":"r43rfsffdsfdsf","url":"https://www.twitter.com/","username":"example@gmail.com"}}}"#,
         );
@@ -547,20 +2494,34 @@ mod tests {
             category: None,
             item: None,
             entry: None,
+            ..Default::default()
         };
 
         assert!(app::run(&args).is_ok());
         let mut wallet = Wallet::new();
-        wallet.load(&file_path);
-        assert_eq!(
-            data,
-            app::generate_wallet_string(&args, &mut wallet).unwrap()
-        );
+        assert!(wallet.load(&file_path).is_ok());
+        assert_eq!(data, app::generate_wallet_string(&args, &wallet).unwrap());
+
+        //Reading never writes, even though the file has no IDs yet.
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), data);
+
+        //Migrating in place keeps them.
+        let args = app::Args {
+            action: Some(String::from("migrate")),
+            ..args
+        };
+        assert!(app::run(&args).is_ok());
+        let mut migrated: Wallet = Wallet::new();
+        assert!(migrated.load(&file_path).is_ok());
+        assert!(!migrated.has_new_ids());
+        assert_eq!(migrated, wallet);
+
+        remove_copy(&file_path);
     }
 
     #[test]
     fn test_delete_action() {
-        let file_path: String = String::from("./tests/testdelete.json");
+        let file_path: String = fixture_copy("testdelete.json");
         assert!(Path::new(&file_path).exists());
         let data = String::from(
             r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"12-34-56"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"},"Twitter":{"password":"r43rfsffdsfdsf","url":"https://www.twitter.com/","username":"example@gmail.com"}}}"#,
@@ -582,42 +2543,41 @@ mod tests {
             category: Some(test_category.clone()),
             item: Some(test_item.clone()),
             entry: Some(test_entry_key.clone()),
+            ..Default::default()
         };
 
         assert!(app::run(&args).is_ok());
         let mut w_obj = Wallet::new();
         assert!(w_obj.empty());
-        assert!(w_obj.load(&file_path));
+        assert!(w_obj.load(&file_path).is_ok());
         assert!(w_obj
-            .get_category(&test_category)
+            .get(&test_category)
             .unwrap()
-            .get_item(&test_item)
+            .get(&test_item)
             .unwrap()
-            .get_entry(&test_entry_key)
+            .get(&test_entry_key)
             .is_none());
 
         args.entry = None;
         assert!(app::run(&args).is_ok());
         let mut w_obj: Wallet = Wallet::new();
         assert!(w_obj.empty());
-        assert!(w_obj.load(&file_path));
-        assert!(w_obj
-            .get_category(&test_category)
-            .unwrap()
-            .get_item(&test_item)
-            .is_none());
+        assert!(w_obj.load(&file_path).is_ok());
+        assert!(w_obj.get(&test_category).unwrap().get(&test_item).is_none());
 
         args.item = None;
         assert!(app::run(&args).is_ok());
         let mut w_obj: Wallet = Wallet::new();
         assert!(w_obj.empty());
-        assert!(w_obj.load(&file_path));
-        assert!(w_obj.get_category(&test_category).is_none());
+        assert!(w_obj.load(&file_path).is_ok());
+        assert!(w_obj.get(&test_category).is_none());
+
+        remove_copy(&file_path);
     }
 
     #[test]
     fn test_update_action() {
-        let file_path: String = String::from("./tests/testupdate.json");
+        let file_path: String = fixture_copy("testupdate.json");
         assert!(Path::new(&file_path).exists());
         let data = String::from(
             r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678","Name":"Mr John Doe","Sort Code":"12-34-56"}},"Websites":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/","username":"example@gmail.com"},"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"},"Twitter":{"password":"r43rfsffdsfdsf","url":"https://www.twitter.com/","username":"example@gmail.com"}}}"#,
@@ -640,35 +2600,29 @@ mod tests {
         let new_test_entry_value: String = String::from("87654321");
 
         let mut w_obj: Wallet = Wallet::new();
-        assert!(w_obj.load(&file_path));
+        assert!(w_obj.load(&file_path).is_ok());
 
         assert!(w_obj
-            .get_category(&old_test_category)
+            .get(&old_test_category)
             .unwrap()
-            .get_item(&old_test_item)
+            .get(&old_test_item)
             .unwrap()
-            .get_entry(&old_test_entry_key)
+            .get(&old_test_entry_key)
             .is_some());
         assert_eq!(
             w_obj
-                .get_category(&old_test_category)
+                .get(&old_test_category)
                 .unwrap()
-                .get_item(&old_test_item)
+                .get(&old_test_item)
                 .unwrap()
-                .get_entry(&old_test_entry_key)
+                .get(&old_test_entry_key)
                 .unwrap(),
             &old_test_entry_value
         );
-        assert!(w_obj
-            .get_category(&new_test_category)
-            .unwrap()
-            .get_item(&new_test_item)
-            .unwrap()
-            .get_entry(&new_test_entry_key)
-            .is_none());
+        assert!(w_obj.get(&new_test_category).is_none());
 
         let args = app::Args {
-            database: String::from("./tests/testdelete.json"),
+            database: file_path.clone(),
             action: Some(String::from("update")),
             category: Some(format!("{}:{}", old_test_category, new_test_category)),
             item: Some(format!("{}:{}", old_test_item, new_test_item)),
@@ -676,35 +2630,45 @@ mod tests {
                 "{}:{},{}",
                 old_test_entry_key, new_test_entry_key, new_test_entry_value
             )),
+            ..Default::default()
         };
 
         assert!(app::run(&args).is_ok());
         let mut w_obj: Wallet = Wallet::new();
         assert!(w_obj.empty());
-        assert!(w_obj.load(&file_path));
-        assert!(w_obj
-            .get_category(&old_test_category)
-            .unwrap()
-            .get_item(&old_test_item)
-            .unwrap()
-            .get_entry(&old_test_entry_key)
-            .is_none());
+        assert!(w_obj.load(&file_path).is_ok());
+        assert!(w_obj.get(&old_test_category).is_none());
         assert!(w_obj
-            .get_category(&new_test_category)
+            .get(&new_test_category)
             .unwrap()
-            .get_item(&new_test_item)
+            .get(&new_test_item)
             .unwrap()
-            .get_entry(&new_test_entry_key)
+            .get(&new_test_entry_key)
             .is_some());
         assert_eq!(
             w_obj
-                .get_category(&new_test_category)
+                .get(&new_test_category)
                 .unwrap()
-                .get_item(&new_test_item)
+                .get(&new_test_item)
                 .unwrap()
-                .get_entry(&new_test_entry_key)
+                .get(&new_test_entry_key)
                 .unwrap(),
             &new_test_entry_value
         );
+
+        //The key an item's ID is saved under cannot name an entry.
+        let args = app::Args {
+            entry: Some(format!(
+                "{}:@id,{}",
+                new_test_entry_key, new_test_entry_value
+            )),
+            category: Some(new_test_category),
+            item: Some(new_test_item),
+            ..args
+        };
+        let error: Error = app::run(&args).unwrap_err();
+        assert!(error.to_string().contains("@id is reserved"));
+
+        remove_copy(&file_path);
     }
 }
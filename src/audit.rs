@@ -0,0 +1,656 @@
+use crate::{
+    category,
+    encoding::to_hex,
+    integrity::Integrity,
+    merge::{self, Key, Kinds},
+    template::Template,
+    wallet::Wallet,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    io::{Error, ErrorKind, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Marks the hash of a record, or the tag of the head, as an HMAC under the audit key.
+const SIGNED_PREFIX: &str = "hmac:";
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added => write!(f, "added"),
+            Change::Removed => write!(f, "removed"),
+            Change::Changed => write!(f, "changed"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+struct RecordBody {
+    seq: u64,
+    timestamp: u64,
+    user: String,
+    action: String,
+    change: Change,
+    path: String,
+    old: Option<String>,
+    new: Option<String>,
+    prev: String,
+}
+
+/// One line of the audit log. Values are only ever stored as keyed fingerprints.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub(crate) struct Record {
+    #[serde(flatten)]
+    body: RecordBody,
+    hash: String,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fingerprint = |value: &Option<String>| value.clone().unwrap_or(String::from("-"));
+        write!(
+            f,
+            "{} {} {} {} {} {} old={} new={}",
+            self.body.seq,
+            format_timestamp(self.body.timestamp),
+            self.body.user,
+            self.body.action,
+            self.body.change,
+            self.body.path,
+            fingerprint(&self.body.old),
+            fingerprint(&self.body.new)
+        )
+    }
+}
+
+/// A hash-chained, append-only log of the changes made to a database.
+///
+/// With the wallet's integrity key, each record is chained with an HMAC and the head
+/// is signed, so the log cannot be rewritten without the key. Without one the chain
+/// only catches accidental damage, and no fingerprints of values are kept at all.
+pub(crate) struct AuditLog {
+    filename: String,
+    key: Option<Vec<u8>>,
+}
+
+impl AuditLog {
+    pub(crate) fn for_database(database: &str, integrity: Option<&Integrity>) -> AuditLog {
+        AuditLog {
+            filename: format!("{}.audit", database),
+            key: integrity.map(|integrity| integrity.derive_key("371pass audit log")),
+        }
+    }
+
+    fn head_filename(&self) -> String {
+        format!("{}.head", self.filename)
+    }
+
+    pub(crate) fn is_keyed(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Fails if the log is signed but there is no key to add to it with.
+    fn check_key(&self) -> Result<(), Error> {
+        if self.key.is_none() && self.head()?.is_some_and(|head| head.tag.is_some()) {
+            return Err(key_required());
+        }
+        Ok(())
+    }
+
+    /// Appends a record for every category, item and entry that differs between the
+    /// two wallets, including the kinds of entries and the templates of categories.
+    pub(crate) fn record_changes(
+        &self,
+        action: &str,
+        before: &Wallet,
+        after: &Wallet,
+    ) -> Result<usize, Error> {
+        let before: BTreeMap<Key, Vec<String>> = flatten(before)?;
+        let after: BTreeMap<Key, Vec<String>> = flatten(after)?;
+
+        let mut changes: Vec<(Change, String, &[String], &[String])> = Vec::new();
+        for (key, old_fields) in before.iter() {
+            match after.get(key) {
+                None => changes.push((Change::Removed, key_path(key), old_fields, &[])),
+                Some(new_fields) if new_fields != old_fields => {
+                    changes.push((Change::Changed, key_path(key), old_fields, new_fields))
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, new_fields) in after.iter() {
+            if !before.contains_key(key) {
+                changes.push((Change::Added, key_path(key), &[], new_fields));
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(0);
+        }
+        self.check_key()?;
+
+        let (mut seq, mut prev) = match self.records()?.last() {
+            Some(last) => (last.body.seq, last.hash.clone()),
+            None => (0, String::from(GENESIS_HASH)),
+        };
+        //Records are only added to a log whose head still matches its last record.
+        if let Some(head) = self.head()? {
+            if head.seq != seq.to_string() || head.hash != prev {
+                return Err(head_mismatch());
+            }
+        }
+        let timestamp: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let user: String = current_user();
+
+        let mut file: fs::File = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        for (change, path, old_fields, new_fields) in changes.iter() {
+            seq += 1;
+            let body: RecordBody = RecordBody {
+                seq,
+                timestamp,
+                user: user.clone(),
+                action: action.to_lowercase(),
+                change: change.clone(),
+                old: self.fingerprint(path, old_fields),
+                new: self.fingerprint(path, new_fields),
+                path: path.clone(),
+                prev,
+            };
+            let record: Record = Record {
+                hash: self.hash_body(&body, self.is_keyed()),
+                body,
+            };
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+            prev = record.hash;
+        }
+
+        let head: String = format!("{} {}", seq, prev);
+        let head: String = match &self.key {
+            Some(key) => format!("{} {}{}", head, SIGNED_PREFIX, mac(key, "head", &[&head])),
+            None => head,
+        };
+        fs::write(self.head_filename(), format!("{}\n", head))?;
+        Ok(changes.len())
+    }
+
+    pub(crate) fn records(&self) -> Result<Vec<Record>, Error> {
+        let contents: String = match fs::read_to_string(&self.filename) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str::<Record>(line).map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error: audit record on line {} is malformed.", number + 1),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Checks the chain and the recorded head, returning the number of records.
+    ///
+    /// With a key, the head must be signed with it, and so must every record from
+    /// the first signed one on. Records from before the log was keyed are covered
+    /// by the chain of the signed records after them.
+    pub(crate) fn verify(&self) -> Result<usize, Error> {
+        let records: Vec<Record> = self.records()?;
+        let tampered = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+        let mut prev: String = String::from(GENESIS_HASH);
+        let mut signed: bool = false;
+        for (position, record) in records.iter().enumerate() {
+            if record.body.seq != position as u64 + 1 {
+                return Err(tampered(format!(
+                    "Error: audit record {} is out of sequence.",
+                    position + 1
+                )));
+            }
+            let record_signed: bool = record.hash.starts_with(SIGNED_PREFIX);
+            if record_signed {
+                if self.key.is_none() {
+                    return Err(key_required());
+                }
+                signed = true;
+            } else if signed {
+                return Err(tampered(format!(
+                    "Error: audit record {} is not signed.",
+                    record.body.seq
+                )));
+            }
+            if record.body.prev != prev
+                || self.hash_body(&record.body, record_signed) != record.hash
+            {
+                return Err(tampered(format!(
+                    "Error: audit record {} has been modified.",
+                    record.body.seq
+                )));
+            }
+            prev = record.hash.clone();
+        }
+
+        let head: Head = match self.head()? {
+            Some(head) => head,
+            None if records.is_empty() => return Ok(0),
+            None => Head::default(),
+        };
+        if head.seq != records.len().to_string() || head.hash != prev {
+            return Err(head_mismatch());
+        }
+        match (&self.key, &head.tag) {
+            (None, Some(_)) => return Err(key_required()),
+            (None, None) => {}
+            (Some(key), tag) => {
+                let expected: String = format!(
+                    "{}{}",
+                    SIGNED_PREFIX,
+                    mac(key, "head", &[&format!("{} {}", head.seq, head.hash)])
+                );
+                if tag.as_deref() != Some(expected.as_str()) {
+                    return Err(tampered(String::from(
+                        "Error: the audit log head is not signed with this key.",
+                    )));
+                }
+            }
+        }
+        Ok(records.len())
+    }
+
+    fn head(&self) -> Result<Option<Head>, Error> {
+        let head: String = match fs::read_to_string(self.head_filename()) {
+            Ok(head) => head,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut fields = head.split_whitespace().map(String::from);
+        Ok(Some(Head {
+            seq: fields.next().unwrap_or_default(),
+            hash: fields.next().unwrap_or_default(),
+            tag: fields.next(),
+        }))
+    }
+
+    /// A short MAC of the fields `flatten` gives a path, over the path too so equal
+    /// passwords don't look equal. Without a key there is nothing that would stop it
+    /// being brute-forced, so none, and items have no fields to fingerprint.
+    fn fingerprint(&self, path: &str, fields: &[String]) -> Option<String> {
+        let key: &[u8] = self.key.as_deref()?;
+        if fields.is_empty() {
+            return None;
+        }
+        let fields: Vec<&str> = [path]
+            .into_iter()
+            .chain(fields.iter().map(String::as_str))
+            .collect();
+        Some(mac(key, "value", &fields)[..16].to_string())
+    }
+
+    /// The HMAC of a record if `signed`, which needs the key, and its plain hash if not.
+    fn hash_body(&self, body: &RecordBody, signed: bool) -> String {
+        let body: String = serde_json::to_string(body).unwrap_or_default();
+        match &self.key {
+            Some(key) if signed => format!("{}{}", SIGNED_PREFIX, mac(key, "record", &[&body])),
+            _ => to_hex(&Sha256::digest(body.as_bytes())),
+        }
+    }
+}
+
+/// The last line of the chain, as `<seq> <hash>` and, once keyed, `hmac:<tag>`.
+#[derive(Default)]
+struct Head {
+    seq: String,
+    hash: String,
+    tag: Option<String>,
+}
+
+fn head_mismatch() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "Error: the audit log has been truncated or its head does not match.",
+    )
+}
+
+fn key_required() -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        "Error: the audit log is signed, pass the wallet's --mac-key to use it.",
+    )
+}
+
+/// HMAC-SHA256 of `fields` under `key`, separated so that a purpose or field boundary
+/// cannot be shifted to make two different inputs match.
+fn mac(key: &[u8], purpose: &str, fields: &[&str]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(purpose.as_bytes());
+    for field in fields {
+        mac.update(&[0]);
+        mac.update(field.as_bytes());
+    }
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// Maps every category, item and entry, keyed as by `merge::flatten`, to what a
+/// change to it is noticed by: an entry's value and any kind, a category's template
+/// if it has one, and nothing for items.
+fn flatten(wallet: &Wallet) -> Result<BTreeMap<Key, Vec<String>>, Error> {
+    let kinds: Kinds = merge::kinds(wallet);
+    let templates: BTreeMap<String, &Template> = wallet.templates();
+    let mut fields: BTreeMap<Key, Vec<String>> = BTreeMap::new();
+    for (key, value) in merge::flatten(wallet) {
+        let mut key_fields: Vec<String> = value.into_iter().collect();
+        if let Some(kind) = kinds.get(&key) {
+            key_fields.push(String::from(kind.name()));
+        }
+        if let (cat_path, None, _) = &key {
+            if let Some(template) = templates.get(cat_path) {
+                key_fields.push(serde_json::to_string(template)?);
+            }
+        }
+        fields.insert(key, key_fields);
+    }
+    Ok(fields)
+}
+
+/// The path of a category, item or entry, with names escaped as in `category::join_path`.
+fn key_path((cat_path, item_ident, entry_ident): &Key) -> String {
+    match (item_ident, entry_ident) {
+        (None, _) => cat_path.clone(),
+        (Some(item_ident), None) => category::join_path(cat_path, item_ident),
+        (Some(item_ident), Some(entry_ident)) => {
+            category::join_path(&category::join_path(cat_path, item_ident), entry_ident)
+        }
+    }
+}
+
+fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or(String::from("unknown"))
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    // Civil date from days since the epoch, after Howard Hinnant's algorithm.
+    let days: i64 = (timestamp / 86400) as i64 + 719468;
+    let seconds: u64 = timestamp % 86400;
+    let era: i64 = days.div_euclid(146097);
+    let day_of_era: i64 = days.rem_euclid(146097);
+    let year_of_era: i64 =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month: i64 = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year: i64 = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{category::Category, entry::EntryKind, template::Field};
+
+    fn test_log(name: &str, integrity: Option<&Integrity>) -> AuditLog {
+        let database = env::temp_dir().join(format!("371pass-{}-{}", name, std::process::id()));
+        let log: AuditLog = AuditLog::for_database(&database.to_string_lossy(), integrity);
+        let _ = fs::remove_file(&log.filename);
+        let _ = fs::remove_file(log.head_filename());
+        log
+    }
+
+    fn database(log: &AuditLog) -> &str {
+        log.filename.strip_suffix(".audit").unwrap()
+    }
+
+    /// Rewrites every record of `log` with `forger`, returning the new last hash.
+    fn forge(log: &AuditLog, forger: &AuditLog, original: &str, signed: bool) -> String {
+        let mut prev: String = String::from(GENESIS_HASH);
+        let mut forged: Vec<String> = Vec::new();
+        for line in original.lines() {
+            let mut record: Record = serde_json::from_str(line).unwrap();
+            record.body.action = String::from("update");
+            record.body.prev = prev;
+            record.hash = forger.hash_body(&record.body, signed);
+            prev = record.hash.clone();
+            forged.push(serde_json::to_string(&record).unwrap());
+        }
+        fs::write(&log.filename, forged.join("\n")).unwrap();
+        prev
+    }
+
+    fn cleanup(log: &AuditLog) {
+        fs::remove_file(&log.filename).unwrap();
+        fs::remove_file(log.head_filename()).unwrap();
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_record_changes() {
+        let integrity: Integrity = Integrity::new(b"audit key");
+        let log: AuditLog = test_log("audit-record", Some(&integrity));
+        assert_eq!(log.verify().unwrap(), 0);
+
+        let before: Wallet = Wallet::new();
+        let mut after: Wallet = Wallet::new();
+        after
+            .new_category("Websites")
+            .new_item("Google")
+            .add_entry("password", "pass1234");
+
+        assert_eq!(log.record_changes("create", &before, &after).unwrap(), 3);
+        assert_eq!(log.record_changes("read", &after, &after).unwrap(), 0);
+
+        let mut updated: Wallet = after.clone();
+        updated
+            .get_mut(&String::from("Websites"))
+            .unwrap()
+            .get_mut(&String::from("Google"))
+            .unwrap()
+            .add_entry("password", "hunter2");
+        assert_eq!(log.record_changes("update", &after, &updated).unwrap(), 1);
+
+        let records: Vec<Record> = log.records().unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[3].body.change, Change::Changed);
+        assert_eq!(records[3].body.path, "Websites/Google/password");
+        assert_ne!(records[3].body.old, records[3].body.new);
+
+        //No plaintext values are ever written.
+        let contents: String = fs::read_to_string(&log.filename).unwrap();
+        assert!(!contents.contains("pass1234"));
+        assert!(!contents.contains("hunter2"));
+
+        assert_eq!(log.verify().unwrap(), 4);
+
+        //Without the key the log can be neither verified nor added to.
+        let unkeyed: AuditLog = AuditLog::for_database(database(&log), None);
+        assert_eq!(
+            unkeyed.verify().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(unkeyed.record_changes("delete", &updated, &after).is_err());
+
+        //Nor with another key.
+        let other: Integrity = Integrity::new(b"other key");
+        let rekeyed: AuditLog = AuditLog::for_database(database(&log), Some(&other));
+        assert!(rekeyed.verify().is_err());
+        cleanup(&log);
+    }
+
+    #[test]
+    fn test_kinds_templates_and_escaped_names() {
+        let integrity: Integrity = Integrity::new(b"audit key");
+        let log: AuditLog = test_log("audit-kinds", Some(&integrity));
+        let before: Wallet = Wallet::builder()
+            .entry("Work", "AWS/GCP", "password", "pass1234")
+            .category("Work/AWS")
+            .build();
+
+        //An item whose name has a `/` is not logged as the sub-category.
+        let mut after: Wallet = before.clone();
+        after.delete_category("Work/AWS");
+        assert_eq!(log.record_changes("delete", &before, &after).unwrap(), 1);
+        assert_eq!(log.records().unwrap()[0].body.path, "Work/AWS");
+
+        let mut typed: Wallet = after.clone();
+        let work: &mut Category = typed.get_mut("Work").unwrap();
+        work.get_mut("AWS/GCP")
+            .unwrap()
+            .set_kind("password", Some(EntryKind::Secret));
+        assert_eq!(log.record_changes("update", &after, &typed).unwrap(), 1);
+        let mut templated: Wallet = typed.clone();
+        templated
+            .get_mut("Work")
+            .unwrap()
+            .set_template(Some(Template::new().field(Field::new("password"))));
+        assert_eq!(
+            log.record_changes("template", &typed, &templated).unwrap(),
+            1
+        );
+
+        let records: Vec<Record> = log.records().unwrap();
+        assert_eq!(records[1].body.path, "Work/AWS%2FGCP/password");
+        assert_ne!(records[1].body.old, records[1].body.new);
+        assert_eq!(records[2].body.path, "Work");
+        assert_eq!(records[2].body.old, None);
+        assert!(records[2].body.new.is_some());
+        cleanup(&log);
+    }
+
+    #[test]
+    fn test_mismatched_head_is_not_added_to() {
+        let log: AuditLog = test_log("audit-head", None);
+        let mut wallet: Wallet = Wallet::new();
+        wallet.new_category("Websites");
+        log.record_changes("create", &Wallet::new(), &wallet)
+            .unwrap();
+        fs::write(log.head_filename(), format!("0 {}\n", GENESIS_HASH)).unwrap();
+
+        let mut changed: Wallet = wallet.clone();
+        changed.new_category("Bank Accounts");
+        let error: Error = log.record_changes("create", &wallet, &changed).unwrap_err();
+        assert!(error.to_string().contains("head does not match"));
+        assert_eq!(log.records().unwrap().len(), 1);
+        cleanup(&log);
+    }
+
+    #[test]
+    fn test_unkeyed_log_keeps_no_fingerprints() {
+        let log: AuditLog = test_log("audit-unkeyed", None);
+        let mut wallet: Wallet = Wallet::new();
+        wallet
+            .new_category("Websites")
+            .new_item("Google")
+            .add_entry("password", "pass1234");
+        assert_eq!(
+            log.record_changes("create", &Wallet::new(), &wallet)
+                .unwrap(),
+            3
+        );
+
+        let records: Vec<Record> = log.records().unwrap();
+        assert!(records
+            .iter()
+            .all(|record| record.body.old.is_none() && record.body.new.is_none()));
+        assert_eq!(log.verify().unwrap(), 3);
+        cleanup(&log);
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let integrity: Integrity = Integrity::new(b"audit key");
+        let log: AuditLog = test_log("audit-verify", Some(&integrity));
+        let mut wallet: Wallet = Wallet::new();
+        wallet.new_category("Websites");
+        log.record_changes("create", &Wallet::new(), &wallet)
+            .unwrap();
+        wallet.new_category("Bank Accounts");
+        log.record_changes("create", &Wallet::new(), &wallet)
+            .unwrap();
+        assert_eq!(log.verify().unwrap(), 3);
+
+        let original: String = fs::read_to_string(&log.filename).unwrap();
+
+        //Editing a record breaks the chain.
+        fs::write(&log.filename, original.replace("create", "update")).unwrap();
+        assert!(log.verify().is_err());
+
+        //Dropping the last record is caught by the head.
+        let truncated: Vec<&str> = original.lines().take(2).collect();
+        fs::write(&log.filename, truncated.join("\n")).unwrap();
+        assert!(log.verify().is_err());
+
+        //Editing a record and recomputing every hash and the head, without the key.
+        let original_head: String = fs::read_to_string(log.head_filename()).unwrap();
+        let forger: AuditLog = AuditLog::for_database(database(&log), None);
+        let prev: String = forge(&log, &forger, &original, false);
+        fs::write(log.head_filename(), format!("3 {}\n", prev)).unwrap();
+        assert!(forger.verify().is_ok());
+        assert_eq!(log.verify().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        //Or signing them with another key.
+        let other: Integrity = Integrity::new(b"other key");
+        let forger: AuditLog = AuditLog::for_database(database(&log), Some(&other));
+        let prev: String = forge(&log, &forger, &original, true);
+        let head: String = format!("3 {}", prev);
+        let tag: String = mac(forger.key.as_ref().unwrap(), "head", &[&head]);
+        fs::write(
+            log.head_filename(),
+            format!("{} {}{}\n", head, SIGNED_PREFIX, tag),
+        )
+        .unwrap();
+        assert!(forger.verify().is_ok());
+        assert!(log.verify().is_err());
+
+        fs::write(&log.filename, original).unwrap();
+        fs::write(log.head_filename(), original_head).unwrap();
+        assert!(log.verify().is_ok());
+        cleanup(&log);
+    }
+}
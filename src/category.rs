@@ -1,53 +1,315 @@
-use crate::item::Item;
-use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use crate::{
+    item::{self, Item, WithoutIds, ID_KEY},
+    merge::{self, Conflict},
+    template::Template,
+    visitor::Visitor,
+};
+use serde::{
+    de::{MapAccess, Visitor as MapVisitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{collections::BTreeMap, fmt};
+use uuid::Uuid;
 
-#[derive(Clone, Eq, Debug, Deserialize)]
-pub(crate) struct Category {
+/// Serialized as a map of its ID, under `@id`, its items and then its
+/// sub-categories, whose keys end in `/`. Keys are escaped with `escape_name`, so
+/// names may contain `/` and no item can be mistaken for the ID. The identifier is
+/// the key the category is stored under, which restores it when deserializing.
+/// The template is saved alongside the categories rather than among the items.
+///
+/// Like items, a category has a random ID that survives renames and moves, and
+/// that is ignored when comparing categories.
+#[derive(Clone, Eq, Debug)]
+pub struct Category {
     identifier: String,
+    id: Uuid,
+    /// Whether the ID was made up when reading a category saved without one.
+    new_id: bool,
     items: BTreeMap<String, Item>,
+    categories: BTreeMap<String, Category>,
+    template: Option<Template>,
+}
+
+/// Escapes `%` and `/` in a category or item name, so that the name can be one
+/// segment of a path like `Work/AWS/Prod`, and the `@` of a name that is the key
+/// IDs are saved under.
+pub(crate) fn escape_name(name: &str) -> String {
+    match name {
+        ID_KEY => String::from("%40id"),
+        name => name.replace('%', "%25").replace('/', "%2F"),
+    }
+}
+
+pub(crate) fn unescape_name(name: &str) -> String {
+    name.replace("%2F", "/")
+        .replace("%40", "@")
+        .replace("%25", "%")
+}
+
+/// The names of the categories along a path, outermost first.
+pub(crate) fn split_path(path: &str) -> Vec<String> {
+    path.split('/').map(unescape_name).collect()
+}
+
+/// Of `candidates`, the one whose ID `address` names: `@` followed by the start of
+/// the ID, with or without its dashes, such as `@3f2a`. Nothing is found if the
+/// start is ambiguous.
+pub(crate) fn find_by_id<T>(
+    address: &str,
+    candidates: impl Iterator<Item = (Uuid, T)>,
+) -> Option<T> {
+    let prefix: String = address.strip_prefix('@')?.replace('-', "").to_lowercase();
+    if prefix.is_empty() {
+        return None;
+    }
+    let mut found = candidates.filter(|(id, _)| id.simple().to_string().starts_with(&prefix));
+    match (found.next(), found.next()) {
+        (Some((_, candidate)), None) => Some(candidate),
+        _ => None,
+    }
+}
+
+/// The path of the sub-category `name` of the category at `parent`, or of the
+/// top-level category `name` if `parent` is empty.
+pub(crate) fn join_path(parent: &str, name: &str) -> String {
+    match parent {
+        "" => escape_name(name),
+        parent => format!("{}/{}", parent, escape_name(name)),
+    }
 }
 
 impl Category {
-    pub(crate) fn new(identifier: String) -> Category {
+    pub fn new(identifier: String) -> Category {
         Category {
             identifier,
+            id: Uuid::new_v4(),
+            new_id: false,
             items: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            template: None,
         }
     }
 
-    pub(crate) fn size(&self) -> usize {
+    pub fn size(&self) -> usize {
         self.items.len()
     }
 
-    pub(crate) fn empty(&self) -> bool {
-        self.items.is_empty()
+    /// Whether the category has neither items nor sub-categories.
+    pub fn empty(&self) -> bool {
+        self.items.is_empty() && self.categories.is_empty()
     }
 
-    pub(crate) fn get_ident(&self) -> &String {
+    pub fn get_ident(&self) -> &String {
         &self.identifier
     }
 
-    pub(crate) fn set_ident(&mut self, identifier: &String) {
-        self.identifier = identifier.clone();
+    pub(crate) fn set_ident(&mut self, identifier: &str) {
+        self.identifier = identifier.to_string();
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub(crate) fn set_id(&mut self, id: Uuid) {
+        self.id = id;
+        self.new_id = false;
+    }
+
+    /// Whether this category or anything in it was read without an ID, so that
+    /// the one it has is not saved yet.
+    pub(crate) fn has_new_ids(&self) -> bool {
+        self.new_id
+            || self.items.values().any(Item::has_new_id)
+            || self.categories.values().any(Category::has_new_ids)
+    }
+
+    /// Gives this category and everything in it new IDs, as for a copy.
+    pub(crate) fn renew_ids(&mut self) {
+        self.set_id(Uuid::new_v4());
+        for item in self.items.values_mut() {
+            item.set_id(Uuid::new_v4());
+        }
+        for category in self.categories.values_mut() {
+            category.renew_ids();
+        }
+    }
+
+    /// Sets the IDs of this category, at `path`, and everything in it from `ids`,
+    /// keyed as by `Wallet::ids`. Those with no ID there are marked as new, or with
+    /// `only_new`, left alone if they have one that was read with them. Returns
+    /// false if any of them had no ID there.
+    pub(crate) fn set_ids(
+        &mut self,
+        path: &str,
+        ids: &BTreeMap<String, Uuid>,
+        only_new: bool,
+    ) -> bool {
+        let mut complete: bool = true;
+        if self.new_id || !only_new {
+            match ids.get(&format!("{}/", path)) {
+                Some(id) => self.set_id(*id),
+                None => {
+                    self.new_id = true;
+                    complete = false;
+                }
+            }
+        }
+        for item in self.items.values_mut() {
+            if only_new && !item.has_new_id() {
+                continue;
+            }
+            match ids.get(&join_path(path, item.get_ident())) {
+                Some(id) => item.set_id(*id),
+                None => {
+                    item.mark_new_id();
+                    complete = false;
+                }
+            }
+        }
+        for category in self.categories.values_mut() {
+            let category_path: String = join_path(path, &category.identifier);
+            complete = category.set_ids(&category_path, ids, only_new) && complete;
+        }
+        complete
+    }
+
+    pub fn template(&self) -> Option<&Template> {
+        self.template.as_ref()
+    }
+
+    /// Sets or clears the template. Items already in the category are left as they are.
+    pub fn set_template(&mut self, template: Option<Template>) {
+        self.template = template;
     }
 
-    pub(crate) fn new_item(&mut self, item_identifier: &String) -> &mut Item {
+    /// Returns the item, adding one first if there is none. A new item starts
+    /// with the fields of the category's template, or empty if it has none.
+    pub fn new_item(&mut self, item_identifier: &str) -> &mut Item {
         if self.items.contains_key(item_identifier) {
             return self.items.get_mut(item_identifier).unwrap();
         }
-        self.items
-            .insert(item_identifier.clone(), Item::new(item_identifier.clone()));
+        let mut item: Item = Item::new(String::from(item_identifier));
+        if let Some(template) = &self.template {
+            template.apply(&mut item);
+        }
+        self.items.insert(String::from(item_identifier), item);
         self.items.get_mut(item_identifier).unwrap()
     }
 
-    pub(crate) fn add_item(&mut self, item: &Item) -> bool {
+    pub fn add_item(&mut self, item: &Item) -> bool {
         self.items
             .insert(item.get_ident().clone(), item.clone())
             .is_none()
     }
 
-    fn merge_items(&mut self, other: &mut Category) {
+    /// The items, ordered by identifier.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .item("Websites", "Twitter")
+    ///     .item("Websites", "Google")
+    ///     .build();
+    /// let category = wallet.get("Websites").unwrap();
+    /// let items: Vec<&String> = category.items().map(|item| item.get_ident()).collect();
+    /// assert_eq!(items, ["Google", "Twitter"]);
+    /// ```
+    pub fn items(&self) -> impl Iterator<Item = &Item> + '_ {
+        self.items.values()
+    }
+
+    /// The sub-categories, ordered by identifier.
+    pub fn categories(&self) -> impl Iterator<Item = &Category> + '_ {
+        self.categories.values()
+    }
+
+    /// Returns the sub-category, adding an empty one first if there is none.
+    pub fn new_category(&mut self, category_identifier: &str) -> &mut Category {
+        self.categories
+            .entry(String::from(category_identifier))
+            .or_insert_with(|| Category::new(String::from(category_identifier)))
+    }
+
+    pub fn add_category(&mut self, category: Category) -> bool {
+        self.categories
+            .insert(category.get_ident().clone(), category)
+            .is_none()
+    }
+
+    /// The name of the sub-category called or, with `@`, identified by `category_identifier`.
+    pub(crate) fn category_name<'a>(&'a self, category_identifier: &'a str) -> Option<&'a str> {
+        if self.categories.contains_key(category_identifier) {
+            return Some(category_identifier);
+        }
+        find_by_id(
+            category_identifier,
+            self.categories
+                .values()
+                .map(|category| (category.id, category.identifier.as_str())),
+        )
+    }
+
+    /// Looks a sub-category up by name or, failing that, by `@` and its ID.
+    pub fn get_category(&self, category_identifier: &str) -> Option<&Category> {
+        self.categories
+            .get(self.category_name(category_identifier)?)
+    }
+
+    pub fn get_category_mut(&mut self, category_identifier: &str) -> Option<&mut Category> {
+        let name: String = String::from(self.category_name(category_identifier)?);
+        self.categories.get_mut(&name)
+    }
+
+    pub(crate) fn remove_category(&mut self, category_identifier: &str) -> Option<Category> {
+        let name: String = String::from(self.category_name(category_identifier)?);
+        self.categories.remove(&name)
+    }
+
+    /// Deletes the sub-category along with everything in it.
+    pub fn delete_category(&mut self, category_identifier: &str) -> bool {
+        self.remove_category(category_identifier).is_some()
+    }
+
+    /// Adds this category and its sub-categories, parents first, to `paths`.
+    pub(crate) fn collect_paths<'a>(
+        &'a self,
+        path: String,
+        paths: &mut Vec<(String, &'a Category)>,
+    ) {
+        paths.push((path.clone(), self));
+        for category in self.categories.values() {
+            category.collect_paths(join_path(&path, category.get_ident()), paths);
+        }
+    }
+
+    pub(crate) fn accept(&mut self, path: &str, visitor: &mut impl Visitor) {
+        visitor.visit_category(path);
+        for item in self.items.values_mut() {
+            item.accept(path, visitor);
+        }
+        for category in self.categories.values_mut() {
+            let category_path: String = join_path(path, &category.identifier);
+            category.accept(&category_path, visitor);
+        }
+    }
+
+    pub(crate) fn mask_sensitive(&mut self) {
+        for item in self.items.values_mut() {
+            item.mask_sensitive();
+        }
+        for category in self.categories.values_mut() {
+            category.mask_sensitive();
+        }
+    }
+
+    /// Merges `other` into this category, and its sub-categories into these.
+    pub(crate) fn merge_items(&mut self, other: &mut Category) {
+        if other.template.is_some() {
+            self.template = other.template.clone();
+        }
         for (key, value) in other.items.iter_mut() {
             if self.items.contains_key(key) {
                 self.items.get_mut(key).unwrap().merge_entries(value);
@@ -55,14 +317,87 @@ impl Category {
                 self.items.insert(key.clone(), value.clone());
             }
         }
+        for (key, value) in other.categories.iter_mut() {
+            if self.categories.contains_key(key) {
+                self.categories.get_mut(key).unwrap().merge_items(value);
+            } else {
+                self.categories.insert(key.clone(), value.clone());
+            }
+        }
     }
 
-    pub(crate) fn get_item(&mut self, item_identifier: &String) -> Option<&mut Item> {
-        return self.items.get_mut(item_identifier);
+    /// Like `merge_items`, but only takes the changes `theirs` made since `base`,
+    /// merging items both sides kept with `Item::merge_entries_three_way`.
+    /// Returns the conflicts, see `merge::three_way`.
+    pub(crate) fn merge_items_three_way(
+        &mut self,
+        base: Option<&Category>,
+        theirs: &Category,
+        path: &str,
+    ) -> Vec<Conflict> {
+        let mut conflicts: Vec<Conflict> = merge::merge_children(
+            &mut self.items,
+            base.map(|base| &base.items),
+            &theirs.items,
+            |name| join_path(path, name),
+            Item::merge_entries_three_way,
+        );
+        conflicts.extend(merge::merge_children(
+            &mut self.categories,
+            base.map(|base| &base.categories),
+            &theirs.categories,
+            |name| join_path(path, name),
+            Category::merge_items_three_way,
+        ));
+
+        //Templates merge like entries, except that a conflict keeps ours.
+        let base_template: Option<&Template> = base.and_then(Category::template);
+        if theirs.template() != base_template && self.template() == base_template {
+            self.template = theirs.template.clone();
+        }
+        conflicts
     }
 
-    pub(crate) fn delete_item(&mut self, item_identifier: &String) -> bool {
-        self.items.remove(item_identifier).is_some()
+    /// The name of the item called or, with `@`, identified by `item_identifier`.
+    pub(crate) fn item_name<'a>(&'a self, item_identifier: &'a str) -> Option<&'a str> {
+        if self.items.contains_key(item_identifier) {
+            return Some(item_identifier);
+        }
+        find_by_id(
+            item_identifier,
+            self.items
+                .values()
+                .map(|item| (item.id(), item.get_ident().as_str())),
+        )
+    }
+
+    /// Looks an item up by name or, failing that, by `@` and its ID.
+    pub fn get(&self, item_identifier: &str) -> Option<&Item> {
+        self.items.get(self.item_name(item_identifier)?)
+    }
+
+    pub fn get_mut(&mut self, item_identifier: &str) -> Option<&mut Item> {
+        let name: String = String::from(self.item_name(item_identifier)?);
+        self.items.get_mut(&name)
+    }
+
+    /// Renames an item, keeping its ID, replacing any item that already had the
+    /// new name. Returns false if there is no such item.
+    pub fn rename_item(&mut self, item_identifier: &str, new_identifier: &str) -> bool {
+        let mut item: Item = match self.item_name(item_identifier).map(String::from) {
+            Some(name) => self.items.remove(&name).unwrap(),
+            None => return false,
+        };
+        item.set_ident(new_identifier);
+        self.items.insert(String::from(new_identifier), item);
+        true
+    }
+
+    pub fn delete_item(&mut self, item_identifier: &str) -> bool {
+        match self.item_name(item_identifier).map(String::from) {
+            Some(name) => self.items.remove(&name).is_some(),
+            None => false,
+        }
     }
 }
 
@@ -71,13 +406,15 @@ impl fmt::Display for Category {
         for item in self.items.iter() {
             write!(f, "{}", serde_json::to_string(&item).unwrap())?;
         }
-        write!(f, "{}", "")
+        Ok(())
     }
 }
 
 impl PartialEq<Self> for Category {
     fn eq(&self, other: &Self) -> bool {
         self.items == other.items
+            && self.categories == other.categories
+            && self.template == other.template
     }
 }
 
@@ -86,16 +423,97 @@ impl Serialize for Category {
     where
         S: Serializer,
     {
-        let mut map: <S as Serializer>::SerializeMap =
-            serializer.serialize_map(Some(self.items.len()))?;
+        self.serialize_map(serializer, true)
+    }
+}
 
+impl Serialize for WithoutIds<'_, Category> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_map(serializer, false)
+    }
+}
+
+impl Category {
+    fn serialize_map<S>(&self, serializer: S, with_ids: bool) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map: <S as Serializer>::SerializeMap = serializer.serialize_map(Some(
+            usize::from(with_ids) + self.items.len() + self.categories.len(),
+        ))?;
+
+        if with_ids {
+            map.serialize_entry(ID_KEY, &self.id.hyphenated())?;
+        }
         for (item_identifier, item_contents) in &self.items {
-            map.serialize_entry(&item_identifier, &item_contents)?;
+            let key: String = escape_name(item_identifier);
+            if with_ids {
+                map.serialize_entry(&key, item_contents)?;
+            } else {
+                map.serialize_entry(&key, &WithoutIds(item_contents))?;
+            }
+        }
+        for (category_identifier, category_contents) in &self.categories {
+            let key: String = format!("{}/", escape_name(category_identifier));
+            if with_ids {
+                map.serialize_entry(&key, category_contents)?;
+            } else {
+                map.serialize_entry(&key, &WithoutIds(category_contents))?;
+            }
         }
         map.end()
     }
 }
 
+impl<'de> Deserialize<'de> for Category {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(CategoryVisitor)
+    }
+}
+
+struct CategoryVisitor;
+
+impl<'de> MapVisitor<'de> for CategoryVisitor {
+    type Value = Category;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of items and sub-categories")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Category, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut category: Category = Category::new(String::new());
+        category.new_id = true;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == ID_KEY {
+                category.set_id(item::next_id(&mut map)?);
+                continue;
+            }
+            match key.strip_suffix('/') {
+                Some(category_key) => {
+                    let mut sub_category: Category = map.next_value()?;
+                    sub_category.set_ident(&unescape_name(category_key));
+                    category.add_category(sub_category);
+                }
+                None => {
+                    let mut item: Item = map.next_value()?;
+                    item.set_ident(&unescape_name(&key));
+                    category.items.insert(item.get_ident().clone(), item);
+                }
+            }
+        }
+        Ok(category)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,7 +536,7 @@ mod tests {
         assert!(category.add_item(&item));
         assert_eq!(category.size(), 1);
         assert!(!category.empty());
-        assert_eq!(category.get_item(&item_identifier).unwrap(), &item);
+        assert_eq!(category.get(&item_identifier).unwrap(), &item);
         assert!(!category.empty());
 
         //Now try to add a new item with the same identifier.
@@ -135,7 +553,7 @@ mod tests {
         assert!(category.add_item(&item3));
         assert_eq!(category.size(), 2);
         assert!(!category.empty());
-        assert_eq!(category.get_item(&item_identifier2).unwrap(), &item3);
+        assert_eq!(category.get(&item_identifier2).unwrap(), &item3);
     }
 
     #[test]
@@ -149,20 +567,20 @@ mod tests {
         assert!(category.add_item(&item));
         assert_eq!(category.size(), 1);
         assert!(!category.empty());
-        assert_eq!(category.get_item(&item_identifier).unwrap(), &item);
+        assert_eq!(category.get(&item_identifier).unwrap(), &item);
         assert!(!category.empty());
 
         //Now try to delete an item that doesn't exist.
         let item_identifier2: String = "Test_Item2".to_string();
         assert!(!category.delete_item(&item_identifier2));
-        assert_eq!(category.get_item(&item_identifier).unwrap(), &item);
+        assert_eq!(category.get(&item_identifier).unwrap(), &item);
         assert_eq!(category.size(), 1);
         assert!(!category.empty());
 
         //Now delete the item that does exist.
         assert!(category.delete_item(&item_identifier));
         //Check if an error is thrown when trying to get the item.
-        //assert_eq!(category.get_item(&item_identifier), some error here);
+        //assert_eq!(category.get(&item_identifier), some error here);
         assert_eq!(category.size(), 0);
         assert!(category.empty());
     }
@@ -0,0 +1,388 @@
+use crate::{
+    category::{escape_name, Category},
+    item::{Item, WithoutIds},
+    wallet::Wallet,
+};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt};
+
+const REDACTED: &str = "<redacted>";
+
+/// A single structural difference between two wallets.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) enum Difference {
+    Added {
+        path: Vec<String>,
+        value: Value,
+    },
+    Removed {
+        path: Vec<String>,
+    },
+    Renamed {
+        from: Vec<String>,
+        to: Vec<String>,
+    },
+    Changed {
+        path: Vec<String>,
+        old: String,
+        new: String,
+    },
+}
+
+/// Compares two wallets category by category, item by item and entry by entry.
+///
+/// Paths are made of the keys the wallet is serialized with, so sub-categories
+/// end in `/` and do not clash with items of the same name.
+///
+/// A category, item or entry that disappears while one with identical
+/// contents appears under a new name at the same level is reported as renamed.
+pub(crate) fn diff(a: &Wallet, b: &Wallet) -> Vec<Difference> {
+    let mut differences: Vec<Difference> = Vec::new();
+    diff_level(&mut differences, &[], Node::Wallet(a), Node::Wallet(b));
+    differences
+}
+
+/// A wallet, or one of the categories, items or entry values below it.
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Wallet(&'a Wallet),
+    Category(&'a Category),
+    Item(&'a Item),
+    Entry(&'a str),
+}
+
+impl<'a> Node<'a> {
+    fn children(self) -> BTreeMap<String, Node<'a>> {
+        match self {
+            Node::Wallet(wallet) => wallet
+                .categories()
+                .map(|category| (escape_name(category.get_ident()), Node::Category(category)))
+                .collect(),
+            Node::Category(category) => category
+                .items()
+                .map(|item| (escape_name(item.get_ident()), Node::Item(item)))
+                .chain(category.categories().map(|sub_category| {
+                    (
+                        format!("{}/", escape_name(sub_category.get_ident())),
+                        Node::Category(sub_category),
+                    )
+                }))
+                .collect(),
+            Node::Item(item) => item
+                .entries()
+                .map(|(key, value)| (String::from(key), Node::Entry(value)))
+                .collect(),
+            Node::Entry(_) => BTreeMap::new(),
+        }
+    }
+
+    /// Whether both hold the same contents, whatever they are called.
+    fn same_contents(self, other: Node) -> bool {
+        match (self, other) {
+            (Node::Entry(a), Node::Entry(b)) => a == b,
+            (Node::Entry(_), _) | (_, Node::Entry(_)) => false,
+            (a, b) => {
+                let (a, b) = (a.children(), b.children());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|((a_key, a_node), (b_key, b_node))| {
+                            a_key == b_key && a_node.same_contents(*b_node)
+                        })
+            }
+        }
+    }
+
+    fn is_empty(self) -> bool {
+        match self {
+            Node::Entry(value) => value.is_empty(),
+            node => node.children().is_empty(),
+        }
+    }
+
+    fn to_value(self) -> Value {
+        match self {
+            Node::Wallet(wallet) => serde_json::to_value(WithoutIds(wallet)),
+            Node::Category(category) => serde_json::to_value(WithoutIds(category)),
+            Node::Item(item) => serde_json::to_value(WithoutIds(item)),
+            Node::Entry(value) => Ok(Value::String(String::from(value))),
+        }
+        .unwrap_or(Value::Null)
+    }
+}
+
+fn diff_level(differences: &mut Vec<Difference>, parent: &[String], a: Node, b: Node) {
+    let a: BTreeMap<String, Node> = a.children();
+    let b: BTreeMap<String, Node> = b.children();
+    let path = |key: &str| -> Vec<String> {
+        let mut path: Vec<String> = parent.to_vec();
+        path.push(String::from(key));
+        path
+    };
+
+    let mut removed: Vec<&str> = a
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !b.contains_key(*key))
+        .collect();
+    let mut added: Vec<&str> = b
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !a.contains_key(*key))
+        .collect();
+
+    removed.retain(|old_key| {
+        let renamed_to = added
+            .iter()
+            .position(|new_key| a[*old_key].same_contents(b[*new_key]) && !a[*old_key].is_empty());
+        match renamed_to {
+            Some(position) => {
+                let new_key: &str = added.remove(position);
+                differences.push(Difference::Renamed {
+                    from: path(old_key),
+                    to: path(new_key),
+                });
+                false
+            }
+            None => true,
+        }
+    });
+
+    for key in removed {
+        differences.push(Difference::Removed { path: path(key) });
+    }
+    for key in added {
+        differences.push(Difference::Added {
+            path: path(key),
+            value: b[key].to_value(),
+        });
+    }
+
+    for (key, a_node) in a.iter() {
+        match (a_node, b.get(key)) {
+            (Node::Entry(old), Some(Node::Entry(new))) if old != new => {
+                differences.push(Difference::Changed {
+                    path: path(key),
+                    old: String::from(*old),
+                    new: String::from(*new),
+                })
+            }
+            (Node::Entry(_), _) | (_, Some(Node::Entry(_))) => {}
+            (_, Some(b_node)) => diff_level(differences, &path(key), *a_node, *b_node),
+            (_, None) => {}
+        }
+    }
+}
+
+/// Renders the differences as an RFC 6902 JSON Patch that turns `a` into `b`.
+pub(crate) fn to_json_patch(differences: &[Difference], reveal: bool) -> Value {
+    let value = |value: &Value| -> Value {
+        if reveal {
+            value.clone()
+        } else {
+            redact(value)
+        }
+    };
+
+    Value::Array(
+        differences
+            .iter()
+            .map(|difference| match difference {
+                Difference::Added { path, value: added } => {
+                    json!({"op": "add", "path": json_pointer(path), "value": value(added)})
+                }
+                Difference::Removed { path } => {
+                    json!({"op": "remove", "path": json_pointer(path)})
+                }
+                Difference::Renamed { from, to } => {
+                    json!({"op": "move", "from": json_pointer(from), "path": json_pointer(to)})
+                }
+                Difference::Changed { path, new, .. } => json!({
+                    "op": "replace",
+                    "path": json_pointer(path),
+                    "value": value(&Value::String(new.clone()))
+                }),
+            })
+            .collect(),
+    )
+}
+
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), redact(value)))
+                .collect(),
+        ),
+        _ => Value::String(String::from(REDACTED)),
+    }
+}
+
+pub(crate) fn json_pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// A path as the user would type it, without the `/` that ends sub-category keys.
+fn display_path(path: &[String]) -> String {
+    path.iter()
+        .map(|segment| segment.strip_suffix('/').unwrap_or(segment))
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+/// Human-readable rendering of a difference, hiding entry values unless revealed.
+pub(crate) struct Display<'a> {
+    pub(crate) difference: &'a Difference,
+    pub(crate) reveal: bool,
+}
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.difference {
+            Difference::Added {
+                path,
+                value: Value::String(value),
+            } if self.reveal => write!(f, "+ {}: {:?}", display_path(path), value),
+            Difference::Added { path, .. } => write!(f, "+ {}", display_path(path)),
+            Difference::Removed { path } => write!(f, "- {}", display_path(path)),
+            Difference::Renamed { from, to } => write!(
+                f,
+                "> {} -> {} (renamed)",
+                display_path(from),
+                display_path(to)
+            ),
+            Difference::Changed { path, old, new } if self.reveal => {
+                write!(f, "~ {}: {:?} -> {:?}", display_path(path), old, new)
+            }
+            Difference::Changed { path, .. } => {
+                write!(f, "~ {}: value changed", display_path(path))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(json: &str) -> Wallet {
+        let mut wallet: Wallet = Wallet::new();
+        wallet.load_json(json).unwrap();
+        wallet
+    }
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('/').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let a: Wallet = wallet(
+            r#"{"Bank Accounts":{"Starling":{"Account Number":"12345678"}},"Websites":{"Facebook":{"password":"pass1234fb"},"Google":{"password":"pass1234","url":"https://www.google.com/"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+        let b: Wallet = wallet(
+            r#"{"Banking":{"Starling":{"Account Number":"12345678"}},"Websites":{"Facebook":{"password":"pass1234fb"},"Google":{"password":"newpass","link":"https://www.google.com/","username":"example@gmail.com"},"X":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Difference::Renamed {
+                    from: path("Bank Accounts"),
+                    to: path("Banking"),
+                },
+                Difference::Renamed {
+                    from: path("Websites/Twitter"),
+                    to: path("Websites/X"),
+                },
+                Difference::Renamed {
+                    from: path("Websites/Google/url"),
+                    to: path("Websites/Google/link"),
+                },
+                Difference::Added {
+                    path: path("Websites/Google/username"),
+                    value: json!("example@gmail.com"),
+                },
+                Difference::Changed {
+                    path: path("Websites/Google/password"),
+                    old: String::from("pass1234"),
+                    new: String::from("newpass"),
+                },
+            ]
+        );
+        assert!(diff(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_secrets_hidden_unless_revealed() {
+        let a: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        let b: Wallet =
+            wallet(r#"{"Websites":{"Google":{"password":"newpass"},"X":{"password":"xpass"}}}"#);
+        let differences: Vec<Difference> = diff(&a, &b);
+
+        let hidden: Vec<String> = differences
+            .iter()
+            .map(|difference| {
+                Display {
+                    difference,
+                    reveal: false,
+                }
+                .to_string()
+            })
+            .collect();
+        assert_eq!(
+            hidden,
+            vec!["+ Websites/X", "~ Websites/Google/password: value changed"]
+        );
+
+        let patch: String = to_json_patch(&differences, false).to_string();
+        assert!(!patch.contains("newpass") && !patch.contains("xpass"));
+
+        assert_eq!(
+            to_json_patch(&differences, true),
+            json!([
+                {"op": "add", "path": "/Websites/X", "value": {"password": "xpass"}},
+                {"op": "replace", "path": "/Websites/Google/password", "value": "newpass"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_nested_categories() {
+        let a: Wallet = wallet(
+            r#"{"Work":{"AWS":{"password":"pass1234"},"AWS/":{"root":{"password":"pass1234"}}}}"#,
+        );
+        let b: Wallet = wallet(
+            r#"{"Work":{"AWS":{"password":"pass1234"},"AWS/":{"root":{"password":"newpass"}}}}"#,
+        );
+        let differences: Vec<Difference> = diff(&a, &b);
+
+        //The sub-category and the item of the same name are told apart.
+        assert_eq!(
+            Display {
+                difference: &differences[0],
+                reveal: false
+            }
+            .to_string(),
+            "~ Work/AWS/root/password: value changed"
+        );
+        assert_eq!(
+            to_json_patch(&differences, true),
+            json!([{"op": "replace", "path": "/Work/AWS~1/root/password", "value": "newpass"}])
+        );
+        assert_eq!(
+            crate::patch::apply_json_patch(&a, &to_json_patch(&differences, true)).unwrap(),
+            b
+        );
+    }
+
+    #[test]
+    fn test_json_pointer_escaping() {
+        assert_eq!(
+            json_pointer(&[String::from("a/b"), String::from("c~d")]),
+            "/a~1b/c~0d"
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    encoding::from_base32,
+    error,
+    finance::{self, CardBrand},
+};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Shown instead of the value of a sensitive entry.
+const MASK: &str = "********";
+
+/// What an entry holds, which decides how it is validated and whether it is shown.
+///
+/// Entries without a kind are plain text and accept anything.
+///
+/// ```
+/// use csc371_remake::EntryKind;
+///
+/// let kind: EntryKind = "date".parse()?;
+/// assert!(kind.validate("2024-02-29").is_ok());
+/// assert!(kind.validate("2023-02-29").is_err());
+/// assert!(EntryKind::Secret.is_sensitive());
+/// # Ok::<(), csc371_remake::Error>(())
+/// ```
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    /// A password, PIN or other value that is masked unless revealed.
+    Secret,
+    /// A username or other single-line login name.
+    Login,
+    Url,
+    Email,
+    /// Free text that may span several lines.
+    Note,
+    /// A calendar date written as `YYYY-MM-DD`.
+    Date,
+    Number,
+    /// A base32 TOTP secret, or an `otpauth://totp/` URI holding one.
+    Totp,
+    Phone,
+    /// A UK sort code such as `12-34-56`.
+    SortCode,
+    /// A UK bank account number of eight digits.
+    AccountNumber,
+    Iban,
+    /// A payment card number, masked except for its brand and last four digits.
+    Card,
+    /// A card expiry date written as `MM/YY` or `MM/YYYY`.
+    Expiry,
+}
+
+const KINDS: [EntryKind; 14] = [
+    EntryKind::Secret,
+    EntryKind::Login,
+    EntryKind::Url,
+    EntryKind::Email,
+    EntryKind::Note,
+    EntryKind::Date,
+    EntryKind::Number,
+    EntryKind::Totp,
+    EntryKind::Phone,
+    EntryKind::SortCode,
+    EntryKind::AccountNumber,
+    EntryKind::Iban,
+    EntryKind::Card,
+    EntryKind::Expiry,
+];
+
+impl EntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::Secret => "secret",
+            EntryKind::Login => "login",
+            EntryKind::Url => "url",
+            EntryKind::Email => "email",
+            EntryKind::Note => "note",
+            EntryKind::Date => "date",
+            EntryKind::Number => "number",
+            EntryKind::Totp => "totp",
+            EntryKind::Phone => "phone",
+            EntryKind::SortCode => "sort-code",
+            EntryKind::AccountNumber => "account-number",
+            EntryKind::Iban => "iban",
+            EntryKind::Card => "card",
+            EntryKind::Expiry => "expiry",
+        }
+    }
+
+    /// Whether values of this kind are masked in output unless revealed.
+    pub fn is_sensitive(&self) -> bool {
+        matches!(self, EntryKind::Secret | EntryKind::Totp | EntryKind::Card)
+    }
+
+    /// What is shown instead of a sensitive value. Cards keep enough to tell them apart.
+    pub(crate) fn mask(&self, value: &str) -> String {
+        let digits: Option<String> = finance::card_digits(value);
+        match (self, digits) {
+            (EntryKind::Card, Some(digits)) => format!(
+                "{} ending {}",
+                CardBrand::detect(&digits).map_or("Card", |brand| brand.name()),
+                &digits[digits.len() - 4..]
+            ),
+            _ => String::from(MASK),
+        }
+    }
+
+    /// Checks that `value` is well formed for this kind. The error never repeats
+    /// the value, which may be a secret.
+    pub fn validate(&self, value: &str) -> error::Result<()> {
+        let valid: bool = match self {
+            EntryKind::Secret | EntryKind::Note => true,
+            EntryKind::Login => !value.is_empty() && !value.contains(['\n', '\r']),
+            EntryKind::Url => url_host(value).is_some(),
+            EntryKind::Email => is_email(value),
+            EntryKind::Date => parse_date(value).is_some(),
+            EntryKind::Number => is_number(value),
+            EntryKind::Totp => Totp::parse(value).is_ok(),
+            EntryKind::Phone => is_phone(value),
+            EntryKind::SortCode => finance::is_sort_code(value),
+            EntryKind::AccountNumber => finance::is_account_number(value),
+            EntryKind::Iban => finance::is_iban(value),
+            EntryKind::Card => finance::is_card_number(value),
+            EntryKind::Expiry => finance::is_expiry(value, today()),
+        };
+        if valid {
+            return Ok(());
+        }
+
+        let expected: &str = match self {
+            EntryKind::Url => "a URL such as https://www.example.com/",
+            EntryKind::Email => "an address such as name@example.com",
+            EntryKind::Date => "a date written as YYYY-MM-DD",
+            EntryKind::Number => "a number",
+            EntryKind::Totp => "a base32 secret or an otpauth://totp/ URI",
+            EntryKind::Phone => "a phone number of 7 to 15 digits",
+            EntryKind::SortCode => "six digits such as 12-34-56",
+            EntryKind::AccountNumber => "eight digits",
+            EntryKind::Iban => "an IBAN with correct check digits",
+            EntryKind::Card => "a card number that passes the Luhn check",
+            EntryKind::Expiry => "a month and year such as 03/27, at most 20 years ahead",
+            _ => "a single non-empty line",
+        };
+        Err(error::Error::InvalidInput(format!(
+            "Error: a {} entry must be {}.",
+            self, expected
+        )))
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = error::Error;
+
+    fn from_str(kind: &str) -> error::Result<EntryKind> {
+        KINDS
+            .into_iter()
+            .find(|candidate| candidate.name() == kind.to_lowercase())
+            .ok_or_else(|| {
+                error::Error::InvalidInput(format!(
+                    "Error: unknown entry type \"{}\", expected one of {}.",
+                    kind,
+                    KINDS.map(|kind| kind.name()).join(", ")
+                ))
+            })
+    }
+}
+
+/// The lowercase host of a URL, without `www.`, user info or port.
+pub(crate) fn url_host(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme.is_empty()
+        || !scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    {
+        return None;
+    }
+    let authority: &str = rest.split(['/', '?', '#']).next()?;
+    let host_port: &str = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host: &str = host_port.split(':').next()?;
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return None;
+    }
+    let host: String = host.to_lowercase();
+    Some(host.strip_prefix("www.").map(String::from).unwrap_or(host))
+}
+
+/// Whether a saved URL is for the site at `url`: the same host or one of its subdomains.
+pub(crate) fn url_matches(saved: &str, url: &str) -> bool {
+    match (url_host(saved), url_host(url)) {
+        (Some(saved), Some(host)) => host == saved || host.ends_with(&format!(".{}", saved)),
+        _ => false,
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !value.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// The year, month and day of a `YYYY-MM-DD` date that exists in the calendar.
+pub(crate) fn parse_date(value: &str) -> Option<(u32, u32, u32)> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return None;
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (u32, u32, u32) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    let leap: bool = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month: u32 = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    (1..=days_in_month)
+        .contains(&day)
+        .then_some((year, month, day))
+}
+
+/// Today's UTC year, month and day.
+pub(crate) fn today() -> (u32, u32, u32) {
+    date_of(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    )
+}
+
+/// The UTC year, month and day of a Unix time.
+pub(crate) fn date_of(unix_time: u64) -> (u32, u32, u32) {
+    //Counts from 1 March 0000 so that leap days fall at the end of each year.
+    let days: u64 = unix_time / 86_400 + 719_468;
+    let era: u64 = days / 146_097;
+    let day_of_era: u64 = days % 146_097;
+    let year_of_era: u64 =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year: u64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month: u64 = (5 * day_of_year + 2) / 153;
+    let day: u64 = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month: u64 = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year: u64 = year_of_era + era * 400 + u64::from(month <= 2);
+    (year as u32, month as u32, day as u32)
+}
+
+fn is_number(value: &str) -> bool {
+    let digits: &str = value.strip_prefix(['-', '+']).unwrap_or(value);
+    let mut parts = digits.splitn(2, '.');
+    let whole: &str = parts.next().unwrap_or_default();
+    let fraction: Option<&str> = parts.next();
+    !whole.is_empty()
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && fraction.is_none_or(|fraction| {
+            !fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit())
+        })
+}
+
+fn is_phone(value: &str) -> bool {
+    let number: &str = value.strip_prefix('+').unwrap_or(value);
+    let digits: usize = number.chars().filter(char::is_ascii_digit).count();
+    number
+        .chars()
+        .all(|c| c.is_ascii_digit() || " -()".contains(c))
+        && (7..=15).contains(&digits)
+}
+
+/// An RFC 6238 time-based one-time password generator.
+#[derive(Debug)]
+pub(crate) struct Totp {
+    secret: Vec<u8>,
+    digits: u32,
+    period: u64,
+}
+
+impl Totp {
+    /// Reads a bare base32 secret or an `otpauth://totp/` URI. Only SHA-1 is supported,
+    /// which is what authenticator apps use unless told otherwise.
+    pub(crate) fn parse(value: &str) -> Result<Totp, Error> {
+        let invalid =
+            |message: &str| Error::new(ErrorKind::InvalidInput, format!("Error: {}", message));
+        let mut totp: Totp = Totp {
+            secret: Vec::new(),
+            digits: 6,
+            period: 30,
+        };
+
+        let secret: String = match value.strip_prefix("otpauth://totp/") {
+            Some(uri) => {
+                let query: &str = uri
+                    .split_once('?')
+                    .map(|(_, query)| query)
+                    .unwrap_or_default();
+                let mut secret: Option<&str> = None;
+                for (name, parameter) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+                    match name.to_lowercase().as_str() {
+                        "secret" => secret = Some(parameter),
+                        "digits" => {
+                            totp.digits = parameter
+                                .parse()
+                                .ok()
+                                .filter(|digits| (6..=8).contains(digits))
+                                .ok_or_else(|| invalid("TOTP digits must be 6, 7 or 8."))?
+                        }
+                        "period" => {
+                            totp.period = parameter
+                                .parse()
+                                .ok()
+                                .filter(|period| *period > 0)
+                                .ok_or_else(|| {
+                                    invalid("TOTP period must be a positive number of seconds.")
+                                })?
+                        }
+                        "algorithm" if !parameter.eq_ignore_ascii_case("SHA1") => {
+                            return Err(invalid("only SHA1 TOTP secrets are supported."))
+                        }
+                        _ => {}
+                    }
+                }
+                String::from(secret.ok_or_else(|| invalid("the otpauth URI has no secret."))?)
+            }
+            None => String::from(value),
+        };
+
+        totp.secret = from_base32(&secret)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| invalid("the TOTP secret is not valid base32."))?;
+        Ok(totp)
+    }
+
+    /// The code for the period containing `unix_time`, zero-padded to its digits.
+    pub(crate) fn code(&self, unix_time: u64) -> String {
+        let counter: u64 = unix_time / self.period;
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(&counter.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        let offset: usize = (hash[hash.len() - 1] & 0x0f) as usize;
+        let truncated: u32 = u32::from_be_bytes([
+            hash[offset],
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]) & 0x7fff_ffff;
+        format!(
+            "{:0width$}",
+            truncated % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Seconds until the code for `unix_time` expires.
+    pub(crate) fn remaining(&self, unix_time: u64) -> u64 {
+        self.period - unix_time % self.period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        assert!(EntryKind::Url.validate("https://www.google.com/").is_ok());
+        assert!(EntryKind::Url.validate("www.google.com").is_err());
+        assert!(EntryKind::Email.validate("example@gmail.com").is_ok());
+        assert!(EntryKind::Email.validate("example@gmail").is_err());
+        assert!(EntryKind::Date.validate("2000-02-29").is_ok());
+        assert!(EntryKind::Date.validate("1900-02-29").is_err());
+        assert!(EntryKind::Number.validate("-12.50").is_ok());
+        assert!(EntryKind::Number.validate("1e5").is_err());
+        assert!(EntryKind::Phone.validate("+44 (0)1792 205678").is_ok());
+        assert!(EntryKind::Phone.validate("12345").is_err());
+        assert!(EntryKind::Login.validate("two\nlines").is_err());
+        assert!(EntryKind::Note.validate("two\nlines").is_ok());
+
+        //Errors never include the value.
+        let error: error::Error = EntryKind::Totp.validate("not base32!").unwrap_err();
+        assert!(!error.to_string().contains("not base32!"));
+        assert!("password".parse::<EntryKind>().is_err());
+        assert_eq!("TOTP".parse::<EntryKind>().unwrap(), EntryKind::Totp);
+
+        assert!(EntryKind::SortCode.validate("12-34-56").is_ok());
+        assert!(EntryKind::AccountNumber.validate("1234567").is_err());
+        assert!(EntryKind::Card.validate("4111 1111 1111 1112").is_err());
+        assert_eq!(
+            "sort-code".parse::<EntryKind>().unwrap(),
+            EntryKind::SortCode
+        );
+
+        assert_eq!(date_of(0), (1970, 1, 1));
+        assert_eq!(date_of(951_782_400), (2000, 2, 29));
+        assert_eq!(date_of(4_107_542_399), (2100, 2, 28));
+    }
+
+    #[test]
+    fn test_url_matching() {
+        assert!(url_matches(
+            "https://www.google.com/",
+            "https://accounts.google.com/signin"
+        ));
+        assert!(url_matches(
+            "https://google.com",
+            "http://GOOGLE.com:8080/?q=1"
+        ));
+        assert!(!url_matches(
+            "https://google.com/",
+            "https://notgoogle.com/"
+        ));
+        assert!(!url_matches(
+            "https://accounts.google.com/",
+            "https://google.com/"
+        ));
+    }
+
+    #[test]
+    fn test_totp_codes() {
+        //The SHA1 test vectors from RFC 6238, whose secret is "12345678901234567890".
+        let totp: Totp = Totp::parse(
+            "otpauth://totp/Example:alice@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8",
+        )
+        .unwrap();
+        assert_eq!(totp.code(59), "94287082");
+        assert_eq!(totp.code(1111111109), "07081804");
+        assert_eq!(totp.code(2000000000), "69279037");
+        assert_eq!(totp.remaining(59), 1);
+
+        let bare: Totp = Totp::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(bare.code(1234567890), "005924");
+        assert!(Totp::parse("otpauth://totp/x?secret=GEZDGNBV&algorithm=SHA256").is_err());
+    }
+}
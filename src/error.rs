@@ -0,0 +1,91 @@
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+};
+
+/// Why loading, saving or reading a wallet failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The wallet, or the entry a reference points to, does not exist.
+    NotFound(String),
+    /// The wallet, or a reference in it, is malformed.
+    InvalidData(String),
+    /// A value does not suit its entry's kind, or a URI names an unknown scheme.
+    InvalidInput(String),
+    /// Reading or writing the wallet failed for any other reason.
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(message)
+            | Error::InvalidData(message)
+            | Error::InvalidInput(message) => {
+                write!(f, "{}", message)
+            }
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The model reports failures as `io::Error`s, as the command line does, and
+/// their kind says which of these they are.
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            ErrorKind::NotFound => Error::NotFound(error.to_string()),
+            ErrorKind::InvalidData => Error::InvalidData(error.to_string()),
+            ErrorKind::InvalidInput => Error::InvalidInput(error.to_string()),
+            _ => Error::Io(error),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::NotFound(message) => io::Error::new(ErrorKind::NotFound, message),
+            Error::InvalidData(message) => io::Error::new(ErrorKind::InvalidData, message),
+            Error::InvalidInput(message) => io::Error::new(ErrorKind::InvalidInput, message),
+            Error::Io(error) => error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_kinds() {
+        //The kinds the model uses keep their meaning, and their message.
+        let error: Error = Error::from(io::Error::new(
+            ErrorKind::NotFound,
+            "Error: the wallet does not exist.",
+        ));
+        assert!(matches!(error, Error::NotFound(_)));
+        assert_eq!(error.to_string(), "Error: the wallet does not exist.");
+        assert!(matches!(
+            Error::from(io::Error::new(ErrorKind::InvalidData, "malformed")),
+            Error::InvalidData(_)
+        ));
+
+        //Anything else is an I/O failure, and converts back unchanged.
+        let error: Error = Error::from(io::Error::new(ErrorKind::WouldBlock, "locked"));
+        assert!(matches!(error, Error::Io(_)));
+        assert_eq!(io::Error::from(error).kind(), ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,239 @@
+/// The latest a card can plausibly expire, in years from today.
+const MAX_CARD_LIFETIME_YEARS: u32 = 20;
+
+/// How many months ahead `audit` starts warning that a card is about to expire.
+pub(crate) const EXPIRY_WARNING_MONTHS: u32 = 2;
+
+/// Lengths of the IBANs of common countries. Others only get the generic checks.
+const IBAN_LENGTHS: [(&str, usize); 20] = [
+    ("AT", 20),
+    ("BE", 16),
+    ("CH", 21),
+    ("CZ", 24),
+    ("DE", 22),
+    ("DK", 18),
+    ("ES", 24),
+    ("FI", 18),
+    ("FR", 27),
+    ("GB", 22),
+    ("GR", 27),
+    ("IE", 22),
+    ("IT", 27),
+    ("LU", 20),
+    ("NL", 18),
+    ("NO", 15),
+    ("PL", 28),
+    ("PT", 25),
+    ("SE", 24),
+    ("SK", 24),
+];
+
+/// A card network, recognised from the leading digits of a card number.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(crate) enum CardBrand {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+    Diners,
+    Jcb,
+}
+
+impl CardBrand {
+    /// Recognises the brand of a card number from its digits alone.
+    pub(crate) fn detect(digits: &str) -> Option<CardBrand> {
+        let prefix = |length: usize| -> u32 {
+            digits
+                .get(..length)
+                .and_then(|prefix| prefix.parse().ok())
+                .unwrap_or(0)
+        };
+        match (prefix(1), prefix(2), prefix(3), prefix(4)) {
+            (4, _, _, _) => Some(CardBrand::Visa),
+            (_, 51..=55, _, _) | (_, _, _, 2221..=2720) => Some(CardBrand::Mastercard),
+            (_, 34 | 37, _, _) => Some(CardBrand::Amex),
+            (_, 65, _, _) | (_, _, 644..=649, _) | (_, _, _, 6011) => Some(CardBrand::Discover),
+            (_, 36 | 38 | 39, _, _) | (_, _, 300..=305, _) => Some(CardBrand::Diners),
+            (_, _, _, 3528..=3589) => Some(CardBrand::Jcb),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            CardBrand::Visa => "Visa",
+            CardBrand::Mastercard => "Mastercard",
+            CardBrand::Amex => "American Express",
+            CardBrand::Discover => "Discover",
+            CardBrand::Diners => "Diners Club",
+            CardBrand::Jcb => "JCB",
+        }
+    }
+
+    fn lengths(&self) -> &'static [usize] {
+        match self {
+            CardBrand::Visa => &[13, 16, 19],
+            CardBrand::Mastercard => &[16],
+            CardBrand::Amex => &[15],
+            CardBrand::Discover | CardBrand::Jcb => &[16, 17, 18, 19],
+            CardBrand::Diners => &[14, 16, 19],
+        }
+    }
+}
+
+/// A UK sort code: six digits, optionally split into pairs by `-` or spaces.
+pub(crate) fn is_sort_code(value: &str) -> bool {
+    let digits: String = value.replace(['-', ' '], "");
+    let separated: bool = value.len() == 8
+        && value.chars().nth(2) == value.chars().nth(5)
+        && matches!(value.chars().nth(2), Some('-' | ' '));
+    digits.len() == 6
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && (value.len() == 6 || separated)
+}
+
+/// A UK account number, which is always eight digits.
+pub(crate) fn is_account_number(value: &str) -> bool {
+    value.len() == 8 && value.chars().all(|c| c.is_ascii_digit())
+}
+
+/// An IBAN, in groups of four or not, whose check digits are right.
+pub(crate) fn is_iban(value: &str) -> bool {
+    let iban: String = value.replace(' ', "").to_uppercase();
+    let (country, check_digits) = (iban.get(..2), iban.get(2..4));
+    let well_formed: bool = (15..=34).contains(&iban.len())
+        && country.is_some_and(|country| country.chars().all(|c| c.is_ascii_uppercase()))
+        && check_digits.is_some_and(|digits| digits.chars().all(|c| c.is_ascii_digit()))
+        && iban.chars().all(|c| c.is_ascii_alphanumeric());
+    if !well_formed {
+        return false;
+    }
+    let expected_length: Option<usize> = IBAN_LENGTHS
+        .iter()
+        .find(|(code, _)| Some(*code) == country)
+        .map(|(_, length)| *length);
+    if expected_length.is_some_and(|length| length != iban.len()) {
+        return false;
+    }
+
+    //ISO 13616: move the first four characters to the end, turn letters into
+    //10 to 35, and the number must leave a remainder of 1 when divided by 97.
+    let rearranged: String = format!("{}{}", &iban[4..], &iban[..4]);
+    let remainder: u32 = rearranged.chars().fold(0, |remainder, c| {
+        let value: u32 = c.to_digit(36).unwrap_or(0);
+        let shift: u32 = if value < 10 { 10 } else { 100 };
+        (remainder * shift + value) % 97
+    });
+    remainder == 1
+}
+
+/// The digits of a card number written with or without spaces or dashes.
+pub(crate) fn card_digits(value: &str) -> Option<String> {
+    let digits: String = value.replace([' ', '-'], "");
+    (digits.chars().all(|c| c.is_ascii_digit()) && (12..=19).contains(&digits.len()))
+        .then_some(digits)
+}
+
+/// A card number that passes the Luhn check and, for known brands, has one of
+/// the brand's lengths.
+pub(crate) fn is_card_number(value: &str) -> bool {
+    let Some(digits) = card_digits(value) else {
+        return false;
+    };
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(position, digit)| match (position % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+        && CardBrand::detect(&digits).is_none_or(|brand| brand.lengths().contains(&digits.len()))
+}
+
+/// The year and month of an expiry date written as `MM/YY` or `MM/YYYY`.
+pub(crate) fn parse_expiry(value: &str) -> Option<(u32, u32)> {
+    let (month, year) = value.trim().split_once('/')?;
+    if month.len() != 2 || !matches!(year.len(), 2 | 4) {
+        return None;
+    }
+    let month: u32 = month
+        .parse()
+        .ok()
+        .filter(|month| (1..=12).contains(month))?;
+    let year: u32 = year.parse().ok()?;
+    Some((if year < 100 { 2000 + year } else { year }, month))
+}
+
+/// An expiry date that is no further ahead of `today` than a card can last.
+pub(crate) fn is_expiry(value: &str, today: (u32, u32, u32)) -> bool {
+    parse_expiry(value).is_some_and(|(year, _)| year <= today.0 + MAX_CARD_LIFETIME_YEARS)
+}
+
+/// Whole months from `today` until the end of the month a card expires in,
+/// negative once it has expired.
+pub(crate) fn months_until_expiry(expiry: (u32, u32), today: (u32, u32, u32)) -> i64 {
+    let (year, month) = expiry;
+    (i64::from(year) * 12 + i64::from(month)) - (i64::from(today.0) * 12 + i64::from(today.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bank_details() {
+        assert!(is_sort_code("12-34-56"));
+        assert!(is_sort_code("12 34 56"));
+        assert!(is_sort_code("123456"));
+        assert!(!is_sort_code("12-34 56"));
+        assert!(!is_sort_code("12-34-5"));
+        assert!(!is_sort_code("1-234-56"));
+
+        assert!(is_account_number("12345678"));
+        assert!(!is_account_number("1234567"));
+        assert!(!is_account_number("1234567a"));
+
+        assert!(is_iban("GB82 WEST 1234 5698 7654 32"));
+        assert!(is_iban("de89370400440532013000"));
+        //A swapped pair of digits, and a digit missing.
+        assert!(!is_iban("GB82 WEST 1234 5698 7654 23"));
+        assert!(!is_iban("GB82 WEST 1234 5698 7654 3"));
+    }
+
+    #[test]
+    fn test_cards() {
+        assert!(is_card_number("4111 1111 1111 1111"));
+        assert!(is_card_number("5500-0000-0000-0004"));
+        assert!(is_card_number("378282246310005"));
+        assert!(!is_card_number("4111 1111 1111 1112"));
+        //Passes the Luhn check, but Amex numbers have 15 digits.
+        assert!(!is_card_number("3400 0000 0000 0009"));
+
+        assert_eq!(CardBrand::detect("4111111111111111"), Some(CardBrand::Visa));
+        assert_eq!(
+            CardBrand::detect("2221000000000009"),
+            Some(CardBrand::Mastercard)
+        );
+        assert_eq!(
+            CardBrand::detect("6011111111111117"),
+            Some(CardBrand::Discover)
+        );
+        assert_eq!(CardBrand::detect("9999999999999995"), None);
+
+        let today: (u32, u32, u32) = (2026, 10, 18);
+        assert_eq!(parse_expiry("03/27"), Some((2027, 3)));
+        assert_eq!(parse_expiry("03/2027"), Some((2027, 3)));
+        assert!(parse_expiry("13/27").is_none());
+        assert!(parse_expiry("3/27").is_none());
+        assert!(is_expiry("01/20", today));
+        assert!(!is_expiry("01/2099", today));
+        assert_eq!(months_until_expiry((2026, 10), today), 0);
+        assert_eq!(months_until_expiry((2026, 9), today), -1);
+        assert_eq!(months_until_expiry((2027, 3), today), 5);
+    }
+}
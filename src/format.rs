@@ -0,0 +1,589 @@
+use crate::{
+    category::{escape_name, split_path},
+    item::ID_KEY,
+    template::Template,
+    wallet::Wallet,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind, Read},
+    path::Path,
+    str::FromStr,
+};
+
+pub(crate) const FORMAT: &str = "371pass";
+
+/// The newest version of the file format this build reads and the one it writes.
+pub(crate) const VERSION: u64 = 7;
+
+/// Upgrades a document from the version at its index plus one to the next version.
+const MIGRATIONS: [fn(Value) -> Value; 6] =
+    [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7];
+
+/// The fields at the top of a saved wallet, ahead of its categories.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct Header {
+    pub(crate) format: String,
+    pub(crate) version: u64,
+    /// Cipher protecting the categories, once wallets can be encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cipher: Option<String>,
+    /// Key derivation parameters that go with `cipher`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) kdf: Option<Value>,
+}
+
+impl Default for Header {
+    fn default() -> Header {
+        Header {
+            format: String::from(FORMAT),
+            version: VERSION,
+            cipher: None,
+            kdf: None,
+        }
+    }
+}
+
+/// A current-version document, header first, then the templates of the categories
+/// that have one and the categories, each with its ID and those of its items.
+#[derive(Serialize)]
+pub(crate) struct Document<'a> {
+    #[serde(flatten)]
+    header: Header,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    templates: BTreeMap<String, &'a Template>,
+    categories: &'a Wallet,
+}
+
+impl Document<'_> {
+    pub(crate) fn new(wallet: &Wallet) -> Document<'_> {
+        Document {
+            header: Header::default(),
+            templates: wallet.templates(),
+            categories: wallet,
+        }
+    }
+}
+
+/// A document read straight into a wallet, skipping the `Value` that migrations need.
+#[derive(Deserialize)]
+struct CurrentDocument {
+    format: String,
+    version: u64,
+    #[serde(default)]
+    cipher: Option<String>,
+    #[serde(default)]
+    templates: BTreeMap<String, Template>,
+    categories: Wallet,
+}
+
+impl CurrentDocument {
+    fn into_wallet(self) -> Wallet {
+        let mut wallet: Wallet = self.categories;
+        for (cat_ident, template) in self.templates {
+            wallet.new_category(&cat_ident).set_template(Some(template));
+        }
+        wallet
+    }
+}
+
+/// Starts every MessagePack wallet. Like PNG's signature, the high bit and line
+/// endings catch files mangled by text-mode transfers, and no text format can start
+/// with it.
+pub(crate) const MSGPACK_MAGIC: &[u8] = b"\x89371pass\r\n\x1a\n";
+
+/// The serializations a single-file wallet can be saved in.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(crate) enum FileFormat {
+    Json,
+    Yaml,
+    Toml,
+    /// Binary, but not substantially smaller or faster than JSON: on the 50k-entry
+    /// wallet in `benches/serialization.rs` it is about 13% smaller, saves about 15%
+    /// faster and loads no faster, since building the wallet dominates loading in
+    /// either format. It is not readable or diffable.
+    MessagePack,
+}
+
+impl FileFormat {
+    /// Picks the format from the file extension, falling back to JSON.
+    pub(crate) fn from_extension(filename: &str) -> FileFormat {
+        Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| extension.to_lowercase().parse().ok())
+            .unwrap_or(FileFormat::Json)
+    }
+
+    /// Recognises binary formats by their magic bytes.
+    pub(crate) fn detect(filename: &str) -> Option<FileFormat> {
+        let mut header: [u8; MSGPACK_MAGIC.len()] = [0; MSGPACK_MAGIC.len()];
+        fs::File::open(filename)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .ok()
+            .filter(|_| header == MSGPACK_MAGIC)
+            .map(|_| FileFormat::MessagePack)
+    }
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "json",
+            FileFormat::Yaml => "yaml",
+            FileFormat::Toml => "toml",
+            FileFormat::MessagePack => "msgpack",
+        }
+    }
+
+    /// Reads a document in this format into a JSON value, ready for `upgrade`.
+    pub(crate) fn parse(&self, file_contents: &[u8]) -> Result<Value, Error> {
+        match self {
+            FileFormat::Json => serde_json::from_slice(file_contents).map_err(invalid_data),
+            FileFormat::Yaml => serde_yaml::from_slice(file_contents).map_err(invalid_data),
+            FileFormat::Toml => toml::from_slice(file_contents).map_err(invalid_data),
+            FileFormat::MessagePack => {
+                rmp_serde::from_slice(strip_magic(file_contents)?).map_err(invalid_data)
+            }
+        }
+    }
+
+    /// Reads a current-version, unencrypted document without going through `parse`
+    /// and `upgrade`, which is what makes loading large wallets fast.
+    ///
+    /// Returns `None` for anything else, which should be read the slow way to get
+    /// it upgraded or a proper error.
+    pub(crate) fn read_current(&self, file_contents: &[u8]) -> Option<Wallet> {
+        let document: CurrentDocument = match self {
+            FileFormat::Json => serde_json::from_slice(file_contents).ok()?,
+            FileFormat::Yaml => serde_yaml::from_slice(file_contents).ok()?,
+            FileFormat::Toml => toml::from_slice(file_contents).ok()?,
+            FileFormat::MessagePack => {
+                rmp_serde::from_slice(strip_magic(file_contents).ok()?).ok()?
+            }
+        };
+        (document.format == FORMAT && document.version == VERSION && document.cipher.is_none())
+            .then(|| document.into_wallet())
+    }
+
+    /// Renders the wallet as a current-version document in this format.
+    pub(crate) fn write(&self, wallet: &Wallet) -> Result<Vec<u8>, Error> {
+        let document: Document = Document::new(wallet);
+        match self {
+            FileFormat::Json => serde_json::to_vec(&document).map_err(invalid_data),
+            FileFormat::Yaml => serde_yaml::to_string(&document)
+                .map(String::into_bytes)
+                .map_err(invalid_data),
+            FileFormat::Toml => toml::to_string(&document)
+                .map(String::into_bytes)
+                .map_err(invalid_data),
+            FileFormat::MessagePack => {
+                let mut file_contents: Vec<u8> = MSGPACK_MAGIC.to_vec();
+                rmp_serde::encode::write_named(&mut file_contents, &document)
+                    .map_err(invalid_data)?;
+                Ok(file_contents)
+            }
+        }
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<FileFormat, Error> {
+        match format {
+            "json" => Ok(FileFormat::Json),
+            "yaml" | "yml" => Ok(FileFormat::Yaml),
+            "toml" => Ok(FileFormat::Toml),
+            "msgpack" | "mpk" => Ok(FileFormat::MessagePack),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Error: unknown database format \"{}\", expected json, yaml, toml or msgpack.",
+                    format
+                ),
+            )),
+        }
+    }
+}
+
+/// Upgrades a document of any supported version and reads the wallet in it.
+pub(crate) fn read(document: Value) -> Result<Wallet, Error> {
+    let document: CurrentDocument =
+        serde_json::from_value(upgrade(document)?).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Error: malformed wallet file: {}", error),
+            )
+        })?;
+    Ok(document.into_wallet())
+}
+
+/// Upgrades a document of any supported version in memory to the current version.
+pub(crate) fn upgrade(mut document: Value) -> Result<Value, Error> {
+    let mut version: u64 = version_of(&document)?;
+
+    if version > VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Error: the wallet is in version {} of the file format, but this version of 371pass only reads up to version {}. Please upgrade 371pass.",
+                version, VERSION
+            ),
+        ));
+    }
+    while version < VERSION {
+        document = MIGRATIONS[version as usize - 1](document);
+        version += 1;
+    }
+
+    let header: Header = serde_json::from_value(document.clone()).map_err(|error| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Error: malformed file header: {}", error),
+        )
+    })?;
+    if let Some(cipher) = header.cipher {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Error: the wallet is encrypted with {}, which this version of 371pass does not support.",
+                cipher
+            ),
+        ));
+    }
+
+    if document.get("categories").is_none() {
+        document["categories"] = json!({});
+    }
+    Ok(document)
+}
+
+fn strip_magic(file_contents: &[u8]) -> Result<&[u8], Error> {
+    file_contents.strip_prefix(MSGPACK_MAGIC).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "Error: the wallet is not a MessagePack wallet file.",
+        )
+    })
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(error: E) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+/// Files saved before the header was introduced are version 1.
+fn version_of(document: &Value) -> Result<u64, Error> {
+    let object = document
+        .as_object()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Error: malformed wallet file."))?;
+
+    //A category called "format" holds an object, never a string.
+    match object.get("format") {
+        Some(Value::String(format)) if format == FORMAT => object
+            .get("version")
+            .and_then(Value::as_u64)
+            .filter(|version| *version > 0)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Error: the wallet has no valid format version.",
+                )
+            }),
+        Some(Value::String(format)) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Error: {} files are not 371pass wallets.", format),
+        )),
+        _ => Ok(1),
+    }
+}
+
+/// Version 1 was the bare map of categories.
+fn v1_to_v2(document: Value) -> Value {
+    json!({"format": FORMAT, "version": 2, "categories": document})
+}
+
+/// Version 3 allows typed entries, which earlier versions never contain.
+fn v2_to_v3(mut document: Value) -> Value {
+    document["version"] = json!(3);
+    document
+}
+
+/// Version 4 allows category templates, which earlier versions never contain.
+fn v3_to_v4(mut document: Value) -> Value {
+    document["version"] = json!(4);
+    document
+}
+
+/// Version 5 allows sub-categories, stored after the items under their name
+/// followed by `/`. Names are escaped so that no item or category name can be
+/// mistaken for one, which only changes names containing `/` or `%`.
+fn v4_to_v5(mut document: Value) -> Value {
+    let escape_keys = |map: &mut Value| {
+        if let Some(map) = map.as_object_mut() {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(name, value)| (escape_name(&name), value))
+                .collect();
+        }
+    };
+    if let Some(categories) = document.get_mut("categories") {
+        if let Some(categories) = categories.as_object_mut() {
+            categories.values_mut().for_each(escape_keys);
+        }
+        escape_keys(categories);
+    }
+    if let Some(templates) = document.get_mut("templates") {
+        escape_keys(templates);
+    }
+    document["version"] = json!(5);
+    document
+}
+
+/// Version 6 saves the IDs of categories and items. Earlier versions have none,
+/// so they are given new ones when loaded.
+fn v5_to_v6(mut document: Value) -> Value {
+    document["version"] = json!(6);
+    document
+}
+
+/// Version 7 saves each ID under `@id` in its category or item, rather than in an
+/// `ids` map keyed by path, so that renaming one by hand keeps its ID. An item or
+/// sub-category called `@id` is escaped to make way for it.
+fn v6_to_v7(mut document: Value) -> Value {
+    fn escape_id_keys(category: &mut Value) {
+        let Some(category) = category.as_object_mut() else {
+            return;
+        };
+        for name in [ID_KEY, "@id/"] {
+            if let Some(value) = category.remove(name) {
+                category.insert(name.replacen('@', "%40", 1), value);
+            }
+        }
+        for (name, child) in category.iter_mut() {
+            if name.ends_with('/') {
+                escape_id_keys(child);
+            }
+        }
+    }
+
+    let ids: Value = document
+        .as_object_mut()
+        .and_then(|document| document.remove("ids"))
+        .unwrap_or_default();
+    if let Some(categories) = document
+        .get_mut("categories")
+        .and_then(Value::as_object_mut)
+    {
+        categories.values_mut().for_each(escape_id_keys);
+    }
+    if let (Some(ids), Some(categories)) = (ids.as_object(), document.get_mut("categories")) {
+        for (path, id) in ids {
+            //Category paths end in `/`, so their last name is empty.
+            let names: Vec<String> = split_path(path);
+            let Some((last, parents)) = names.split_last() else {
+                continue;
+            };
+            let mut record: Option<&mut Value> =
+                parents
+                    .iter()
+                    .enumerate()
+                    .try_fold(&mut *categories, |map, (depth, name)| {
+                        let key: String = match depth {
+                            0 => escape_name(name),
+                            _ => format!("{}/", escape_name(name)),
+                        };
+                        map.get_mut(key)
+                    });
+            if !last.is_empty() {
+                record = record.and_then(|category| category.get_mut(escape_name(last)));
+            }
+            if let Some(record) = record.and_then(Value::as_object_mut) {
+                record.insert(String::from(ID_KEY), id.clone());
+            }
+        }
+    }
+    document["version"] = json!(7);
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{category::Category, template::Field};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_legacy_files_are_upgraded() {
+        let legacy: &str = r#"{"format":{"Google":{"password":"pass1234"}}}"#;
+        let document: Value = upgrade(FileFormat::Json.parse(legacy.as_bytes()).unwrap()).unwrap();
+        assert_eq!(
+            document["categories"],
+            json!({"format": {"Google": {"password": "pass1234"}}})
+        );
+
+        //Legacy files have no IDs, so the ones given when loading are saved with the wallet.
+        let wallet: Wallet = read(document).unwrap();
+        assert!(wallet.has_new_ids());
+        let written: Value = FileFormat::Json
+            .parse(&FileFormat::Json.write(&wallet).unwrap())
+            .unwrap();
+        assert_eq!(written["version"], json!(7));
+        assert_eq!(
+            written["categories"]["format"]["Google"],
+            json!({
+                "@id": wallet.get("format").unwrap().get("Google").unwrap().id(),
+                "password": "pass1234"
+            })
+        );
+
+        let reread: Wallet = read(written).unwrap();
+        assert!(!reread.has_new_ids());
+        assert_eq!(reread.ids(), wallet.ids());
+    }
+
+    #[test]
+    fn test_names_escaped_when_upgrading() {
+        //Before version 5 nothing was a sub-category, whatever its name.
+        let legacy: Value = json!({
+            "format": FORMAT,
+            "version": 4,
+            "templates": {"Websites/Social": {"fields": [{"name": "password"}]}},
+            "categories": {"Websites/Social": {"Facebook/": {"password": "pass1234fb"}}}
+        });
+        let wallet: Wallet = read(legacy).unwrap();
+        assert_eq!(wallet.size(), 1);
+        let category = wallet.get("Websites%2FSocial").unwrap();
+        assert_eq!(category.get_ident(), "Websites/Social");
+        assert!(category.template().is_some());
+        assert_eq!(category.categories().count(), 0);
+        assert_eq!(
+            category.get("Facebook/").unwrap().get("password"),
+            Some("pass1234fb")
+        );
+    }
+
+    #[test]
+    fn test_ids_moved_into_records() {
+        let legacy: Value = json!({
+            "format": FORMAT,
+            "version": 6,
+            "ids": {
+                "Work/": "00000000-0000-0000-0000-000000000001",
+                "Work/AWS%2FGCP/": "00000000-0000-0000-0000-000000000002",
+                "Work/AWS%2FGCP/@id": "00000000-0000-0000-0000-000000000003",
+                "Gone/": "00000000-0000-0000-0000-000000000004"
+            },
+            "categories": {"Work": {"AWS%2FGCP/": {"@id": {"password": "pass1234"}}}}
+        });
+        let document: Value = upgrade(legacy).unwrap();
+        assert_eq!(document.get("ids"), None);
+        assert_eq!(
+            document["categories"],
+            json!({"Work": {
+                "@id": "00000000-0000-0000-0000-000000000001",
+                "AWS%2FGCP/": {
+                    "@id": "00000000-0000-0000-0000-000000000002",
+                    "%40id": {"@id": "00000000-0000-0000-0000-000000000003", "password": "pass1234"}
+                }
+            }})
+        );
+
+        let wallet: Wallet = read(document).unwrap();
+        assert!(!wallet.has_new_ids());
+        let category: &Category = wallet.get("Work/AWS%2FGCP").unwrap();
+        assert_eq!(category.get_ident(), "AWS/GCP");
+        assert_eq!(category.get("@id").unwrap().id(), Uuid::from_u128(3));
+    }
+
+    #[test]
+    fn test_unsupported_files_are_refused() {
+        let newer: Value = json!({"format": FORMAT, "version": VERSION + 1, "categories": {}});
+        let error: Error = upgrade(newer).unwrap_err();
+        assert!(error.to_string().contains("upgrade 371pass"));
+
+        let encrypted: Value = json!({
+            "format": FORMAT,
+            "version": VERSION,
+            "cipher": "chacha20poly1305",
+            "kdf": {"name": "argon2id"},
+            "categories": "..."
+        });
+        assert!(upgrade(encrypted).is_err());
+
+        assert!(upgrade(json!({"format": "keepass", "version": 1})).is_err());
+    }
+
+    #[test]
+    fn test_yaml_and_toml_documents() {
+        let mut wallet: Wallet = Wallet::new();
+        wallet
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":{"type":"secret","value":"pass1234"},"url":"https://www.google.com/"}}}"#,
+            )
+            .unwrap();
+
+        wallet.get_mut("Bank Accounts").unwrap().set_template(Some(
+            Template::new().field(Field::new("Sort Code").required()),
+        ));
+
+        let toml: Vec<u8> = FileFormat::Toml.write(&wallet).unwrap();
+        assert!(toml.starts_with(b"format = \"371pass\"\nversion = 7\n"));
+        let yaml: Vec<u8> = FileFormat::Yaml.write(&wallet).unwrap();
+        assert!(yaml.starts_with(b"format: 371pass\nversion: 7\n"));
+
+        for (format, contents) in [(FileFormat::Toml, toml), (FileFormat::Yaml, yaml)] {
+            assert_eq!(read(format.parse(&contents).unwrap()).unwrap(), wallet);
+            assert_eq!(format.read_current(&contents).unwrap(), wallet);
+            assert_eq!(format.read_current(&contents).unwrap().ids(), wallet.ids());
+        }
+
+        //Hand-edited values that YAML or TOML read as numbers are named, not changed.
+        let contents: &[u8] =
+            b"format: 371pass\nversion: 6\ncategories:\n  Cards:\n    Visa:\n      pin: 1234\n";
+        let error: Error = read(FileFormat::Yaml.parse(contents).unwrap()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("pin: the value 1234 must be quoted"));
+        let contents: &[u8] = b"format = \"371pass\"\nversion = 6\n[categories.Cards.Visa.pin]\ntype = \"number\"\nvalue = 1234\n";
+        let error: Error = read(FileFormat::Toml.parse(contents).unwrap()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("pin: the value 1234 must be quoted"));
+
+        assert_eq!(FileFormat::from_extension("wallet.YML"), FileFormat::Yaml);
+        assert_eq!(FileFormat::from_extension("wallet"), FileFormat::Json);
+        assert!("xml".parse::<FileFormat>().is_err());
+    }
+
+    #[test]
+    fn test_msgpack_documents() {
+        let mut wallet: Wallet = Wallet::new();
+        wallet
+            .load_json(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"}}}"#,
+            )
+            .unwrap();
+
+        let msgpack: Vec<u8> = FileFormat::MessagePack.write(&wallet).unwrap();
+        assert!(msgpack.starts_with(MSGPACK_MAGIC));
+        assert!(msgpack.len() < FileFormat::Json.write(&wallet).unwrap().len());
+
+        //The fast path and the upgrade path read the same wallet.
+        assert_eq!(
+            FileFormat::MessagePack.read_current(&msgpack).unwrap(),
+            wallet
+        );
+        assert_eq!(
+            read(FileFormat::MessagePack.parse(&msgpack).unwrap()).unwrap(),
+            wallet
+        );
+
+        assert!(FileFormat::MessagePack.parse(&msgpack[1..]).is_err());
+        assert_eq!(
+            "msgpack".parse::<FileFormat>().unwrap(),
+            FileFormat::MessagePack
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::encoding::{from_hex, to_hex};
+use hmac::{Hmac, KeyInit, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    fmt, fs,
+    io::{Error, ErrorKind},
+};
+
+const TAG_PREFIX: &str = "hmac-sha256:";
+
+/// A keyed HMAC-SHA256 over the canonical JSON of a wallet, stored next to the database file.
+#[derive(Clone, Eq, PartialEq)]
+pub(crate) struct Integrity {
+    key: Vec<u8>,
+    accept_tampered: bool,
+}
+
+impl Integrity {
+    pub(crate) fn new(key: &[u8]) -> Integrity {
+        Integrity {
+            key: key.to_vec(),
+            accept_tampered: false,
+        }
+    }
+
+    pub(crate) fn load(filename: &str) -> Result<Integrity, Error> {
+        let key: Vec<u8> = fs::read(filename)?;
+        if key.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Error: integrity key file is empty.",
+            ));
+        }
+        Ok(Integrity::new(&key))
+    }
+
+    /// Loads the wallet anyway when verification fails, returning a warning instead.
+    pub(crate) fn accept_tampered(mut self, accept_tampered: bool) -> Integrity {
+        self.accept_tampered = accept_tampered;
+        self
+    }
+
+    pub(crate) fn tag_filename(filename: &str) -> String {
+        format!("{}.mac", filename)
+    }
+
+    pub(crate) fn sign(&self, file_contents: &str) -> Result<String, Error> {
+        let mac = self.mac(&canonicalize(file_contents)?);
+        Ok(format!(
+            "{}{}",
+            TAG_PREFIX,
+            to_hex(&mac.finalize().into_bytes())
+        ))
+    }
+
+    /// Returns the warning to show if verification failed but the wallet is accepted anyway.
+    pub(crate) fn verify(
+        &self,
+        filename: &str,
+        file_contents: &str,
+    ) -> Result<Option<String>, Error> {
+        let tag: Option<String> = fs::read_to_string(Integrity::tag_filename(filename)).ok();
+        self.verify_tag(tag.as_deref(), file_contents)
+    }
+
+    /// Like `verify`, for stores that keep the tag themselves rather than next to a file.
+    pub(crate) fn verify_tag(
+        &self,
+        tag: Option<&str>,
+        file_contents: &str,
+    ) -> Result<Option<String>, Error> {
+        match self.check(tag, file_contents) {
+            Err(error) if self.accept_tampered => {
+                Ok(Some(format!("Warning: {} Loading anyway.", error)))
+            }
+            Err(error) => Err(Error::new(
+                error.kind(),
+                format!("Error: {} Use --accept-tampered to load it anyway.", error),
+            )),
+            Ok(()) => Ok(None),
+        }
+    }
+
+    /// A key for another use of the wallet key, so that its MACs never match the wallet's.
+    pub(crate) fn derive_key(&self, purpose: &str) -> Vec<u8> {
+        self.mac(purpose).finalize().into_bytes().to_vec()
+    }
+
+    fn check(&self, tag: Option<&str>, file_contents: &str) -> Result<(), Error> {
+        let tag: &str = tag.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "The wallet has no integrity tag.")
+        })?;
+        let tag: Vec<u8> = tag
+            .trim()
+            .strip_prefix(TAG_PREFIX)
+            .and_then(from_hex)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "The wallet integrity tag is malformed.",
+                )
+            })?;
+
+        self.mac(&canonicalize(file_contents)?)
+            .verify_slice(&tag)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "The wallet failed its integrity check and may have been modified outside 371pass.",
+                )
+            })
+    }
+
+    fn mac(&self, canonical: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(canonical.as_bytes());
+        mac
+    }
+}
+
+impl fmt::Debug for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Integrity")
+            .field("accept_tampered", &self.accept_tampered)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sorted keys and no insignificant whitespace, so reformatting alone is not tampering.
+fn canonicalize(file_contents: &str) -> Result<String, Error> {
+    let value: Value = serde_json::from_str(file_contents)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+    serde_json::to_string(&value).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_ignores_formatting() {
+        let integrity: Integrity = Integrity::new(b"secret key");
+        let compact: String = integrity
+            .sign(r#"{"Websites":{"Google":{"url":"https://www.google.com/"}}}"#)
+            .unwrap();
+        let pretty: String = integrity
+            .sign("{\n  \"Websites\": {\n    \"Google\": { \"url\": \"https://www.google.com/\" }\n  }\n}")
+            .unwrap();
+        assert!(compact.starts_with(TAG_PREFIX));
+        assert_eq!(compact, pretty);
+
+        let other_key: String = Integrity::new(b"other key")
+            .sign(r#"{"Websites":{"Google":{"url":"https://www.google.com/"}}}"#)
+            .unwrap();
+        assert_ne!(compact, other_key);
+    }
+}
@@ -1,52 +1,261 @@
-use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
-use std::{collections::BTreeMap, fmt};
+use crate::{
+    category,
+    entry::EntryKind,
+    merge::{Conflict, ConflictKind},
+    visitor::Visitor,
+};
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess},
+    ser::{self, SerializeMap},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+use uuid::Uuid;
 
-#[derive(Clone, Eq, Debug, Deserialize)]
-pub(crate) struct Item {
-    #[serde(flatten)]
+/// The key an item's or category's ID is saved under, ahead of its entries, items
+/// or sub-categories. No entry can be called this.
+pub(crate) const ID_KEY: &str = "@id";
+
+/// Serialized as a map of its ID, under `@id`, and its entries. The identifier is
+/// the key the item is stored under in its category, which restores it when
+/// deserializing.
+///
+/// The ID is random and stays the same when the item is renamed or moved, which
+/// is why it is saved with the item rather than by its name. An item saved without
+/// one is given a new one when it is read. IDs are ignored when comparing items.
+///
+/// Entries without a kind are saved as plain strings and typed ones as
+/// `{"type": <kind>, "value": <value>}`.
+#[derive(Clone, Eq, Debug)]
+pub struct Item {
     identifier: String,
+    id: Uuid,
+    /// Whether the ID was made up when reading an item saved without one.
+    new_id: bool,
     entries: BTreeMap<String, String>,
+    kinds: BTreeMap<String, EntryKind>,
 }
 
 impl Item {
-    pub(crate) fn new(identifier: String) -> Item {
+    pub fn new(identifier: String) -> Item {
         Item {
             identifier,
+            id: Uuid::new_v4(),
+            new_id: false,
             entries: BTreeMap::new(),
+            kinds: BTreeMap::new(),
         }
     }
 
-    pub(crate) fn size(&self) -> usize {
+    pub fn size(&self) -> usize {
         self.entries.len()
     }
 
-    pub(crate) fn empty(&self) -> bool {
+    pub fn empty(&self) -> bool {
         self.entries.is_empty()
     }
 
-    pub(crate) fn get_ident(&self) -> &String {
+    pub fn get_ident(&self) -> &String {
         &self.identifier
     }
 
-    pub(crate) fn set_ident(&mut self, identifier: &String) {
-        self.identifier = identifier.clone();
+    pub(crate) fn set_ident(&mut self, identifier: &str) {
+        self.identifier = identifier.to_string();
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub(crate) fn set_id(&mut self, id: Uuid) {
+        self.id = id;
+        self.new_id = false;
+    }
+
+    /// Whether the item was read without an ID, so that the one it has is not saved yet.
+    pub(crate) fn has_new_id(&self) -> bool {
+        self.new_id
     }
 
-    pub(crate) fn add_entry(&mut self, key: &String, value: &String) -> bool {
-        self.entries.insert(key.clone(), value.clone()).is_none()
+    pub(crate) fn mark_new_id(&mut self) {
+        self.new_id = true;
+    }
+
+    /// Returns false, after replacing its value, if the key was already there.
+    pub fn add_entry(&mut self, key: &str, value: &str) -> bool {
+        self.entries
+            .insert(key.to_string(), value.to_string())
+            .is_none()
+    }
+
+    /// The entries as `(key, value)` pairs, ordered by key.
+    ///
+    /// ```
+    /// use csc371_remake::Item;
+    ///
+    /// let mut item: Item = Item::new(String::from("Google"));
+    /// item.add_entry("username", "example@gmail.com");
+    /// item.add_entry("password", "pass1234");
+    /// let keys: Vec<&str> = item.entries().map(|(key, _)| key).collect();
+    /// assert_eq!(keys, ["password", "username"]);
+    /// ```
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub(crate) fn accept(&mut self, category: &str, visitor: &mut impl Visitor) {
+        visitor.visit_item(category, &self.identifier);
+        for (key, value) in self.entries.iter_mut() {
+            visitor.visit_entry(category, &self.identifier, key, value);
+        }
     }
 
     pub(crate) fn merge_entries(&mut self, other: &mut Item) {
         for (key, value) in other.entries.iter() {
-            self.add_entry(&key, &value);
+            self.add_entry(key, value);
+        }
+        self.kinds
+            .extend(other.kinds.iter().map(|(key, kind)| (key.clone(), *kind)));
+    }
+
+    /// Like `merge_entries`, but only takes the changes `theirs` made since `base`,
+    /// keeping ours where both sides changed an entry. Their value is then kept
+    /// too, under the first free `<key> (theirs)` name, and the conflict returned.
+    pub(crate) fn merge_entries_three_way(
+        &mut self,
+        base: Option<&Item>,
+        theirs: &Item,
+        path: &str,
+    ) -> Vec<Conflict> {
+        let empty: Item = Item::new(String::new());
+        let base: &Item = base.unwrap_or(&empty);
+        let keys: BTreeSet<String> = base
+            .entries
+            .keys()
+            .chain(self.entries.keys())
+            .chain(theirs.entries.keys())
+            .cloned()
+            .collect();
+
+        let mut conflicts: Vec<Conflict> = Vec::new();
+        let mut theirs_kept: Vec<(String, Conflict)> = Vec::new();
+        for key in keys.iter() {
+            let (b, o, t) = (
+                base.entries.get(key),
+                self.entries.get(key),
+                theirs.entries.get(key),
+            );
+            //Kinds merge like values, except that a conflict keeps our kind.
+            let (b_kind, o_kind, t_kind) = (base.kind(key), self.kind(key), theirs.kind(key));
+            let kind: Option<EntryKind> = if t_kind != b_kind && o_kind == b_kind {
+                t_kind
+            } else {
+                o_kind
+            };
+
+            if o == t || t == b {
+                self.set_kind(key, kind);
+                continue;
+            }
+            if o == b {
+                match t {
+                    Some(value) => {
+                        self.add_entry(key, value);
+                        self.set_kind(key, kind);
+                    }
+                    None => {
+                        self.delete_entry(key);
+                    }
+                }
+                continue;
+            }
+
+            let conflict: Conflict = Conflict {
+                kind: match (b, o, t) {
+                    (None, _, _) => ConflictKind::BothAdded,
+                    (Some(_), Some(_), Some(_)) => ConflictKind::BothChanged,
+                    _ => ConflictKind::DeletedAndChanged,
+                },
+                path: category::join_path(path, key),
+                kept_as: None,
+            };
+            match (o, t) {
+                //Deleted by us and changed by them, so their change is kept.
+                (None, Some(value)) => {
+                    self.add_entry(key, value);
+                    self.set_kind(key, t_kind);
+                    conflicts.push(conflict);
+                }
+                (Some(_), Some(_)) => theirs_kept.push((key.clone(), conflict)),
+                _ => conflicts.push(conflict),
+            }
+        }
+
+        //Named once every key is merged, so that no name either side uses is taken.
+        for (key, mut conflict) in theirs_kept {
+            let name: String = (1..)
+                .map(|number| match number {
+                    1 => format!("{} (theirs)", key),
+                    number => format!("{} (theirs {})", key, number),
+                })
+                .find(|name| !keys.contains(name) && !self.entries.contains_key(name))
+                .unwrap();
+            self.add_entry(&name, &theirs.entries[&key]);
+            self.set_kind(&name, theirs.kind(&key));
+            conflict.kept_as = Some(name);
+            conflicts.push(conflict);
+        }
+        conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+        conflicts
+    }
+
+    pub fn kind(&self, key: &str) -> Option<EntryKind> {
+        self.kinds.get(key).copied()
+    }
+
+    /// Sets or clears the kind of an existing entry, returning false if there is no such entry.
+    ///
+    /// ```
+    /// use csc371_remake::{EntryKind, Item};
+    ///
+    /// let mut item: Item = Item::new(String::from("Google"));
+    /// item.add_entry("password", "pass1234");
+    /// assert!(item.set_kind("password", Some(EntryKind::Secret)));
+    /// assert!(serde_json::to_string(&item)?
+    ///     .ends_with(r#""password":{"type":"secret","value":"pass1234"}}"#));
+    /// # Ok::<(), serde_json::Error>(())
+    /// ```
+    pub fn set_kind(&mut self, key: &str, kind: Option<EntryKind>) -> bool {
+        if !self.entries.contains_key(key) {
+            return false;
         }
+        match kind {
+            Some(kind) => self.kinds.insert(String::from(key), kind),
+            None => self.kinds.remove(key),
+        };
+        true
     }
 
-    pub(crate) fn get_entry(&mut self, key: &String) -> Option<&mut String> {
-        return self.entries.get_mut(key);
+    /// Replaces the values of sensitive entries with a mask, for output.
+    pub(crate) fn mask_sensitive(&mut self) {
+        for (key, kind) in self.kinds.iter() {
+            if let (true, Some(value)) = (kind.is_sensitive(), self.entries.get_mut(key)) {
+                *value = kind.mask(value);
+            }
+        }
     }
 
-    pub(crate) fn delete_entry(&mut self, key: &String) -> bool {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    pub fn delete_entry(&mut self, key: &str) -> bool {
+        self.kinds.remove(key);
         self.entries.remove(key).is_some()
     }
 }
@@ -56,30 +265,233 @@ impl fmt::Display for Item {
         for entry in self.entries.iter() {
             write!(f, "{}", serde_json::to_string(&entry).unwrap())?;
         }
-        write!(f, "{}", "")
+        Ok(())
     }
 }
 
 impl PartialEq<Self> for Item {
     fn eq(&self, other: &Self) -> bool {
-        self.identifier == self.identifier && self.entries == other.entries
+        self.identifier == other.identifier
+            && self.entries == other.entries
+            && self.kinds == other.kinds
     }
 }
 
+/// Serializes a wallet, category or item without the IDs of its records, as read
+/// output shows it and as wallets were saved before the IDs were kept in them.
+pub(crate) struct WithoutIds<'a, T>(pub(crate) &'a T);
+
 impl Serialize for Item {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
+        self.serialize_map(serializer, true)
+    }
+}
+
+impl Serialize for WithoutIds<'_, Item> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_map(serializer, false)
+    }
+}
+
+impl Item {
+    fn serialize_map<S>(&self, serializer: S, with_id: bool) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.entries.contains_key(ID_KEY) {
+            return Err(ser::Error::custom(format!(
+                "{} cannot name an entry, it is where the item's ID is saved",
+                ID_KEY
+            )));
+        }
         let mut map: <S as Serializer>::SerializeMap =
-            serializer.serialize_map(Some(self.entries.len()))?;
+            serializer.serialize_map(Some(self.entries.len() + usize::from(with_id)))?;
+        if with_id {
+            map.serialize_entry(ID_KEY, &self.id.hyphenated())?;
+        }
         for (key, value) in &self.entries {
-            map.serialize_entry(&key, &value)?;
+            match self.kinds.get(key) {
+                Some(kind) => map.serialize_entry(key, &TypedEntry { kind: *kind, value })?,
+                None => map.serialize_entry(key, value)?,
+            }
         }
         map.end()
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TypedEntry<V> {
+    #[serde(rename = "type")]
+    kind: EntryKind,
+    value: V,
+}
+
+/// An entry as it is saved: a plain string or a typed entry.
+enum StoredEntry {
+    Plain(String),
+    Typed(TypedEntry<Text>),
+}
+
+/// An entry's value, which must be a string. YAML and TOML read unquoted values
+/// such as `pin: 1234` as numbers, and `1.50` or `0x1F` would not be kept as written.
+struct Text(String);
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TextVisitor;
+
+        impl de::Visitor<'_> for TextVisitor {
+            type Value = Text;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Text, E> {
+                Ok(Text(String::from(value)))
+            }
+
+            fn visit_string<E: de::Error>(self, value: String) -> Result<Text, E> {
+                Ok(Text(value))
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<Text, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Text, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Text, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Text, E> {
+                Err(unquoted(value))
+            }
+        }
+
+        deserializer.deserialize_any(TextVisitor)
+    }
+}
+
+fn unquoted<E: de::Error>(value: impl fmt::Display) -> E {
+    E::custom(format!(
+        "the value {} must be quoted, as in \"{}\", to be kept exactly as written",
+        value, value
+    ))
+}
+
+impl<'de> Deserialize<'de> for StoredEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct StoredEntryVisitor;
+
+        impl<'de> de::Visitor<'de> for StoredEntryVisitor {
+            type Value = StoredEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string or a typed entry")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<StoredEntry, E> {
+                Ok(StoredEntry::Plain(String::from(value)))
+            }
+
+            fn visit_string<E: de::Error>(self, value: String) -> Result<StoredEntry, E> {
+                Ok(StoredEntry::Plain(value))
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<StoredEntry, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<StoredEntry, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<StoredEntry, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<StoredEntry, E> {
+                Err(unquoted(value))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<StoredEntry, A::Error> {
+                TypedEntry::deserialize(MapAccessDeserializer::new(map)).map(StoredEntry::Typed)
+            }
+        }
+
+        deserializer.deserialize_any(StoredEntryVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ItemVisitor;
+
+        impl<'de> de::Visitor<'de> for ItemVisitor {
+            type Value = Item;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of entries")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Item, A::Error> {
+                let mut item: Item = Item::new(String::new());
+                item.new_id = true;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == ID_KEY {
+                        item.set_id(next_id(&mut map)?);
+                        continue;
+                    }
+                    //Named, since the error alone does not say which entry it is in.
+                    let entry: StoredEntry = map
+                        .next_value()
+                        .map_err(|error| de::Error::custom(format!("{}: {}", key, error)))?;
+                    match entry {
+                        StoredEntry::Plain(value) => item.entries.insert(key, value),
+                        StoredEntry::Typed(TypedEntry {
+                            kind,
+                            value: Text(value),
+                        }) => {
+                            item.kinds.insert(key.clone(), kind);
+                            item.entries.insert(key, value)
+                        }
+                    };
+                }
+                Ok(item)
+            }
+        }
+
+        deserializer.deserialize_map(ItemVisitor)
+    }
+}
+
+/// Reads the value of an `@id` key.
+pub(crate) fn next_id<'de, A: MapAccess<'de>>(map: &mut A) -> Result<Uuid, A::Error> {
+    let invalid = |error: &dyn fmt::Display| de::Error::custom(format!("{}: {}", ID_KEY, error));
+    let id: String = map.next_value().map_err(|error| invalid(&error))?;
+    Uuid::parse_str(&id).map_err(|error| invalid(&error))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,7 +513,7 @@ mod tests {
         assert!(item.add_entry(&first_key, &first_val));
         assert_eq!(item.size(), 1);
         assert!(!item.empty());
-        assert_eq!(item.get_entry(&first_key).unwrap(), &first_val);
+        assert_eq!(item.get(&first_key).unwrap(), &first_val);
 
         //The add another entry with the same key.
         assert!(!item.add_entry(&first_key, &first_val));
@@ -114,8 +526,8 @@ mod tests {
 
         assert!(item.add_entry(&second_key, &second_val));
         assert_eq!(item.size(), 2);
-        assert!(item.empty());
-        assert_eq!(item.get_entry(&second_key).unwrap(), &second_val);
+        assert!(!item.empty());
+        assert_eq!(item.get(&second_key).unwrap(), &second_val);
     }
 
     #[test]
@@ -129,12 +541,12 @@ mod tests {
         assert!(item.add_entry(&first_key, &first_val));
         assert_eq!(item.size(), 1);
         assert!(!item.empty());
-        assert_eq!(item.get_entry(&first_key).unwrap(), &first_val);
+        assert_eq!(item.get(&first_key).unwrap(), &first_val);
 
         //Delete non-existent entry and validate nothing changed.
         let username: String = String::from("username");
         assert!(!item.delete_entry(&username));
-        assert_eq!(item.get_entry(&first_key).unwrap(), &first_val);
+        assert_eq!(item.get(&first_key).unwrap(), &first_val);
         assert_eq!(item.size(), 1);
 
         //Now delete the real entry and validate it is gone.
@@ -0,0 +1,73 @@
+//! The wallet model behind 371pass: a [`Wallet`] of [`Category`]s, each holding
+//! [`Item`]s of string entries.
+//!
+//! ```
+//! use csc371_remake::Wallet;
+//!
+//! let mut wallet: Wallet = Wallet::builder()
+//!     .entry("Websites", "Google", "password", "pass1234")
+//!     .entry("Websites", "Google", "url", "https://www.google.com/")
+//!     .build();
+//!
+//! wallet.save("mem://example")?;
+//! let mut loaded: Wallet = Wallet::new();
+//! loaded.load("mem://example")?;
+//! assert_eq!(loaded, wallet);
+//! # Ok::<(), csc371_remake::Error>(())
+//! ```
+//!
+//! Wallets are `Send + Sync`, so read-heavy servers can share one behind an
+//! `RwLock` and look entries up with `get` from many threads at once.
+//!
+//! ```
+//! use csc371_remake::Wallet;
+//! use std::{sync::RwLock, thread};
+//!
+//! let wallet: RwLock<Wallet> = RwLock::new(
+//!     Wallet::builder()
+//!         .entry("Websites", "Google", "password", "pass1234")
+//!         .build(),
+//! );
+//! thread::scope(|scope| {
+//!     for _ in 0..4 {
+//!         scope.spawn(|| {
+//!             let wallet = wallet.read().unwrap();
+//!             let google = wallet.get("Websites").and_then(|category| category.get("Google"));
+//!             assert_eq!(google.and_then(|item| item.get("password")), Some("pass1234"));
+//!         });
+//!     }
+//! });
+//! wallet
+//!     .write()
+//!     .unwrap()
+//!     .new_category("Bank Accounts");
+//! ```
+
+//The binary compiles these modules too, for the command line, and uses parts of
+//them the library does not.
+#![allow(dead_code)]
+
+mod category;
+mod encoding;
+mod entry;
+mod error;
+mod finance;
+mod format;
+mod integrity;
+mod item;
+mod merge;
+mod reference;
+mod store;
+mod template;
+mod visitor;
+mod wallet;
+
+pub use crate::{
+    category::Category,
+    entry::EntryKind,
+    error::{Error, Result},
+    item::Item,
+    template::{Field, Generator, Problem, Template, Violation},
+    visitor::Visitor,
+    wallet::{Wallet, WalletBuilder},
+};
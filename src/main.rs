@@ -1,6 +1,29 @@
-pub mod _371pass;
+//The command line needs the model's crate-private parts, such as the stores and
+//the integrity check, so it compiles the model itself rather than using the
+//library. Each crate uses only part of the modules they share.
+#![allow(dead_code)]
+
+mod _371pass;
+mod audit;
 mod category;
+mod diff;
+mod encoding;
+mod entry;
+mod error;
+mod finance;
+mod format;
+mod integrity;
 mod item;
+mod merge;
+mod patch;
+mod reference;
+mod shamir;
+mod share;
+mod store;
+mod sync;
+mod template;
+mod transfer;
+mod visitor;
 mod wallet;
 
 use crate::_371pass::app;
@@ -0,0 +1,313 @@
+use crate::{entry::EntryKind, wallet::Wallet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// A category path, an item within that category, or an entry within that item.
+pub(crate) type Key = (String, Option<String>, Option<String>);
+
+/// The kinds of the typed entries in a wallet.
+pub(crate) type Kinds = BTreeMap<Key, EntryKind>;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) enum ConflictKind {
+    /// Both sides changed an entry to different values.
+    BothChanged,
+    /// Both sides added an entry with different values.
+    BothAdded,
+    /// One side deleted what the other side changed.
+    DeletedAndChanged,
+}
+
+/// A change that could not be merged automatically. Both values are kept in the result.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct Conflict {
+    pub(crate) kind: ConflictKind,
+    pub(crate) path: String,
+    /// The key their value was kept under, when both sides changed an entry.
+    pub(crate) kept_as: Option<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sides: &str = match self.kind {
+            ConflictKind::BothChanged => "changed on both sides",
+            ConflictKind::BothAdded => "added on both sides",
+            ConflictKind::DeletedAndChanged => {
+                return write!(
+                    f,
+                    "CONFLICT {}: deleted on one side and changed on the other, kept the change",
+                    self.path
+                );
+            }
+        };
+        write!(
+            f,
+            "CONFLICT {}: {}, kept ours and theirs as \"{}\"",
+            self.path,
+            sides,
+            self.kept_as.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// Merges the changes made in `ours` and `theirs` since `base`.
+///
+/// Walks the wallets as `Wallet::merge_categories`, `Category::merge_items` and
+/// `Item::merge_entries` do, but against `base`: anything changed on only one
+/// side takes that side's version, and deletions on one side win over an
+/// unchanged other side. Unlike `merge_entries`, which lets the incoming value
+/// overwrite, a conflicting entry keeps our value under its key and their value
+/// under a free `<key> (theirs)` name. Categories and items keep their IDs, ours
+/// where both sides have one.
+pub(crate) fn three_way(base: &Wallet, ours: &Wallet, theirs: &Wallet) -> (Wallet, Vec<Conflict>) {
+    let mut merged: Wallet = ours.clone();
+    let conflicts: Vec<Conflict> = merged.merge_categories_three_way(base, theirs);
+    (merged, conflicts)
+}
+
+/// Merges named categories or items into `ours`. Those added or deleted on one side
+/// only are added or deleted. Those deleted on one side and changed on the other
+/// are kept, changed, as a conflict. Those both sides have are merged by `merge`.
+pub(crate) fn merge_children<T: Clone + PartialEq>(
+    ours: &mut BTreeMap<String, T>,
+    base: Option<&BTreeMap<String, T>>,
+    theirs: &BTreeMap<String, T>,
+    path: impl Fn(&str) -> String,
+    merge: impl Fn(&mut T, Option<&T>, &T, &str) -> Vec<Conflict>,
+) -> Vec<Conflict> {
+    let names: BTreeSet<String> = base
+        .into_iter()
+        .flat_map(BTreeMap::keys)
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect();
+
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    for name in names {
+        let b: Option<&T> = base.and_then(|base| base.get(&name));
+        let t: Option<&T> = theirs.get(&name);
+        let deleted_and_changed = || Conflict {
+            kind: ConflictKind::DeletedAndChanged,
+            path: path(&name),
+            kept_as: None,
+        };
+
+        if let (Some(o), Some(t)) = (ours.get_mut(&name), t) {
+            conflicts.extend(merge(o, b, t, &path(&name)));
+            continue;
+        }
+        match (ours.get(&name), t, b) {
+            //Added on one side, or deleted on both.
+            (_, _, None) => {
+                if let Some(t) = t {
+                    ours.insert(name.clone(), t.clone());
+                }
+            }
+            //Deleted by them and unchanged by us, or the other way round.
+            (Some(o), None, Some(b)) if o == b => {
+                ours.remove(&name);
+            }
+            (None, Some(t), Some(b)) if t == b => {}
+            (Some(_), None, Some(_)) => conflicts.push(deleted_and_changed()),
+            (None, Some(t), Some(_)) => {
+                conflicts.push(deleted_and_changed());
+                ours.insert(name.clone(), t.clone());
+            }
+            _ => {}
+        }
+    }
+    conflicts
+}
+
+pub(crate) fn flatten(wallet: &Wallet) -> BTreeMap<Key, Option<String>> {
+    let mut keys: BTreeMap<Key, Option<String>> = BTreeMap::new();
+    for (cat_path, category) in wallet.all_categories() {
+        for item in category.items() {
+            keys.insert(
+                (cat_path.clone(), Some(item.get_ident().clone()), None),
+                None,
+            );
+        }
+        keys.insert((cat_path, None, None), None);
+    }
+    for (cat_path, item_ident, entry_ident, entry_val) in wallet.walk() {
+        keys.insert(
+            (
+                cat_path,
+                Some(String::from(item_ident)),
+                Some(String::from(entry_ident)),
+            ),
+            Some(String::from(entry_val)),
+        );
+    }
+    keys
+}
+
+/// The kinds of the typed entries in `wallet`, keyed as by `flatten`.
+pub(crate) fn kinds(wallet: &Wallet) -> Kinds {
+    let mut kinds: Kinds = BTreeMap::new();
+    for (cat_path, category) in wallet.all_categories() {
+        for item in category.items() {
+            for (entry_ident, _) in item.entries() {
+                if let Some(kind) = item.kind(entry_ident) {
+                    kinds.insert(
+                        (
+                            cat_path.clone(),
+                            Some(item.get_ident().clone()),
+                            Some(String::from(entry_ident)),
+                        ),
+                        kind,
+                    );
+                }
+            }
+        }
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+
+    fn wallet(json: &str) -> Wallet {
+        let mut wallet: Wallet = Wallet::new();
+        wallet.load_json(json).unwrap();
+        wallet
+    }
+
+    #[test]
+    fn test_non_conflicting_changes() {
+        let base: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+        //Ours changes the Google password and adds a category.
+        let ours: Wallet = wallet(
+            r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"newpass","url":"https://www.google.com/"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+        //Theirs deletes Twitter and adds a username to Google.
+        let theirs: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"}}}"#,
+        );
+
+        let (merged, conflicts) = three_way(&base, &ours, &theirs);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            wallet(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"newpass","url":"https://www.google.com/","username":"example@gmail.com"}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_conflicts_keep_both_values() {
+        let base: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"pass1234"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+        let ours: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"ours","pin":"1111"},"Twitter":{"password":"changed"}}}"#,
+        );
+        let theirs: Wallet =
+            wallet(r#"{"Websites":{"Google":{"password":"theirs","pin":"2222"}}}"#);
+
+        let (merged, conflicts) = three_way(&base, &ours, &theirs);
+        assert_eq!(
+            conflicts,
+            vec![
+                Conflict {
+                    kind: ConflictKind::BothChanged,
+                    path: String::from("Websites/Google/password"),
+                    kept_as: Some(String::from("password (theirs)")),
+                },
+                Conflict {
+                    kind: ConflictKind::BothAdded,
+                    path: String::from("Websites/Google/pin"),
+                    kept_as: Some(String::from("pin (theirs)")),
+                },
+                Conflict {
+                    kind: ConflictKind::DeletedAndChanged,
+                    path: String::from("Websites/Twitter"),
+                    kept_as: None,
+                },
+            ]
+        );
+        assert_eq!(
+            merged,
+            wallet(
+                r#"{"Websites":{"Google":{"password":"ours","password (theirs)":"theirs","pin":"1111","pin (theirs)":"2222"},"Twitter":{"password":"changed"}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_conflicts_never_overwrite() {
+        //Ours already has a "password (theirs)" entry of its own.
+        let base: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        let ours: Wallet =
+            wallet(r#"{"Websites":{"Google":{"password":"ours","password (theirs)":"kept"}}}"#);
+        let theirs: Wallet = wallet(r#"{"Websites":{"Google":{"password":"theirs"}}}"#);
+
+        let (merged, conflicts) = three_way(&base, &ours, &theirs);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kept_as.as_deref(), Some("password (theirs 2)"));
+        assert_eq!(
+            merged,
+            wallet(
+                r#"{"Websites":{"Google":{"password":"ours","password (theirs)":"kept","password (theirs 2)":"theirs"}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_deleted_item_changed_on_the_other_side() {
+        let base: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        let deleted: Wallet = wallet(r#"{"Websites":{}}"#);
+        let added_to: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"pass1234","username":"example@gmail.com"}}}"#,
+        );
+        let expected: Vec<Conflict> = vec![Conflict {
+            kind: ConflictKind::DeletedAndChanged,
+            path: String::from("Websites/Google"),
+            kept_as: None,
+        }];
+
+        //Whichever side deleted it, the item is kept with the change and reported.
+        let (merged, conflicts) = three_way(&base, &deleted, &added_to);
+        assert_eq!(conflicts, expected);
+        assert_eq!(merged, added_to);
+        let (merged, conflicts) = three_way(&base, &added_to, &deleted);
+        assert_eq!(conflicts, expected);
+        assert_eq!(merged, added_to);
+
+        //Deleting a whole category that the other side left alone is not a conflict.
+        let (merged, conflicts) = three_way(&base, &base, &wallet("{}"));
+        assert!(conflicts.is_empty());
+        assert!(merged.empty());
+    }
+
+    #[test]
+    fn test_templates_merge_without_filling_in_items() {
+        let base: Wallet = wallet(r#"{"Bank Accounts":{}}"#);
+        let ours: Wallet = wallet(r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}}}"#);
+        let mut theirs: Wallet = base.clone();
+        let template: Template =
+            Template::new().field(crate::template::Field::new("Name").default_value("Mr John Doe"));
+        theirs
+            .get_mut("Bank Accounts")
+            .unwrap()
+            .set_template(Some(template.clone()));
+
+        let (merged, conflicts) = three_way(&base, &ours, &theirs);
+        assert!(conflicts.is_empty());
+        let mut expected: Wallet = ours.clone();
+        expected
+            .get_mut("Bank Accounts")
+            .unwrap()
+            .set_template(Some(template));
+        assert_eq!(merged, expected);
+    }
+}
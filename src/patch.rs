@@ -0,0 +1,184 @@
+use crate::wallet::Wallet;
+use serde_json::Value;
+use std::io::{Error, ErrorKind};
+
+/// Applies an RFC 6902 JSON Patch to a copy of `wallet`.
+///
+/// Either every operation succeeds and the patched wallet is returned, or an
+/// error names the first operation that failed and `wallet` is left untouched.
+/// Categories and items keep their IDs wherever the patch moves them, and those
+/// it writes without one take the ID of whatever was at their path.
+pub(crate) fn apply_json_patch(wallet: &Wallet, patch: &Value) -> Result<Wallet, Error> {
+    let operations: &Vec<Value> = patch.as_array().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Error: a JSON Patch must be an array of operations.",
+        )
+    })?;
+
+    let mut document: Value = serde_json::to_value(wallet)?;
+    for (position, operation) in operations.iter().enumerate() {
+        apply_operation(&mut document, operation).map_err(|error| {
+            Error::new(
+                error.kind(),
+                format!("Error: operation {}: {}", position + 1, error),
+            )
+        })?;
+    }
+
+    let mut patched: Wallet = Wallet::new();
+    patched.load_json(&document.to_string()).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Error: the patched document is not a valid wallet.",
+        )
+    })?;
+    patched.fill_ids(&wallet.ids());
+    Ok(patched)
+}
+
+fn apply_operation(document: &mut Value, operation: &Value) -> Result<(), Error> {
+    let field = |name: &str| -> Result<&str, Error> {
+        operation
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(format!("missing \"{}\".", name)))
+    };
+    let value = || -> Result<Value, Error> {
+        operation
+            .get("value")
+            .cloned()
+            .ok_or_else(|| invalid(String::from("missing \"value\".")))
+    };
+
+    match field("op")? {
+        "add" => add(document, field("path")?, value()?),
+        "remove" => remove(document, field("path")?).map(|_| ()),
+        "replace" => {
+            let path: &str = field("path")?;
+            *document.pointer_mut(path).ok_or_else(|| not_found(path))? = value()?;
+            Ok(())
+        }
+        "move" => {
+            let moved: Value = remove(document, field("from")?)?;
+            add(document, field("path")?, moved)
+        }
+        "copy" => {
+            let from: &str = field("from")?;
+            let copied: Value = document
+                .pointer(from)
+                .cloned()
+                .ok_or_else(|| not_found(from))?;
+            add(document, field("path")?, copied)
+        }
+        "test" => {
+            let path: &str = field("path")?;
+            if document.pointer(path) != Some(&value()?) {
+                return Err(invalid(format!("test failed at {}.", path)));
+            }
+            Ok(())
+        }
+        op => Err(invalid(format!("unknown op \"{}\".", op))),
+    }
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), Error> {
+    let (parent, key) = split_pointer(path)?;
+    document
+        .pointer_mut(parent)
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| not_found(parent))?
+        .insert(key, value);
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, Error> {
+    let (parent, key) = split_pointer(path)?;
+    document
+        .pointer_mut(parent)
+        .and_then(Value::as_object_mut)
+        .and_then(|object| object.remove(&key))
+        .ok_or_else(|| not_found(path))
+}
+
+fn split_pointer(path: &str) -> Result<(&str, String), Error> {
+    let (parent, key) = path
+        .rsplit_once('/')
+        .ok_or_else(|| invalid(format!("invalid path \"{}\".", path)))?;
+    Ok((parent, key.replace("~1", "/").replace("~0", "~")))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn not_found(path: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("{} does not exist.", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+    use serde_json::json;
+
+    fn wallet(json: &str) -> Wallet {
+        let mut wallet: Wallet = Wallet::new();
+        wallet.load_json(json).unwrap();
+        wallet
+    }
+
+    #[test]
+    fn test_apply_json_patch() {
+        let original: Wallet = wallet(
+            r#"{"Websites":{"Google":{"password":"pass1234","username":"old@gmail.com"},"Twitter":{"username":"old@gmail.com"}}}"#,
+        );
+        let patch: Value = json!([
+            {"op": "test", "path": "/Websites/Google/username", "value": "old@gmail.com"},
+            {"op": "replace", "path": "/Websites/Google/username", "value": "new@gmail.com"},
+            {"op": "replace", "path": "/Websites/Twitter/username", "value": "new@gmail.com"},
+            {"op": "move", "from": "/Websites/Twitter", "path": "/Websites/X"},
+            {"op": "add", "path": "/Bank Accounts", "value": {"Starling": {"Sort Code": "12-34-56"}}},
+            {"op": "copy", "from": "/Websites/Google/password", "path": "/Websites/X/password"},
+            {"op": "remove", "path": "/Websites/Google/password"}
+        ]);
+
+        assert_eq!(
+            apply_json_patch(&original, &patch).unwrap(),
+            wallet(
+                r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"username":"new@gmail.com"},"X":{"password":"pass1234","username":"new@gmail.com"}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_failed_operation_rejects_patch() {
+        let original: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+
+        let missing: Value = json!([
+            {"op": "replace", "path": "/Websites/Google/password", "value": "newpass"},
+            {"op": "remove", "path": "/Websites/Facebook"}
+        ]);
+        let result = apply_json_patch(&original, &missing);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("operation 2"));
+
+        //Entries must stay strings.
+        let not_a_wallet: Value =
+            json!([{"op": "add", "path": "/Websites/Google/pin", "value": 1234}]);
+        assert!(apply_json_patch(&original, &not_a_wallet).is_err());
+    }
+
+    #[test]
+    fn test_round_trip_with_diff() {
+        let a: Wallet = wallet(
+            r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"}}}"#,
+        );
+        let b: Wallet = wallet(
+            r#"{"Banking":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"link":"https://www.google.com/","password":"newpass"},"X":{"password":"xpass"}}}"#,
+        );
+
+        let patch: Value = diff::to_json_patch(&diff::diff(&a, &b), true);
+        assert_eq!(apply_json_patch(&a, &patch).unwrap(), b);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{category, visitor::Visitor, wallet::Wallet};
+use std::io::{Error, ErrorKind};
+
+/// Starts a reference to another entry, `{ref:<entry path>}`, where the entry path
+/// is as `Wallet::entry_at` takes it, e.g. `{ref:Websites/Google/password}`.
+const REFERENCE_START: &str = "{ref:";
+
+/// Whether `value` refers to any other entry.
+pub(crate) fn contains_reference(value: &str) -> bool {
+    value.contains(REFERENCE_START)
+}
+
+/// The path of an entry as `Wallet::entry_at` takes it.
+pub(crate) fn entry_path(cat_path: &str, item_ident: &str, key: &str) -> String {
+    category::join_path(&category::join_path(cat_path, item_ident), key)
+}
+
+/// `value`, the value of the entry at `path`, with every reference in it replaced
+/// by the value of the entry it refers to, itself resolved. Fails if a reference
+/// points to no entry or leads back to one it is being resolved for.
+pub(crate) fn resolve(wallet: &Wallet, path: &str, value: &str) -> Result<String, Error> {
+    resolve_chain(wallet, value, &mut vec![String::from(path)])
+}
+
+/// Resolves the references in `value`, the value of the last entry in `chain`.
+/// The entries before it are the ones whose references led there.
+fn resolve_chain(wallet: &Wallet, value: &str, chain: &mut Vec<String>) -> Result<String, Error> {
+    let mut resolved: String = String::new();
+    let mut rest: &str = value;
+    while let Some((before, reference)) = rest.split_once(REFERENCE_START) {
+        //Without its closing brace it is just text.
+        let Some((target, after)) = reference.split_once('}') else {
+            break;
+        };
+        if chain.iter().any(|path| path == target) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Error: reference cycle {} -> {}.",
+                    chain.join(" -> "),
+                    target
+                ),
+            ));
+        }
+        let target_value: &str = wallet.entry_at(target).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Error: {} refers to {}, which does not exist.",
+                    chain[chain.len() - 1],
+                    target
+                ),
+            )
+        })?;
+
+        chain.push(String::from(target));
+        let target_value: String = resolve_chain(wallet, target_value, chain)?;
+        chain.pop();
+        resolved.push_str(before);
+        resolved.push_str(&target_value);
+        rest = after;
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// A copy of `wallet` with the references resolved in the entries `selected` picks
+/// by category path, item and key, so a broken reference elsewhere does not matter.
+pub(crate) fn resolve_selected(
+    wallet: &Wallet,
+    selected: impl Fn(&str, &str, &str) -> bool,
+) -> Result<Wallet, Error> {
+    let mut resolved: Wallet = wallet.clone();
+    let mut resolver: Resolver<_> = Resolver {
+        wallet,
+        selected,
+        error: None,
+    };
+    resolved.accept(&mut resolver);
+    match resolver.error {
+        Some(error) => Err(error),
+        None => Ok(resolved),
+    }
+}
+
+/// The paths of the entries whose references resolved in `before` but no longer
+/// do in `after`, because what they refer to was deleted or moved.
+pub(crate) fn broken_by(before: &Wallet, after: &Wallet) -> Vec<String> {
+    after
+        .walk()
+        .filter(|(.., value)| contains_reference(value))
+        .map(|(cat_path, item, key, value)| (entry_path(&cat_path, item, key), value))
+        .filter(|(path, value)| {
+            resolve(after, path, value).is_err() && resolve(before, path, value).is_ok()
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Fails, naming the entries, if `after` breaks references that resolved in `before`.
+pub(crate) fn check_not_broken(before: &Wallet, after: &Wallet) -> Result<(), Error> {
+    let broken: Vec<String> = broken_by(before, after);
+    if broken.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+            "Error: this would break the references in {}. Use --force to go ahead anyway.",
+            broken.join(", ")
+        ),
+    ))
+}
+
+/// Resolves the selected entries against the wallet as it was before any were,
+/// keeping the first error.
+struct Resolver<'a, F> {
+    wallet: &'a Wallet,
+    selected: F,
+    error: Option<Error>,
+}
+
+impl<F: Fn(&str, &str, &str) -> bool> Visitor for Resolver<'_, F> {
+    fn visit_entry(&mut self, category: &str, item: &str, key: &str, value: &mut String) {
+        if self.error.is_some()
+            || !contains_reference(value)
+            || !(self.selected)(category, item, key)
+        {
+            return;
+        }
+        match resolve(self.wallet, &entry_path(category, item, key), value) {
+            Ok(resolved) => *value = resolved,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet() -> Wallet {
+        Wallet::builder()
+            .entry("Websites", "Google", "password", "pass1234")
+            .entry(
+                "Dashboards",
+                "Grafana",
+                "password",
+                "{ref:Websites/Google/password}",
+            )
+            .entry(
+                "Dashboards",
+                "Kibana",
+                "login",
+                "admin:{ref:Dashboards/Grafana/password}",
+            )
+            .entry("Dashboards", "Kibana", "note", "{ref:unclosed")
+            .build()
+    }
+
+    #[test]
+    fn test_references_resolve() {
+        let mut w: Wallet = wallet();
+        let resolved: Wallet = resolve_selected(&w, |_, _, _| true).unwrap();
+        assert_eq!(
+            resolved.entry_at("Dashboards/Grafana/password"),
+            Some("pass1234")
+        );
+        assert_eq!(
+            resolved.entry_at("Dashboards/Kibana/login"),
+            Some("admin:pass1234")
+        );
+        assert_eq!(
+            resolved.entry_at("Dashboards/Kibana/note"),
+            Some("{ref:unclosed")
+        );
+
+        //Changing the target changes what every reference to it resolves to.
+        w.get_mut("Websites")
+            .unwrap()
+            .get_mut("Google")
+            .unwrap()
+            .add_entry("password", "newpass");
+        let resolved: Wallet = resolve_selected(&w, |_, _, _| true).unwrap();
+        assert_eq!(
+            resolved.entry_at("Dashboards/Kibana/login"),
+            Some("admin:newpass")
+        );
+
+        //A reference by ID survives the target being renamed.
+        let google: String = w
+            .get("Websites")
+            .unwrap()
+            .get("Google")
+            .unwrap()
+            .id()
+            .to_string();
+        let value: String = format!("{{ref:@{}/password}}", &google[..8]);
+        assert!(w
+            .get_mut("Websites")
+            .unwrap()
+            .rename_item("Google", "Gmail"));
+        assert_eq!(
+            resolve(&w, "Dashboards/Grafana/password", &value).unwrap(),
+            "newpass"
+        );
+    }
+
+    #[test]
+    fn test_broken_references() {
+        let mut w: Wallet = wallet();
+        w.new_category("Websites")
+            .new_item("Google")
+            .add_entry("password", "{ref:Dashboards/Kibana/login}");
+        let error: Error = resolve_selected(&w, |_, _, _| true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("reference cycle"));
+
+        let error: Error =
+            resolve(&w, "Websites/Google/url", "{ref:Websites/Google/url}").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error: Error =
+            resolve(&w, "Websites/Google/url", "{ref:Websites/Twitter/password}").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        //Entries that are not selected are left alone, broken or not.
+        let resolved: Wallet = resolve_selected(&w, |_, _, key| key == "note").unwrap();
+        assert_eq!(
+            resolved.entry_at("Dashboards/Grafana/password"),
+            Some("{ref:Websites/Google/password}")
+        );
+    }
+
+    #[test]
+    fn test_broken_by() {
+        let before: Wallet = wallet();
+        let mut after: Wallet = before.clone();
+        assert!(broken_by(&before, &after).is_empty());
+
+        assert!(after.delete_category("Websites"));
+        assert_eq!(
+            broken_by(&before, &after),
+            vec![
+                String::from("Dashboards/Grafana/password"),
+                String::from("Dashboards/Kibana/login")
+            ]
+        );
+
+        //Deleting the referring entries along with the target breaks nothing.
+        assert!(after.delete_category("Dashboards"));
+        assert!(broken_by(&before, &after).is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::encoding::{from_hex, to_hex};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+const SHARE_PREFIX: &str = "371pass";
+
+/// Bytes of the random split ID carried by every share, and of the hash of the
+/// secret that is split along with it.
+const DIGEST_LEN: usize = 8;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct Share {
+    threshold: u8,
+    index: u8,
+    /// Random ID chosen per split, so shares of different splits never match.
+    split_id: Vec<u8>,
+    /// This share of the secret followed by its hash. The hash is split like the
+    /// secret, so fewer than `threshold` shares tell nothing about either.
+    payload: Vec<u8>,
+}
+
+impl Share {
+    fn body(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}",
+            SHARE_PREFIX,
+            self.threshold,
+            self.index,
+            to_hex(&self.split_id),
+            to_hex(&self.payload)
+        )
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let body: String = self.body();
+        write!(f, "{}-{}", body, checksum(&body))
+    }
+}
+
+impl FromStr for Share {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        let s: &str = s.trim();
+
+        let (body, check) = s
+            .rsplit_once('-')
+            .ok_or_else(|| invalid("Error: malformed share."))?;
+        if checksum(body) != check.to_lowercase() {
+            return Err(invalid("Error: share checksum mismatch, check for typos."));
+        }
+
+        let parts: Vec<&str> = body.split('-').collect::<Vec<&str>>();
+        if parts.len() != 5 || parts[0] != SHARE_PREFIX {
+            return Err(invalid("Error: malformed share."));
+        }
+
+        let threshold: u8 = parts[1]
+            .parse()
+            .map_err(|_| invalid("Error: malformed share threshold."))?;
+        let index: u8 = parts[2]
+            .parse()
+            .map_err(|_| invalid("Error: malformed share index."))?;
+        let split_id: Vec<u8> =
+            from_hex(parts[3]).ok_or_else(|| invalid("Error: malformed share ID."))?;
+        let payload: Vec<u8> =
+            from_hex(parts[4]).ok_or_else(|| invalid("Error: malformed share payload."))?;
+
+        if threshold == 0
+            || index == 0
+            || split_id.len() != DIGEST_LEN
+            || payload.len() <= DIGEST_LEN
+        {
+            return Err(invalid("Error: malformed share."));
+        }
+
+        Ok(Share {
+            threshold,
+            index,
+            split_id,
+            payload,
+        })
+    }
+}
+
+/// Splits `secret` into `count` shares, any `threshold` of which recombine to the secret.
+pub(crate) fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>, Error> {
+    if secret.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Error: cannot split an empty secret.",
+        ));
+    }
+    if threshold == 0 || count < threshold {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Error: threshold must be between 1 and the number of shares.",
+        ));
+    }
+
+    let mut split_id: Vec<u8> = vec![0; DIGEST_LEN];
+    rand::fill(&mut split_id[..]);
+    let mut checked: Vec<u8> = secret.to_vec();
+    checked.extend(secret_digest(secret));
+
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share {
+            threshold,
+            index,
+            split_id: split_id.clone(),
+            payload: Vec::with_capacity(checked.len()),
+        })
+        .collect();
+
+    let mut coefficients: Vec<u8> = vec![0; threshold as usize];
+    for byte in &checked {
+        coefficients[0] = *byte;
+        rand::fill(&mut coefficients[1..]);
+
+        for share in shares.iter_mut() {
+            let y: u8 = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, coefficient| gf_mul(acc, share.index) ^ coefficient);
+            share.payload.push(y);
+        }
+    }
+    Ok(shares)
+}
+
+/// Recombines shares produced by `split` back into the original secret. Fails if
+/// the result does not match the hash of the secret the shares were split from.
+pub(crate) fn combine(shares: &[Share]) -> Result<Vec<u8>, Error> {
+    let first: &Share = shares
+        .first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Error: no shares provided."))?;
+
+    for (position, share) in shares.iter().enumerate() {
+        if share.threshold != first.threshold
+            || share.split_id != first.split_id
+            || share.payload.len() != first.payload.len()
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: shares do not belong to the same secret.",
+            ));
+        }
+        if shares[..position].iter().any(|s| s.index == share.index) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Error: the same share was provided more than once.",
+            ));
+        }
+    }
+
+    if shares.len() < first.threshold as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Error: {} shares are required but only {} were provided.",
+                first.threshold,
+                shares.len()
+            ),
+        ));
+    }
+
+    let shares: &[Share] = &shares[..first.threshold as usize];
+    let mut checked: Vec<u8> = vec![0; first.payload.len()];
+
+    for share in shares {
+        let mut basis: u8 = 1;
+        for other in shares.iter().filter(|s| s.index != share.index) {
+            basis = gf_mul(basis, gf_div(other.index, other.index ^ share.index));
+        }
+        for (byte, y) in checked.iter_mut().zip(share.payload.iter()) {
+            *byte ^= gf_mul(*y, basis);
+        }
+    }
+
+    let (secret, digest) = checked.split_at(checked.len() - DIGEST_LEN);
+    if secret_digest(secret) != digest {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Error: the shares do not recombine to the original secret.",
+        ));
+    }
+    Ok(secret.to_vec())
+}
+
+fn secret_digest(secret: &[u8]) -> Vec<u8> {
+    Sha256::digest(secret)[..DIGEST_LEN].to_vec()
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product: u8 = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry: bool = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 is the multiplicative inverse of b in GF(2^8).
+    let mut inverse: u8 = 1;
+    for _ in 0..254 {
+        inverse = gf_mul(inverse, b);
+    }
+    gf_mul(a, inverse)
+}
+
+fn checksum(body: &str) -> String {
+    to_hex(&Sha256::digest(body.as_bytes())[..4])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_combine() {
+        let secret: &[u8] = b"correct horse battery staple";
+        let shares: Vec<Share> = split(secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        //Any three shares should recover the secret.
+        for a in 0..5 {
+            for b in (a + 1)..5 {
+                for c in (b + 1)..5 {
+                    let subset: Vec<Share> =
+                        vec![shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                    assert_eq!(combine(&subset).unwrap(), secret);
+                }
+            }
+        }
+
+        //Two shares are not enough.
+        assert!(combine(&shares[..2]).is_err());
+
+        //The same share twice does not count twice.
+        let duplicated: Vec<Share> = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine(&duplicated).is_err());
+    }
+
+    #[test]
+    fn test_combine_checks_the_secret() {
+        let first: Vec<Share> = split(b"correct horse battery staple", 2, 3).unwrap();
+        let second: Vec<Share> = split(b"correct horse battery stable", 2, 3).unwrap();
+
+        //Shares of different splits are refused.
+        let mixed: Vec<Share> = vec![first[0].clone(), second[1].clone()];
+        assert!(combine(&mixed).is_err());
+
+        //Even with the split's ID copied over, the result is checked.
+        let mut forged: Share = second[1].clone();
+        forged.split_id = first[1].split_id.clone();
+        let error: Error = combine(&[first[0].clone(), forged]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_invalid_split_arguments() {
+        assert!(split(b"", 2, 3).is_err());
+        assert!(split(b"secret", 0, 3).is_err());
+        assert!(split(b"secret", 4, 3).is_err());
+    }
+
+    #[test]
+    fn test_share_text_round_trip() {
+        let shares: Vec<Share> = split(b"recovery", 2, 3).unwrap();
+        for share in &shares {
+            let text: String = share.to_string();
+            assert!(text.starts_with("371pass-2-"));
+            //The hash of the secret is only found by recombining.
+            assert!(!text.contains(&to_hex(&secret_digest(b"recovery"))));
+            assert_eq!(&text.parse::<Share>().unwrap(), share);
+        }
+    }
+
+    #[test]
+    fn test_share_checksum_catches_typos() {
+        let share: Share = split(b"recovery", 2, 3).unwrap().remove(0);
+        let text: String = share.to_string();
+
+        //Change one character of the payload.
+        let position: usize = text.len() - 12;
+        let replacement: char = if &text[position..position + 1] == "0" {
+            '1'
+        } else {
+            '0'
+        };
+        let mut typo: String = text.clone();
+        typo.replace_range(position..position + 1, &replacement.to_string());
+
+        let result = typo.parse::<Share>();
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
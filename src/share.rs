@@ -0,0 +1,216 @@
+use crate::encoding::{from_hex, to_hex};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Write},
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const PUBLIC_KEY_PREFIX: &str = "371pass-pub-";
+const SECRET_KEY_PREFIX: &str = "371pass-secret-";
+const BUNDLE_PREFIX: &str = "371pass-bundle-v1-";
+
+/// An X25519 keypair used to receive shared items.
+pub(crate) struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub(crate) fn generate() -> Identity {
+        Identity {
+            secret: StaticSecret::from(random_bytes::<32>()),
+        }
+    }
+
+    pub(crate) fn public_key(&self) -> String {
+        format!(
+            "{}{}",
+            PUBLIC_KEY_PREFIX,
+            to_hex(PublicKey::from(&self.secret).as_bytes())
+        )
+    }
+
+    pub(crate) fn load(filename: &str) -> Result<Identity, Error> {
+        let contents: String = fs::read_to_string(filename)?;
+        let bytes: [u8; 32] = contents
+            .trim()
+            .strip_prefix(SECRET_KEY_PREFIX)
+            .and_then(parse_key_bytes)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Error: identity file does not contain a secret key.",
+                )
+            })?;
+        Ok(Identity {
+            secret: StaticSecret::from(bytes),
+        })
+    }
+
+    /// Writes the secret key to `filename`, refusing to replace an existing identity.
+    pub(crate) fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut options: fs::OpenOptions = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file: fs::File = options.open(filename)?;
+        writeln!(
+            file,
+            "{}{}",
+            SECRET_KEY_PREFIX,
+            to_hex(self.secret.as_bytes())
+        )
+    }
+}
+
+/// Encrypts `plaintext` so that only the holder of `recipient`'s identity can read it.
+pub(crate) fn seal(plaintext: &[u8], recipient: &str) -> Result<String, Error> {
+    let recipient: PublicKey = parse_public_key(recipient)?;
+    let ephemeral: StaticSecret = StaticSecret::from(random_bytes::<32>());
+    let ephemeral_public: PublicKey = PublicKey::from(&ephemeral);
+
+    let shared_secret = ephemeral.diffie_hellman(&recipient);
+    if !shared_secret.was_contributory() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Error: invalid recipient public key.",
+        ));
+    }
+
+    let key: Key = derive_key(shared_secret.as_bytes(), &ephemeral_public, &recipient);
+    let nonce: [u8; 12] = random_bytes::<12>();
+    let ciphertext: Vec<u8> = ChaCha20Poly1305::new(&key)
+        .encrypt(&Nonce::from(nonce), plaintext)
+        .map_err(|_| Error::other("Error: unable to encrypt bundle."))?;
+
+    Ok(format!(
+        "{}{}-{}-{}",
+        BUNDLE_PREFIX,
+        to_hex(ephemeral_public.as_bytes()),
+        to_hex(&nonce),
+        to_hex(&ciphertext)
+    ))
+}
+
+/// Decrypts a bundle produced by `seal` with the recipient's identity.
+pub(crate) fn open(bundle: &str, identity: &Identity) -> Result<Vec<u8>, Error> {
+    let malformed = || Error::new(ErrorKind::InvalidData, "Error: malformed share bundle.");
+
+    let parts: Vec<&str> = bundle
+        .trim()
+        .strip_prefix(BUNDLE_PREFIX)
+        .ok_or_else(malformed)?
+        .split('-')
+        .collect::<Vec<&str>>();
+    if parts.len() != 3 {
+        return Err(malformed());
+    }
+
+    let ephemeral_public: PublicKey =
+        PublicKey::from(parse_key_bytes(parts[0]).ok_or_else(malformed)?);
+    let nonce: [u8; 12] = from_hex(parts[1])
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(malformed)?;
+    let ciphertext: Vec<u8> = from_hex(parts[2]).ok_or_else(malformed)?;
+
+    let recipient: PublicKey = PublicKey::from(&identity.secret);
+    let shared_secret = identity.secret.diffie_hellman(&ephemeral_public);
+    let key: Key = derive_key(shared_secret.as_bytes(), &ephemeral_public, &recipient);
+
+    ChaCha20Poly1305::new(&key)
+        .decrypt(&Nonce::from(nonce), ciphertext.as_slice())
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Error: unable to decrypt bundle, it was not shared with this identity or has been modified.",
+            )
+        })
+}
+
+fn derive_key(shared_secret: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(BUNDLE_PREFIX.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(ephemeral.as_bytes());
+    hasher.update(recipient.as_bytes());
+    let digest: [u8; 32] = hasher.finalize().into();
+    Key::from(digest)
+}
+
+fn parse_public_key(text: &str) -> Result<PublicKey, Error> {
+    text.trim()
+        .strip_prefix(PUBLIC_KEY_PREFIX)
+        .and_then(parse_key_bytes)
+        .map(PublicKey::from)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Error: invalid recipient public key.",
+            )
+        })
+}
+
+fn parse_key_bytes(hex: &str) -> Option<[u8; 32]> {
+    from_hex(hex)?.try_into().ok()
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes: [u8; N] = [0; N];
+    rand::fill(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_seal_and_open() {
+        let identity: Identity = Identity::generate();
+        let plaintext: &[u8] = br#"{"Websites":{"Google":{"password":"pass1234"}}}"#;
+
+        let bundle: String = seal(plaintext, &identity.public_key()).unwrap();
+        assert!(bundle.starts_with(BUNDLE_PREFIX));
+        assert!(!bundle.contains("pass1234"));
+        assert_eq!(open(&bundle, &identity).unwrap(), plaintext);
+
+        //Someone else's identity cannot open the bundle.
+        let other: Identity = Identity::generate();
+        assert!(open(&bundle, &other).is_err());
+
+        //Flipping a ciphertext character is detected.
+        let mut tampered: String = bundle.clone();
+        let last: char = tampered.pop().unwrap();
+        tampered.push(if last == '0' { '1' } else { '0' });
+        assert!(open(&tampered, &identity).is_err());
+    }
+
+    #[test]
+    fn test_invalid_public_key() {
+        assert!(seal(b"data", "not-a-key").is_err());
+        assert!(seal(b"data", "371pass-pub-abcd").is_err());
+    }
+
+    #[test]
+    fn test_identity_save_and_load() {
+        let path = env::temp_dir().join(format!("371pass-identity-{}", std::process::id()));
+        let filename: String = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+
+        let identity: Identity = Identity::generate();
+        assert!(identity.save(&filename).is_ok());
+
+        //An existing identity is never overwritten.
+        assert!(Identity::generate().save(&filename).is_err());
+
+        let loaded: Identity = Identity::load(&filename).unwrap();
+        assert_eq!(loaded.public_key(), identity.public_key());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1821 @@
+use crate::{
+    category::{self, Category},
+    entry::EntryKind,
+    format::{self, FileFormat},
+    integrity::Integrity,
+    item::{Item, WithoutIds},
+    merge::{self, Key, Kinds},
+    template::Template,
+    wallet::Wallet,
+};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{Error, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const SQLITE_EXTENSIONS: [&str; 3] = ["sqlite", "sqlite3", "db"];
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS categories (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        template TEXT,
+        uuid TEXT
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        id INTEGER PRIMARY KEY,
+        category_id INTEGER NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        uuid TEXT,
+        UNIQUE (category_id, name)
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        item_id INTEGER NOT NULL REFERENCES items (id) ON DELETE CASCADE,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        kind TEXT,
+        PRIMARY KEY (item_id, key)
+    );
+";
+
+/// Where a wallet is loaded from and saved to.
+pub(crate) trait WalletStore {
+    fn load(&mut self, wallet: &mut Wallet) -> Result<(), Error>;
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Error>;
+
+    /// Loads the category at `cat_path` with only its item `item_ident`, for actions
+    /// on that one item. Stores that keep the wallet whole load all of it.
+    fn load_item(
+        &mut self,
+        wallet: &mut Wallet,
+        _cat_path: &str,
+        _item_ident: &str,
+    ) -> Result<(), Error> {
+        self.load(wallet)
+    }
+
+    /// Saves the item loaded by `load_item`, or deletes it if the wallet no longer
+    /// has it. Stores that keep the wallet whole save all of it.
+    fn save_item(
+        &mut self,
+        wallet: &Wallet,
+        _cat_path: &str,
+        _item_ident: &str,
+    ) -> Result<(), Error> {
+        self.save(wallet)
+    }
+
+    /// Keeps other 371pass processes from using the wallet until the lock is dropped.
+    fn lock(&mut self) -> Result<Lock, Error>;
+}
+
+/// Where `--database` points, given either as a plain path or as a URI.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) enum Location<'a> {
+    /// A plain path, stored as SQLite if it looks like a SQLite database and as a single file otherwise.
+    Path(&'a str),
+    /// `file://path`, always a single JSON, YAML or TOML file.
+    File(&'a str),
+    /// `sqlite://path`
+    Sqlite(&'a str),
+    /// `dir://path`, a directory holding one JSON file per item.
+    Directory(&'a str),
+    /// `mem://name`, kept in memory for as long as the process runs.
+    Memory(&'a str),
+}
+
+impl Location<'_> {
+    pub(crate) fn parse(database: &str) -> Result<Location<'_>, Error> {
+        match database.split_once("://") {
+            None => Ok(Location::Path(database)),
+            Some(("file", path)) => Ok(Location::File(path)),
+            Some(("sqlite", path)) => Ok(Location::Sqlite(path)),
+            Some(("dir", path)) => Ok(Location::Directory(path)),
+            Some(("mem", name)) => Ok(Location::Memory(name)),
+            Some((scheme, _)) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Error: unsupported database scheme \"{}://\".", scheme),
+            )),
+        }
+    }
+
+    /// The path on disk, next to which the lock, integrity tag and audit log are kept.
+    pub(crate) fn local_path(&self) -> Option<&str> {
+        match self {
+            Location::Path(path)
+            | Location::File(path)
+            | Location::Sqlite(path)
+            | Location::Directory(path) => Some(path),
+            Location::Memory(_) => None,
+        }
+    }
+}
+
+/// Opens the store `database` points to. SQLite databases are only created when `create` is set.
+///
+/// Single files are read and written in `format`, in the format their magic bytes
+/// name, or in the format their extension names.
+pub(crate) fn open(
+    database: &str,
+    create: bool,
+    format: Option<FileFormat>,
+) -> Result<Box<dyn WalletStore>, Error> {
+    match Location::parse(database)? {
+        Location::Path(path) if format.is_none() && is_sqlite(path) => {
+            Ok(Box::new(SqliteStore::open(path, create)?))
+        }
+        Location::Path(path) | Location::File(path) => Ok(Box::new(FileStore::new(
+            path,
+            format
+                .or_else(|| FileFormat::detect(path))
+                .unwrap_or_else(|| FileFormat::from_extension(path)),
+        ))),
+        Location::Sqlite(path) => Ok(Box::new(SqliteStore::open(path, create)?)),
+        Location::Directory(path) => Ok(Box::new(DirectoryStore::new(path))),
+        Location::Memory(name) => Ok(Box::new(MemoryStore::new(name))),
+    }
+}
+
+fn is_sqlite(filename: &str) -> bool {
+    let path: &Path = Path::new(filename);
+    let has_extension: bool = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| SQLITE_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+
+    let mut header: [u8; 16] = [0; 16];
+    let has_header: bool = fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && header == SQLITE_MAGIC;
+
+    has_extension || has_header
+}
+
+/// Held while a process is using a wallet. Dropping it releases the wallet.
+#[derive(Debug)]
+pub(crate) enum Lock {
+    /// `<path>.lock`, open and locked with the OS's advisory lock.
+    File(PathBuf, fs::File),
+    Memory(String),
+}
+
+impl Lock {
+    /// Takes the OS lock on `<path>.lock` and writes our PID to it, and fails if
+    /// another process holds it. The OS releases the lock of a process that exits,
+    /// so a lock file left behind by one is simply taken.
+    fn file(path: &str) -> Result<Lock, Error> {
+        let lock_filename: PathBuf = PathBuf::from(format!("{}.lock", path));
+        loop {
+            let mut file: fs::File = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_filename)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => {
+                    let mut holder: String = String::new();
+                    let _ = file.read_to_string(&mut holder);
+                    let holder: &str = holder.trim();
+                    return Err(Error::new(
+                        ErrorKind::WouldBlock,
+                        if holder.is_empty() {
+                            format!("Error: {} is in use by another 371pass process.", path)
+                        } else {
+                            format!(
+                                "Error: {} is in use by another 371pass process ({}).",
+                                path, holder
+                            )
+                        },
+                    ));
+                }
+                Err(fs::TryLockError::Error(error)) => return Err(error),
+            }
+
+            //The holder we waited on deletes the file as it lets go, so the one we
+            //locked may no longer be the lock file. Then we lock the new one.
+            if !same_file(&file, &lock_filename) {
+                continue;
+            }
+            file.set_len(0)?;
+            writeln!(file, "{}", process::id())?;
+            return Ok(Lock::File(lock_filename, file));
+        }
+    }
+
+    fn memory(name: &str) -> Result<Lock, Error> {
+        if !MEMORY_LOCKS.lock().unwrap().insert(String::from(name)) {
+            return Err(Error::new(
+                ErrorKind::WouldBlock,
+                format!("Error: mem://{} is already in use.", name),
+            ));
+        }
+        Ok(Lock::Memory(String::from(name)))
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(unix)]
+fn same_file(file: &fs::File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(named)) => open.dev() == named.dev() && open.ino() == named.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_file: &fs::File, path: &Path) -> bool {
+    path.exists()
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        match self {
+            //Deleted while still locked, so no one takes it in between.
+            Lock::File(lock_filename, file) => {
+                let _ = fs::remove_file(lock_filename);
+                let _ = file.unlock();
+            }
+            Lock::Memory(name) => {
+                MEMORY_LOCKS.lock().unwrap().remove(name);
+            }
+        }
+    }
+}
+
+/// The whole wallet as one JSON, YAML, TOML or MessagePack document in a single file.
+pub(crate) struct FileStore {
+    filename: String,
+    format: FileFormat,
+}
+
+impl FileStore {
+    pub(crate) fn new(filename: &str, format: FileFormat) -> FileStore {
+        FileStore {
+            filename: String::from(filename),
+            format,
+        }
+    }
+}
+
+impl WalletStore for FileStore {
+    fn load(&mut self, wallet: &mut Wallet) -> Result<(), Error> {
+        let file_contents: Vec<u8> = fs::read(&self.filename)?;
+        if wallet.get_integrity().is_none() {
+            if let Some(mut loaded) = self.format.read_current(&file_contents) {
+                wallet.merge_categories(&mut loaded);
+                return Ok(());
+            }
+        }
+
+        let document: Value = self.format.parse(&file_contents)?;
+        if let Some(integrity) = wallet.get_integrity() {
+            let warning: Option<String> =
+                integrity.verify(&self.filename, &document.to_string())?;
+            wallet.add_warning(warning);
+        }
+        wallet.merge_categories(&mut format::read(document)?);
+        Ok(())
+    }
+
+    //The tag covers the document as JSON, so it does not depend on the format it is saved in.
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Error> {
+        let file_contents: Vec<u8> = self.format.write(wallet)?;
+        fs::write(&self.filename, &file_contents)?;
+        if wallet.get_integrity().is_none() {
+            return Ok(());
+        }
+        let document: Value = self.format.parse(&file_contents)?;
+        write_tag(&self.filename, wallet, &document.to_string())
+    }
+
+    fn lock(&mut self) -> Result<Lock, Error> {
+        Lock::file(&self.filename)
+    }
+}
+
+/// A directory holding `<category>/<item>.json`, one pretty-printed file per item
+/// with its ID, `<category>/.template.json` for categories with a template and
+/// `<category>/.id` for the category's ID. Sub-categories are directories inside
+/// their category's directory.
+///
+/// Keeping every item in its own file with sorted keys means a wallet kept in git
+/// diffs and merges item by item. Saving only rewrites the files whose contents
+/// changed, and empty categories keep a `.gitkeep` so git does not drop them.
+pub(crate) struct DirectoryStore {
+    directory: String,
+}
+
+const KEEP_FILENAME: &str = ".gitkeep";
+const TEMPLATE_FILENAME: &str = ".template.json";
+pub(crate) const ID_FILENAME: &str = ".id";
+/// Where the IDs of a category and its items were kept, by name, before they were
+/// saved in the category's `.id` and the item files. Read, then deleted on saving.
+pub(crate) const LEGACY_IDS_FILENAME: &str = ".ids.json";
+
+/// The contents of a legacy `<category>/.ids.json`.
+#[derive(Deserialize)]
+struct LegacyIds {
+    category: Uuid,
+    #[serde(default)]
+    items: BTreeMap<String, Uuid>,
+}
+
+impl DirectoryStore {
+    pub(crate) fn new(directory: &str) -> DirectoryStore {
+        DirectoryStore {
+            directory: String::from(directory),
+        }
+    }
+
+    /// The item files currently on disk, by category path and item identifier. As
+    /// with categories, names starting with `.` are never items.
+    fn stored_files(&self) -> Result<BTreeMap<(String, String), PathBuf>, Error> {
+        let mut files: BTreeMap<(String, String), PathBuf> = BTreeMap::new();
+        for (cat_path, category_path) in self.stored_categories()? {
+            for dir_entry in fs::read_dir(&category_path)? {
+                let path: PathBuf = dir_entry?.path();
+                if file_name(&path).starts_with('.') || path.is_dir() {
+                    continue;
+                }
+                if let Some(item_ident) = json_file_stem(&path) {
+                    files.insert((cat_path.clone(), item_ident), path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// The category directories currently on disk, by category path, with each
+    /// sub-category a directory inside its parent's. Names starting with `.`, such
+    /// as `.git`, are never categories since `encode_file_name` escapes a leading dot.
+    fn stored_categories(&self) -> Result<BTreeMap<String, PathBuf>, Error> {
+        let mut categories: BTreeMap<String, PathBuf> = BTreeMap::new();
+        let mut unvisited: Vec<(String, PathBuf)> =
+            vec![(String::new(), PathBuf::from(&self.directory))];
+        while let Some((parent, directory)) = unvisited.pop() {
+            for dir_entry in fs::read_dir(&directory)? {
+                let path: PathBuf = dir_entry?.path();
+                let name: String = file_name(&path);
+                if path.is_dir() && !name.starts_with('.') {
+                    let cat_path: String = category::join_path(&parent, &decode_file_name(&name));
+                    categories.insert(cat_path.clone(), path.clone());
+                    unvisited.push((cat_path, path));
+                }
+            }
+        }
+        Ok(categories)
+    }
+
+    fn category_path(&self, cat_path: &str) -> PathBuf {
+        category::split_path(cat_path)
+            .iter()
+            .fold(PathBuf::from(&self.directory), |path, name| {
+                path.join(encode_file_name(name))
+            })
+    }
+
+    fn item_path(&self, cat_path: &str, item_ident: &str) -> PathBuf {
+        self.category_path(cat_path)
+            .join(format!("{}.json", encode_file_name(item_ident)))
+    }
+}
+
+impl WalletStore for DirectoryStore {
+    fn load(&mut self, wallet: &mut Wallet) -> Result<(), Error> {
+        let mut loaded: Wallet = Wallet::new();
+        let mut ids: BTreeMap<String, Uuid> = BTreeMap::new();
+        for (cat_path, category_path) in self.stored_categories()? {
+            let legacy_path: PathBuf = category_path.join(LEGACY_IDS_FILENAME);
+            if legacy_path.exists() {
+                let legacy: LegacyIds = serde_json::from_str(&fs::read_to_string(&legacy_path)?)
+                    .map_err(|error| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Error: {}: {}", legacy_path.display(), error),
+                        )
+                    })?;
+                ids.insert(format!("{}/", cat_path), legacy.category);
+                for (item_ident, id) in legacy.items {
+                    ids.insert(category::join_path(&cat_path, &item_ident), id);
+                }
+            }
+            let id_path: PathBuf = category_path.join(ID_FILENAME);
+            if id_path.exists() {
+                let id: Uuid = parse_uuid(&cat_path, fs::read_to_string(&id_path)?.trim())?;
+                ids.insert(format!("{}/", cat_path), id);
+            }
+
+            let category = loaded.new_category(&cat_path);
+            let template_path: PathBuf = category_path.join(TEMPLATE_FILENAME);
+            if template_path.exists() {
+                let template: Template = serde_json::from_str(&fs::read_to_string(&template_path)?)
+                    .map_err(|error| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Error: {}: {}", template_path.display(), error),
+                        )
+                    })?;
+                category.set_template(Some(template));
+            }
+        }
+        for ((cat_path, item_ident), path) in self.stored_files()? {
+            let mut item: Item =
+                serde_json::from_str(&fs::read_to_string(&path)?).map_err(|error| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error: {}: {}", path.display(), error),
+                    )
+                })?;
+            item.set_ident(&item_ident);
+            if !item.has_new_id() {
+                ids.insert(category::join_path(&cat_path, &item_ident), item.id());
+            }
+            loaded.new_category(&cat_path).add_item(&item);
+        }
+
+        loaded.set_ids(&ids);
+        wallet.merge_categories(&mut loaded);
+        verify_tag(&self.directory, wallet)
+    }
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Error> {
+        fs::create_dir_all(&self.directory)?;
+        let contains = |cat_path: &String, item_ident: Option<&String>| -> bool {
+            match (wallet.get(cat_path), item_ident) {
+                (Some(category), Some(item_ident)) => category.get(item_ident).is_some(),
+                (Some(_), None) => true,
+                (None, _) => false,
+            }
+        };
+
+        //A parent's path is a prefix of its sub-categories', so they are removed first.
+        let removed: Vec<PathBuf> = self
+            .stored_categories()?
+            .into_iter()
+            .rev()
+            .filter(|(cat_path, _)| !contains(cat_path, None))
+            .map(|(_, path)| path)
+            .collect();
+        //Checked before anything is removed, so that a save does not stop half done.
+        for path in &removed {
+            if let Some(unknown) = unknown_files(path)?.first() {
+                return Err(Error::new(
+                    ErrorKind::DirectoryNotEmpty,
+                    format!(
+                        "Error: could not remove {}: {} is not part of the wallet. Move or delete it and try again.",
+                        path.display(),
+                        unknown.display()
+                    ),
+                ));
+            }
+        }
+
+        for ((cat_path, item_ident), path) in self.stored_files()? {
+            if !contains(&cat_path, Some(&item_ident)) {
+                fs::remove_file(path)?;
+            }
+        }
+        for path in removed {
+            for dir_entry in fs::read_dir(&path)? {
+                let file: PathBuf = dir_entry?.path();
+                if !file.is_dir() {
+                    fs::remove_file(file)?;
+                }
+            }
+            fs::remove_dir(&path).map_err(|error| {
+                Error::new(
+                    error.kind(),
+                    format!("Error: could not remove {}: {}", path.display(), error),
+                )
+            })?;
+        }
+
+        for (cat_path, category) in wallet.all_categories() {
+            let category_path: PathBuf = self.category_path(&cat_path);
+            fs::create_dir_all(&category_path)?;
+
+            let keep_path: PathBuf = category_path.join(KEEP_FILENAME);
+            if category.empty() && !keep_path.exists() {
+                fs::write(&keep_path, "")?;
+            } else if !category.empty() && keep_path.exists() {
+                fs::remove_file(&keep_path)?;
+            }
+
+            let template_path: PathBuf = category_path.join(TEMPLATE_FILENAME);
+            match category.template() {
+                Some(template) => {
+                    let contents: String = format!("{}\n", serde_json::to_string_pretty(template)?);
+                    if fs::read_to_string(&template_path).ok().as_deref() != Some(contents.as_str())
+                    {
+                        fs::write(&template_path, contents)?;
+                    }
+                }
+                None if template_path.exists() => fs::remove_file(&template_path)?,
+                None => {}
+            }
+
+            let id_path: PathBuf = category_path.join(ID_FILENAME);
+            let contents: String = format!("{}\n", category.id().hyphenated());
+            if fs::read_to_string(&id_path).ok().as_deref() != Some(contents.as_str()) {
+                fs::write(&id_path, contents)?;
+            }
+            let legacy_path: PathBuf = category_path.join(LEGACY_IDS_FILENAME);
+            if legacy_path.exists() {
+                fs::remove_file(&legacy_path)?;
+            }
+
+            for item in category.items() {
+                let path: PathBuf = self.item_path(&cat_path, item.get_ident());
+                let contents: String = item_file_contents(item)?;
+                if fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+                    fs::write(&path, contents)?;
+                }
+            }
+        }
+        write_tag(&self.directory, wallet, &serde_json::to_string(wallet)?)
+    }
+
+    fn lock(&mut self) -> Result<Lock, Error> {
+        Lock::file(&self.directory)
+    }
+}
+
+/// The files in the category directory `path` that are neither items, sub-categories,
+/// our own metadata nor clutter such as `.DS_Store` or editor backups.
+fn unknown_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut unknown: Vec<PathBuf> = Vec::new();
+    for dir_entry in fs::read_dir(path)? {
+        let path: PathBuf = dir_entry?.path();
+        let name: String = file_name(&path);
+        let known: bool = if path.is_dir() {
+            !name.starts_with('.')
+        } else {
+            [
+                KEEP_FILENAME,
+                TEMPLATE_FILENAME,
+                ID_FILENAME,
+                LEGACY_IDS_FILENAME,
+            ]
+            .contains(&name.as_str())
+                || (!name.starts_with('.') && json_file_stem(&path).is_some())
+                || is_clutter(&name)
+        };
+        if !known {
+            unknown.push(path);
+        }
+    }
+    Ok(unknown)
+}
+
+/// Files that operating systems and editors leave next to the ones they open, which
+/// are deleted along with a category's directory.
+fn is_clutter(name: &str) -> bool {
+    matches!(name, ".DS_Store" | "Thumbs.db" | "desktop.ini")
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".bak")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// An item as it is written to `<category>/<item>.json`.
+pub(crate) fn item_file_contents(item: &Item) -> Result<String, Error> {
+    Ok(format!("{}\n", serde_json::to_string_pretty(item)?))
+}
+
+pub(crate) fn json_file_stem(path: &Path) -> Option<String> {
+    if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+        return None;
+    }
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(decode_file_name)
+}
+
+/// Escapes the characters that cannot appear in a file name, so any identifier can be stored.
+fn encode_file_name(ident: &str) -> String {
+    let mut file_name: String = String::new();
+    for (position, c) in ident.chars().enumerate() {
+        match c {
+            '%' | '/' | '\\' | ':' => file_name.push_str(&format!("%{:02X}", c as u32)),
+            '.' if position == 0 => file_name.push_str("%2E"),
+            c => file_name.push(c),
+        }
+    }
+    file_name
+}
+
+pub(crate) fn decode_file_name(file_name: &str) -> String {
+    let mut ident: String = String::new();
+    let mut rest: &str = file_name;
+    while let Some(position) = rest.find('%') {
+        ident.push_str(&rest[..position]);
+        let escaped: Option<char> = rest
+            .get(position + 1..position + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .map(char::from);
+        match escaped {
+            Some(c) => {
+                ident.push(c);
+                rest = &rest[position + 3..];
+            }
+            None => {
+                ident.push('%');
+                rest = &rest[position + 1..];
+            }
+        }
+    }
+    ident.push_str(rest);
+    ident
+}
+
+static MEMORY: Mutex<BTreeMap<String, (String, Option<String>)>> = Mutex::new(BTreeMap::new());
+static MEMORY_LOCKS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// A wallet kept in memory by name, with its integrity tag, for tests and scripting.
+pub(crate) struct MemoryStore {
+    name: String,
+}
+
+impl MemoryStore {
+    pub(crate) fn new(name: &str) -> MemoryStore {
+        MemoryStore {
+            name: String::from(name),
+        }
+    }
+}
+
+impl WalletStore for MemoryStore {
+    fn load(&mut self, wallet: &mut Wallet) -> Result<(), Error> {
+        let (json_val, tag) = MEMORY
+            .lock()
+            .unwrap()
+            .get(&self.name)
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("Error: mem://{} does not exist.", self.name),
+                )
+            })?;
+        if let Some(integrity) = wallet.get_integrity() {
+            let warning: Option<String> = integrity.verify_tag(tag.as_deref(), &json_val)?;
+            wallet.add_warning(warning);
+        }
+        let document: Value = FileFormat::Json.parse(json_val.as_bytes())?;
+        wallet.merge_categories(&mut format::read(document)?);
+        Ok(())
+    }
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Error> {
+        let json_val: String = serde_json::to_string(&format::Document::new(wallet))?;
+        let tag: Option<String> = match wallet.get_integrity() {
+            Some(integrity) => Some(integrity.sign(&json_val)?),
+            None => None,
+        };
+        MEMORY
+            .lock()
+            .unwrap()
+            .insert(self.name.clone(), (json_val, tag));
+        Ok(())
+    }
+
+    fn lock(&mut self) -> Result<Lock, Error> {
+        Lock::memory(&self.name)
+    }
+}
+
+/// Categories, items and entries as rows in a SQLite database. Sub-categories
+/// are rows of their own, named by their path.
+///
+/// Saving compares the wallet with the rows already stored and only inserts,
+/// updates or deletes the rows that differ, all in one transaction. Actions on a
+/// single item read and write only that item's rows, unless the wallet has an
+/// integrity key, whose tag covers the whole wallet.
+pub(crate) struct SqliteStore {
+    filename: String,
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub(crate) fn open(filename: &str, create: bool) -> Result<SqliteStore, Error> {
+        let mut flags: OpenFlags = OpenFlags::default();
+        if !create {
+            flags.remove(OpenFlags::SQLITE_OPEN_CREATE);
+        }
+        let connection: Connection = Connection::open_with_flags(filename, flags)
+            .map_err(|error| Error::new(ErrorKind::NotFound, format!("{}: {}", filename, error)))?;
+        connection.execute_batch(SCHEMA).map_err(sqlite_error)?;
+
+        //Databases created before entries had kinds, categories had templates or either
+        //had IDs are missing the columns.
+        add_missing_column(&connection, "entries", "kind")?;
+        add_missing_column(&connection, "categories", "template")?;
+        add_missing_column(&connection, "categories", "uuid")?;
+        add_missing_column(&connection, "items", "uuid")?;
+        escape_category_names(&connection)?;
+
+        Ok(SqliteStore {
+            filename: String::from(filename),
+            connection,
+        })
+    }
+
+    /// Every category, item and entry in the database, keyed the same way as `merge::flatten`,
+    /// and the kinds of the entries that have one.
+    fn stored_keys(&self) -> Result<(BTreeMap<Key, Option<String>>, Kinds), Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT categories.name, items.name, entries.key, entries.value, entries.kind
+                 FROM categories
+                 LEFT JOIN items ON items.category_id = categories.id
+                 LEFT JOIN entries ON entries.item_id = items.id",
+            )
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            })
+            .map_err(sqlite_error)?;
+
+        let mut keys: BTreeMap<Key, Option<String>> = BTreeMap::new();
+        let mut kinds: Kinds = BTreeMap::new();
+        for row in rows {
+            let (cat_ident, item_ident, entry_ident, entry_val, entry_kind) =
+                row.map_err(sqlite_error)?;
+            keys.insert((cat_ident.clone(), None, None), None);
+            if let Some(item_ident) = item_ident {
+                keys.insert((cat_ident.clone(), Some(item_ident.clone()), None), None);
+                if let Some(entry_ident) = entry_ident {
+                    let key: Key = (cat_ident, Some(item_ident), Some(entry_ident));
+                    if let Some(entry_kind) = entry_kind {
+                        kinds.insert(key.clone(), entry_kind.parse()?);
+                    }
+                    keys.insert(key, entry_val);
+                }
+            }
+        }
+        Ok((keys, kinds))
+    }
+
+    /// A wallet holding the category at `cat_path`, with its template and the IDs of it
+    /// and its parents, and only its item `item_ident`. Empty if neither is stored.
+    fn get_item(&self, cat_path: &str, item_ident: &str) -> Result<Wallet, Error> {
+        let mut loaded: Wallet = Wallet::new();
+        let mut ids: BTreeMap<String, Uuid> = BTreeMap::new();
+        let mut path: String = String::new();
+        for name in category::split_path(cat_path) {
+            path = category::join_path(&path, &name);
+            let id: Option<Option<String>> = self
+                .connection
+                .query_row(
+                    "SELECT uuid FROM categories WHERE name = ?1",
+                    params![path],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(sqlite_error)?;
+            if let Some(Some(id)) = id {
+                ids.insert(format!("{}/", path), parse_uuid(&path, &id)?);
+            }
+        }
+
+        let row: Option<(i64, Option<String>)> = self
+            .connection
+            .query_row(
+                "SELECT id, template FROM categories WHERE name = ?1",
+                params![cat_path],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+        let Some((category_id, template)) = row else {
+            return Ok(loaded);
+        };
+        let category: &mut Category = loaded.new_category(cat_path);
+
+        let row: Option<(i64, Option<String>)> = self
+            .connection
+            .query_row(
+                "SELECT id, uuid FROM items WHERE category_id = ?1 AND name = ?2",
+                params![category_id, item_ident],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+        if let Some((item_id, id)) = row {
+            let item: &mut Item = category.new_item(item_ident);
+            for (key, value, kind) in stored_entries(&self.connection, item_id)? {
+                item.add_entry(&key, &value);
+                item.set_kind(&key, kind);
+            }
+            if let Some(id) = id {
+                let item_path: String = category::join_path(cat_path, item_ident);
+                ids.insert(item_path, parse_uuid(cat_path, &id)?);
+            }
+        }
+        //Set once the item is loaded, so that it is not filled in from it.
+        if let Some(template) = template {
+            category.set_template(Some(parse_template(cat_path, &template)?));
+        }
+        loaded.set_ids(&ids);
+        Ok(loaded)
+    }
+
+    /// Writes the item `item_ident` of the category at `cat_path` as `wallet` has
+    /// it, adding the category and its parents if they are not stored yet.
+    fn put_item(&mut self, wallet: &Wallet, cat_path: &str, item_ident: &str) -> Result<(), Error> {
+        let Some(item) = wallet
+            .get(cat_path)
+            .and_then(|category| category.get(item_ident))
+        else {
+            return self.delete_item(cat_path, item_ident);
+        };
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+
+        let mut path: String = String::new();
+        for name in category::split_path(cat_path) {
+            path = category::join_path(&path, &name);
+            let id: Option<String> = wallet.get(&path).map(|category| category.id().to_string());
+            transaction
+                .execute(
+                    "INSERT INTO categories (name, uuid) VALUES (?1, ?2)
+                     ON CONFLICT (name) DO NOTHING",
+                    params![path, id],
+                )
+                .map_err(sqlite_error)?;
+        }
+        transaction
+            .execute(
+                "INSERT INTO items (category_id, name, uuid)
+                 SELECT id, ?2, ?3 FROM categories WHERE name = ?1
+                 ON CONFLICT (category_id, name) DO UPDATE SET uuid = excluded.uuid
+                 WHERE uuid IS NOT excluded.uuid",
+                params![cat_path, item_ident, item.id().to_string()],
+            )
+            .map_err(sqlite_error)?;
+        let item_id: i64 = transaction
+            .query_row(
+                "SELECT items.id FROM items
+                 JOIN categories ON items.category_id = categories.id
+                 WHERE categories.name = ?1 AND items.name = ?2",
+                params![cat_path, item_ident],
+                |row| row.get(0),
+            )
+            .map_err(sqlite_error)?;
+
+        let stored: BTreeMap<String, (String, Option<EntryKind>)> =
+            stored_entries(&transaction, item_id)?
+                .into_iter()
+                .map(|(key, value, kind)| (key, (value, kind)))
+                .collect();
+        for key in stored.keys().filter(|key| item.get(key).is_none()) {
+            transaction
+                .execute(
+                    "DELETE FROM entries WHERE item_id = ?1 AND key = ?2",
+                    params![item_id, key],
+                )
+                .map_err(sqlite_error)?;
+        }
+        for (key, value) in item.entries() {
+            let kind: Option<EntryKind> = item.kind(key);
+            if stored.get(key) == Some(&(String::from(value), kind)) {
+                continue;
+            }
+            transaction
+                .execute(
+                    "INSERT INTO entries (item_id, key, value, kind) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (item_id, key) DO UPDATE
+                     SET value = excluded.value, kind = excluded.kind",
+                    params![item_id, key, value, kind.map(|kind| kind.name())],
+                )
+                .map_err(sqlite_error)?;
+        }
+        transaction.commit().map_err(sqlite_error)
+    }
+
+    /// Deletes the item `item_ident` of the category at `cat_path`, and so its entries.
+    fn delete_item(&self, cat_path: &str, item_ident: &str) -> Result<(), Error> {
+        self.connection
+            .execute(
+                "DELETE FROM items WHERE name = ?2
+                 AND category_id = (SELECT id FROM categories WHERE name = ?1)",
+                params![cat_path, item_ident],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    /// The templates of the categories that have one, as JSON.
+    fn stored_templates(&self) -> Result<BTreeMap<String, String>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, template FROM categories WHERE template IS NOT NULL")
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(sqlite_error)?;
+        rows.collect::<Result<BTreeMap<String, String>, _>>()
+            .map_err(sqlite_error)
+    }
+
+    /// The IDs of the categories and items that have one, keyed as by `Wallet::ids`.
+    fn stored_ids(&self) -> Result<BTreeMap<String, Uuid>, Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT name, NULL, uuid FROM categories WHERE uuid IS NOT NULL
+                 UNION ALL
+                 SELECT categories.name, items.name, items.uuid
+                 FROM items JOIN categories ON items.category_id = categories.id
+                 WHERE items.uuid IS NOT NULL",
+            )
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(sqlite_error)?;
+
+        let mut ids: BTreeMap<String, Uuid> = BTreeMap::new();
+        for row in rows {
+            let (cat_ident, item_ident, id) = row.map_err(sqlite_error)?;
+            let id: Uuid = parse_uuid(&cat_ident, &id)?;
+            match item_ident {
+                Some(item_ident) => ids.insert(category::join_path(&cat_ident, &item_ident), id),
+                None => ids.insert(format!("{}/", cat_ident), id),
+            };
+        }
+        Ok(ids)
+    }
+}
+
+fn add_missing_column(connection: &Connection, table: &str, column: &str) -> Result<(), Error> {
+    let has_column: bool = connection
+        .query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(sqlite_error)?
+        > 0;
+    if !has_column {
+        connection
+            .execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} TEXT", table, column),
+                [],
+            )
+            .map_err(sqlite_error)?;
+    }
+    Ok(())
+}
+
+/// Categories are stored by path since sub-categories were added, so the names in
+/// older databases have their `/` and `%` escaped to keep them top-level categories.
+/// `user_version` records that this has been done.
+fn escape_category_names(connection: &Connection) -> Result<(), Error> {
+    let user_version: i64 = connection
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(sqlite_error)?;
+    if user_version < 1 {
+        connection
+            .execute_batch(
+                "UPDATE categories SET name = replace(replace(name, '%', '%25'), '/', '%2F');
+                 PRAGMA user_version = 1;",
+            )
+            .map_err(sqlite_error)?;
+    }
+    Ok(())
+}
+
+impl WalletStore for SqliteStore {
+    fn load(&mut self, wallet: &mut Wallet) -> Result<(), Error> {
+        let mut loaded: Wallet = Wallet::new();
+        let (keys, kinds) = self.stored_keys()?;
+        for (key, entry_val) in keys {
+            let (cat_ident, item_ident, entry_ident) = &key;
+            let category = loaded.new_category(cat_ident);
+            if let Some(item_ident) = item_ident {
+                let item = category.new_item(item_ident);
+                if let (Some(entry_ident), Some(entry_val)) = (entry_ident, entry_val) {
+                    item.add_entry(entry_ident, &entry_val);
+                    item.set_kind(entry_ident, kinds.get(&key).copied());
+                }
+            }
+        }
+        //Set once the items are loaded, so that they are not filled in from it.
+        for (cat_ident, template) in self.stored_templates()? {
+            let template: Template = parse_template(&cat_ident, &template)?;
+            loaded.new_category(&cat_ident).set_template(Some(template));
+        }
+        loaded.set_ids(&self.stored_ids()?);
+
+        wallet.merge_categories(&mut loaded);
+        verify_tag(&self.filename, wallet)
+    }
+
+    fn save(&mut self, wallet: &Wallet) -> Result<(), Error> {
+        let (stored, stored_kinds) = self.stored_keys()?;
+        let stored_templates: BTreeMap<String, String> = self.stored_templates()?;
+        let stored_ids: BTreeMap<String, Uuid> = self.stored_ids()?;
+        let current: BTreeMap<Key, Option<String>> = merge::flatten(wallet);
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+
+        //Deleting a category or item cascades to its items and entries, so only the
+        //outermost removal is needed. Sub-categories are rows of their own.
+        for key in stored.keys().filter(|key| !current.contains_key(*key)) {
+            let parent_removed: bool = match key {
+                (cat, Some(_), None) => !current.contains_key(&(cat.clone(), None, None)),
+                (cat, Some(item), Some(_)) => {
+                    !current.contains_key(&(cat.clone(), Some(item.clone()), None))
+                }
+                _ => false,
+            };
+            if parent_removed {
+                continue;
+            }
+
+            match key {
+                (cat, None, _) => {
+                    transaction.execute("DELETE FROM categories WHERE name = ?1", params![cat])
+                }
+                (cat, Some(item), None) => transaction.execute(
+                    "DELETE FROM items WHERE name = ?2
+                     AND category_id = (SELECT id FROM categories WHERE name = ?1)",
+                    params![cat, item],
+                ),
+                (cat, Some(item), Some(entry)) => transaction.execute(
+                    "DELETE FROM entries WHERE key = ?3 AND item_id = (
+                         SELECT items.id FROM items
+                         JOIN categories ON items.category_id = categories.id
+                         WHERE categories.name = ?1 AND items.name = ?2)",
+                    params![cat, item, entry],
+                ),
+            }
+            .map_err(sqlite_error)?;
+        }
+
+        //Keys are ordered so that a category comes before its items and an item before its entries.
+        for (key, value) in current.iter() {
+            let kind: Option<EntryKind> = match key {
+                (cat, Some(item), Some(entry)) => wallet
+                    .get(cat)
+                    .and_then(|category| category.get(item))
+                    .and_then(|item| item.kind(entry)),
+                _ => None,
+            };
+            if stored.get(key) == Some(value) && stored_kinds.get(key).copied() == kind {
+                continue;
+            }
+
+            match (key, value) {
+                ((cat, None, _), _) => {
+                    transaction.execute("INSERT INTO categories (name) VALUES (?1)", params![cat])
+                }
+                ((cat, Some(item), None), _) => transaction.execute(
+                    "INSERT INTO items (category_id, name)
+                     SELECT id, ?2 FROM categories WHERE name = ?1",
+                    params![cat, item],
+                ),
+                ((cat, Some(item), Some(entry)), value) => transaction.execute(
+                    "INSERT INTO entries (item_id, key, value, kind)
+                     SELECT items.id, ?3, ?4, ?5 FROM items
+                     JOIN categories ON items.category_id = categories.id
+                     WHERE categories.name = ?1 AND items.name = ?2
+                     ON CONFLICT (item_id, key) DO UPDATE
+                     SET value = excluded.value, kind = excluded.kind",
+                    params![
+                        cat,
+                        item,
+                        entry,
+                        value.clone().unwrap_or_default(),
+                        kind.map(|kind| kind.name())
+                    ],
+                ),
+            }
+            .map_err(sqlite_error)?;
+        }
+
+        for (cat_path, category) in wallet.all_categories() {
+            let template: Option<String> =
+                category.template().map(serde_json::to_string).transpose()?;
+            if stored_templates.get(&cat_path) != template.as_ref() {
+                transaction
+                    .execute(
+                        "UPDATE categories SET template = ?2 WHERE name = ?1",
+                        params![cat_path, template],
+                    )
+                    .map_err(sqlite_error)?;
+            }
+
+            if stored_ids.get(&format!("{}/", cat_path)) != Some(&category.id()) {
+                transaction
+                    .execute(
+                        "UPDATE categories SET uuid = ?2 WHERE name = ?1",
+                        params![cat_path, category.id().to_string()],
+                    )
+                    .map_err(sqlite_error)?;
+            }
+            for item in category.items() {
+                if stored_ids.get(&category::join_path(&cat_path, item.get_ident()))
+                    != Some(&item.id())
+                {
+                    transaction
+                        .execute(
+                            "UPDATE items SET uuid = ?3 WHERE name = ?2
+                             AND category_id = (SELECT id FROM categories WHERE name = ?1)",
+                            params![cat_path, item.get_ident(), item.id().to_string()],
+                        )
+                        .map_err(sqlite_error)?;
+                }
+            }
+        }
+
+        transaction.commit().map_err(sqlite_error)?;
+        write_tag(&self.filename, wallet, &serde_json::to_string(wallet)?)
+    }
+
+    fn load_item(
+        &mut self,
+        wallet: &mut Wallet,
+        cat_path: &str,
+        item_ident: &str,
+    ) -> Result<(), Error> {
+        if wallet.get_integrity().is_some() {
+            return self.load(wallet);
+        }
+        let mut loaded: Wallet = self.get_item(cat_path, item_ident)?;
+        wallet.merge_categories(&mut loaded);
+        Ok(())
+    }
+
+    fn save_item(
+        &mut self,
+        wallet: &Wallet,
+        cat_path: &str,
+        item_ident: &str,
+    ) -> Result<(), Error> {
+        if wallet.get_integrity().is_some() {
+            return self.save(wallet);
+        }
+        self.put_item(wallet, cat_path, item_ident)
+    }
+
+    fn lock(&mut self) -> Result<Lock, Error> {
+        Lock::file(&self.filename)
+    }
+}
+
+/// The entries of the item with the row ID `item_id`, with their kinds.
+fn stored_entries(
+    connection: &Connection,
+    item_id: i64,
+) -> Result<Vec<(String, String, Option<EntryKind>)>, Error> {
+    let mut statement = connection
+        .prepare("SELECT key, value, kind FROM entries WHERE item_id = ?1")
+        .map_err(sqlite_error)?;
+    let rows = statement
+        .query_map(params![item_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })
+        .map_err(sqlite_error)?;
+
+    let mut entries: Vec<(String, String, Option<EntryKind>)> = Vec::new();
+    for row in rows {
+        let (key, value, kind) = row.map_err(sqlite_error)?;
+        entries.push((key, value, kind.map(|kind| kind.parse()).transpose()?));
+    }
+    Ok(entries)
+}
+
+fn parse_uuid(cat_ident: &str, id: &str) -> Result<Uuid, Error> {
+    Uuid::parse_str(id).map_err(|error| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Error: malformed ID in {}: {}", cat_ident, error),
+        )
+    })
+}
+
+fn parse_template(cat_ident: &str, template: &str) -> Result<Template, Error> {
+    serde_json::from_str(template).map_err(|error| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Error: malformed template for {}: {}", cat_ident, error),
+        )
+    })
+}
+
+/// Checks the tag next to `filename` against the wallet as it was read back from the store.
+fn verify_tag(filename: &str, wallet: &mut Wallet) -> Result<(), Error> {
+    if let Some(integrity) = wallet.get_integrity() {
+        let contents: String = serde_json::to_string(wallet)?;
+        //Before the IDs were saved in the records, they were left out of what was signed.
+        let legacy: String = serde_json::to_string(&WithoutIds(&*wallet))?;
+        let strict: Integrity = integrity.clone().accept_tampered(false);
+        let signed: &str = if strict.verify(filename, &contents).is_err()
+            && strict.verify(filename, &legacy).is_ok()
+        {
+            &legacy
+        } else {
+            &contents
+        };
+        let warning: Option<String> = integrity.verify(filename, signed)?;
+        wallet.add_warning(warning);
+    }
+    Ok(())
+}
+
+/// Signs `contents`, which is what `verify_tag` or `Integrity::verify` will check when loading.
+fn write_tag(filename: &str, wallet: &Wallet, contents: &str) -> Result<(), Error> {
+    if let Some(integrity) = wallet.get_integrity() {
+        let tag: String = integrity.sign(contents)?;
+        fs::write(Integrity::tag_filename(filename), tag)?;
+    }
+    Ok(())
+}
+
+fn sqlite_error(error: rusqlite::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Field;
+
+    fn temp_database(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("371pass-{}-{}.sqlite", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    fn wallet(json: &str) -> Wallet {
+        let mut wallet: Wallet = Wallet::new();
+        wallet.load_json(json).unwrap();
+        wallet
+    }
+
+    #[test]
+    fn test_sqlite_round_trip() {
+        let filename: String = temp_database("round-trip");
+        let original: Wallet = wallet(
+            r#"{"Bank Accounts":{},"Websites":{"Facebook":{},"Google":{"password":{"type":"secret","value":"pass1234"},"url":"https://www.google.com/"}}}"#,
+        );
+
+        //A missing database is only created when saving.
+        assert!(SqliteStore::open(&filename, false).is_err());
+        assert!(SqliteStore::open(&filename, true)
+            .unwrap()
+            .save(&original)
+            .is_ok());
+        assert!(is_sqlite(&filename));
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(open(&filename, false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded, original);
+
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_save_only_writes_changes() {
+        let filename: String = temp_database("changes");
+        let mut store: SqliteStore = SqliteStore::open(&filename, true).unwrap();
+        store
+            .save(&wallet(
+                r#"{"Websites":{"Facebook":{"password":"pass1234fb"},"Google":{"password":"pass1234","url":"https://www.google.com/"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+            ))
+            .unwrap();
+
+        //Saving the same wallet again writes nothing.
+        let changes: u64 = store.connection.total_changes();
+        let mut loaded: Wallet = Wallet::new();
+        store.load(&mut loaded).unwrap();
+        store.save(&loaded).unwrap();
+        assert_eq!(store.connection.total_changes(), changes);
+
+        //One changed entry, plus a deleted item and the entry that cascades with it.
+        let mut changed: Wallet = loaded.clone();
+        let websites: &mut Category = changed.get_mut("Websites").unwrap();
+        websites.delete_item("Twitter");
+        websites
+            .get_mut("Google")
+            .unwrap()
+            .add_entry("password", "newpass");
+        store.save(&changed).unwrap();
+        assert_eq!(store.connection.total_changes(), changes + 3);
+
+        let mut reloaded: Wallet = Wallet::new();
+        store.load(&mut reloaded).unwrap();
+        assert_eq!(reloaded, changed);
+
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_item_actions_only_touch_that_item() {
+        let filename: String = temp_database("item");
+        let mut store: SqliteStore = SqliteStore::open(&filename, true).unwrap();
+        let original: Wallet = wallet(
+            r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{"Google":{"password":"pass1234"},"Twitter":{"password":"r43rfsffdsfdsf"}}}"#,
+        );
+        store.save(&original).unwrap();
+
+        //Only the category and the one item are loaded, with their IDs.
+        let mut google: Wallet = Wallet::new();
+        store.load_item(&mut google, "Websites", "Google").unwrap();
+        assert_eq!(
+            google,
+            wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#)
+        );
+        assert_eq!(google.ids(), {
+            let mut ids: BTreeMap<String, Uuid> = original.ids();
+            ids.retain(|path, _| path == "Websites/" || path == "Websites/Google");
+            ids
+        });
+
+        //Changing an entry writes only that row.
+        let changes: u64 = store.connection.total_changes();
+        google
+            .get_mut("Websites")
+            .unwrap()
+            .get_mut("Google")
+            .unwrap()
+            .add_entry("password", "newpass");
+        store.save_item(&google, "Websites", "Google").unwrap();
+        assert_eq!(store.connection.total_changes(), changes + 1);
+
+        //A new item in new categories adds their rows, and deleting an item removes
+        //it and the entry that cascades with it.
+        let mut aws: Wallet = Wallet::new();
+        store.load_item(&mut aws, "Work/AWS", "Prod").unwrap();
+        assert!(aws.empty());
+        aws.new_category("Work/AWS")
+            .new_item("Prod")
+            .add_entry("key", "value");
+        store.save_item(&aws, "Work/AWS", "Prod").unwrap();
+        assert_eq!(store.connection.total_changes(), changes + 5);
+        let mut twitter: Wallet = Wallet::new();
+        store
+            .load_item(&mut twitter, "Websites", "Twitter")
+            .unwrap();
+        twitter.get_mut("Websites").unwrap().delete_item("Twitter");
+        store.save_item(&twitter, "Websites", "Twitter").unwrap();
+        assert_eq!(store.connection.total_changes(), changes + 7);
+
+        let mut reloaded: Wallet = Wallet::new();
+        store.load(&mut reloaded).unwrap();
+        let expected: Wallet = Wallet::builder()
+            .entry("Bank Accounts", "Starling", "Sort Code", "12-34-56")
+            .entry("Websites", "Google", "password", "newpass")
+            .entry("Work/AWS", "Prod", "key", "value")
+            .build();
+        assert_eq!(reloaded, expected);
+
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_location_parse() {
+        assert_eq!(
+            Location::parse("./tests/testdatabase.json").unwrap(),
+            Location::Path("./tests/testdatabase.json")
+        );
+        assert_eq!(
+            Location::parse("dir:///home/wallet").unwrap(),
+            Location::Directory("/home/wallet")
+        );
+        assert_eq!(Location::parse("mem://test").unwrap().local_path(), None);
+        assert!(Location::parse("https://example.com/wallet.json").is_err());
+    }
+
+    #[test]
+    fn test_directory_store() {
+        let path = std::env::temp_dir().join(format!("371pass-dir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let database: String = format!("dir://{}", path.to_string_lossy());
+        let original: Wallet = wallet(
+            r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Empty":{},"Websites/Social":{"Facebook":{"password":"pass1234fb","url":"https://www.facebook.com/"}}}"#,
+        );
+
+        assert!(open(&database, false, None)
+            .unwrap()
+            .load(&mut Wallet::new())
+            .is_err());
+        assert!(open(&database, true, None).unwrap().save(&original).is_ok());
+        let social: &Category = original.get("Websites%2FSocial").unwrap();
+        assert_eq!(
+            fs::read_to_string(path.join("Websites%2FSocial").join("Facebook.json")).unwrap(),
+            format!(
+                "{{\n  \"@id\": \"{}\",\n  \"password\": \"pass1234fb\",\n  \"url\": \"https://www.facebook.com/\"\n}}\n",
+                social.get("Facebook").unwrap().id()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(path.join("Websites%2FSocial").join(ID_FILENAME)).unwrap(),
+            format!("{}\n", social.id())
+        );
+        assert!(path.join("Empty").join(KEEP_FILENAME).exists());
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(open(&database, false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded, original);
+        assert_eq!(loaded.ids(), original.ids());
+
+        //Unchanged items are not rewritten, and deleted ones lose their file.
+        let starling: PathBuf = path.join("Bank Accounts").join("Starling.json");
+        fs::File::options()
+            .write(true)
+            .open(&starling)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH)
+            .unwrap();
+        let mut changed: Wallet = loaded.clone();
+        changed.delete_category("Empty");
+        let social: &mut Category = changed.get_mut("Websites%2FSocial").unwrap();
+        social.delete_item("Facebook");
+        social.add_item(&Item::new(String::from("Twitter")));
+        assert!(open(&database, true, None).unwrap().save(&changed).is_ok());
+        assert_eq!(
+            fs::metadata(&starling).unwrap().modified().unwrap(),
+            std::time::UNIX_EPOCH
+        );
+        assert!(!path
+            .join("Websites%2FSocial")
+            .join("Facebook.json")
+            .exists());
+        assert!(!path.join("Empty").exists());
+
+        let mut loaded: Wallet = Wallet::new();
+        assert!(open(&database, false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded, changed);
+
+        //IDs kept the way earlier versions did are read, then saved the current way.
+        let bank_accounts: PathBuf = path.join("Bank Accounts");
+        let legacy_id: Uuid = Uuid::new_v4();
+        fs::remove_file(bank_accounts.join(ID_FILENAME)).unwrap();
+        fs::write(
+            bank_accounts.join(LEGACY_IDS_FILENAME),
+            format!("{{\"category\":\"{}\",\"items\":{{}}}}", legacy_id),
+        )
+        .unwrap();
+        let mut loaded: Wallet = Wallet::new();
+        assert!(open(&database, false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded.get("Bank Accounts").unwrap().id(), legacy_id);
+        assert!(open(&database, true, None).unwrap().save(&loaded).is_ok());
+        assert!(!bank_accounts.join(LEGACY_IDS_FILENAME).exists());
+        assert_eq!(
+            fs::read_to_string(bank_accounts.join(ID_FILENAME)).unwrap(),
+            format!("{}\n", legacy_id)
+        );
+
+        //Clutter is removed with a deleted category, but other files stop the save.
+        fs::write(bank_accounts.join(".DS_Store"), "").unwrap();
+        fs::write(bank_accounts.join("Starling.json~"), "").unwrap();
+        fs::write(bank_accounts.join("notes.txt"), "").unwrap();
+        let emptied: Wallet = wallet(r#"{"Websites/Social":{"Twitter":{}}}"#);
+        let error: Error = open(&database, true, None)
+            .unwrap()
+            .save(&emptied)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DirectoryNotEmpty);
+        assert!(error.to_string().contains("notes.txt"));
+        assert!(starling.exists());
+        fs::remove_file(bank_accounts.join("notes.txt")).unwrap();
+        assert!(open(&database, true, None).unwrap().save(&emptied).is_ok());
+        assert!(!bank_accounts.exists());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tags_signed_without_ids() {
+        let path = std::env::temp_dir().join(format!("371pass-dir-tag-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let directory: String = path.to_string_lossy().to_string();
+        let mut original: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        original.set_integrity(Integrity::new(b"secret"));
+        assert!(open(&format!("dir://{}", directory), true, None)
+            .unwrap()
+            .save(&original)
+            .is_ok());
+
+        //Wallets saved before the IDs were signed still verify.
+        let integrity: Integrity = Integrity::new(b"secret");
+        let legacy: String = serde_json::to_string(&WithoutIds(&original)).unwrap();
+        fs::write(
+            Integrity::tag_filename(&directory),
+            integrity.sign(&legacy).unwrap(),
+        )
+        .unwrap();
+        let mut loaded: Wallet = Wallet::new();
+        loaded.set_integrity(integrity.clone());
+        assert!(open(&format!("dir://{}", directory), false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded, original);
+
+        fs::write(
+            Integrity::tag_filename(&directory),
+            integrity.sign("{}").unwrap(),
+        )
+        .unwrap();
+        let mut loaded: Wallet = Wallet::new();
+        loaded.set_integrity(integrity);
+        assert!(open(&format!("dir://{}", directory), false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_err());
+
+        fs::remove_dir_all(&path).unwrap();
+        let _ = fs::remove_file(Integrity::tag_filename(&directory));
+    }
+
+    #[test]
+    fn test_templates_round_trip() {
+        let directory = std::env::temp_dir().join(format!("371pass-templates-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let yaml: String = directory
+            .with_extension("yaml")
+            .to_string_lossy()
+            .to_string();
+        let sqlite: String = temp_database("templates");
+
+        let mut original: Wallet =
+            wallet(r#"{"Bank Accounts":{"Starling":{"Sort Code":"12-34-56"}},"Websites":{}}"#);
+        original
+            .get_mut("Bank Accounts")
+            .unwrap()
+            .set_template(Some(
+                Template::new()
+                    .field(Field::new("Sort Code").required())
+                    .field(Field::new("Name").default_value("Mr John Doe")),
+            ));
+
+        for database in [
+            format!("dir://{}", directory.to_string_lossy()),
+            yaml.clone(),
+            format!("sqlite://{}", sqlite),
+            String::from("mem://templates"),
+        ] {
+            assert!(open(&database, true, None).unwrap().save(&original).is_ok());
+
+            //Loaded items are not filled in from the template.
+            let mut loaded: Wallet = Wallet::new();
+            open(&database, false, None)
+                .unwrap()
+                .load(&mut loaded)
+                .unwrap();
+            assert_eq!(loaded, original, "{}", database);
+
+            let mut cleared: Wallet = original.clone();
+            cleared.get_mut("Bank Accounts").unwrap().set_template(None);
+            assert!(open(&database, true, None).unwrap().save(&cleared).is_ok());
+            let mut loaded: Wallet = Wallet::new();
+            open(&database, false, None)
+                .unwrap()
+                .load(&mut loaded)
+                .unwrap();
+            assert_eq!(loaded, cleared, "{}", database);
+        }
+        assert!(!directory
+            .join("Bank Accounts")
+            .join(TEMPLATE_FILENAME)
+            .exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_file(&yaml).unwrap();
+        fs::remove_file(&sqlite).unwrap();
+    }
+
+    #[test]
+    fn test_nested_categories_round_trip() {
+        let directory = std::env::temp_dir().join(format!("371pass-nested-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let json: String = directory
+            .with_extension("json")
+            .to_string_lossy()
+            .to_string();
+        let sqlite: String = temp_database("nested");
+
+        let mut original: Wallet = Wallet::builder()
+            .entry("Work", "Slack", "password", "hunter2")
+            .entry("Work/AWS/Prod", "root-account", "password", "pass1234")
+            .category("Work/AWS/Dev")
+            .entry("Websites%2FSocial", "Facebook", "password", "pass1234fb")
+            .build();
+        original
+            .get_mut("Work/AWS")
+            .unwrap()
+            .set_template(Some(Template::new().field(Field::new("password"))));
+        let mut pruned: Wallet = original.clone();
+        assert!(pruned.delete_category("Work/AWS/Prod"));
+
+        for database in [
+            format!("dir://{}", directory.to_string_lossy()),
+            json.clone(),
+            format!("sqlite://{}", sqlite),
+            String::from("mem://nested"),
+        ] {
+            for expected in [&original, &pruned] {
+                assert!(open(&database, true, None).unwrap().save(expected).is_ok());
+                let mut loaded: Wallet = Wallet::new();
+                open(&database, false, None)
+                    .unwrap()
+                    .load(&mut loaded)
+                    .unwrap();
+                assert_eq!(&loaded, expected, "{}", database);
+                assert_eq!(loaded.ids(), expected.ids(), "{}", database);
+                assert!(!loaded.has_new_ids());
+            }
+        }
+        assert!(directory.join("Work").join("Slack.json").exists());
+        assert!(directory
+            .join("Work")
+            .join("AWS")
+            .join("Dev")
+            .join(KEEP_FILENAME)
+            .exists());
+        assert!(!directory.join("Work").join("AWS").join("Prod").exists());
+        assert!(directory
+            .join("Websites%2FSocial")
+            .join("Facebook.json")
+            .exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_file(&json).unwrap();
+        fs::remove_file(&sqlite).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_category_names_escaped() {
+        let filename: String = temp_database("legacy-names");
+        let connection: Connection = Connection::open(&filename).unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO categories (name) VALUES ('Websites/Social'), ('100%');
+                 INSERT INTO items (category_id, name) VALUES (1, 'Facebook');",
+            )
+            .unwrap();
+        drop(connection);
+
+        //Categories from before sub-categories stay top-level, and are only escaped once.
+        for _ in 0..2 {
+            let mut loaded: Wallet = Wallet::new();
+            open(&filename, false, None)
+                .unwrap()
+                .load(&mut loaded)
+                .unwrap();
+            let names: Vec<&String> = loaded.categories().map(Category::get_ident).collect();
+            assert_eq!(names, ["100%", "Websites/Social"]);
+            assert!(loaded.get("Websites%2FSocial/").is_none());
+            assert!(loaded
+                .get("Websites%2FSocial")
+                .unwrap()
+                .get("Facebook")
+                .is_some());
+        }
+
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_file_name_escaping() {
+        for ident in ["Websites", "a/b\\c:d", ".hidden", "100%", "%2F"] {
+            assert_eq!(decode_file_name(&encode_file_name(ident)), ident);
+        }
+        assert_eq!(encode_file_name(".hidden"), "%2Ehidden");
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let original: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        let mut store: Box<dyn WalletStore> = open("mem://test-memory-store", true, None).unwrap();
+        assert!(store.load(&mut Wallet::new()).is_err());
+        assert!(store.save(&original).is_ok());
+
+        //The wallet outlives the store that saved it.
+        let mut loaded: Wallet = Wallet::new();
+        assert!(open("mem://test-memory-store", false, None)
+            .unwrap()
+            .load(&mut loaded)
+            .is_ok());
+        assert_eq!(loaded, original);
+    }
+
+    #[test]
+    fn test_msgpack_detected_by_magic_bytes() {
+        let original: Wallet = wallet(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        let path = std::env::temp_dir().join(format!("371pass-msgpack-{}", std::process::id()));
+        let filename: &str = path.to_str().unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(FileStore::new(filename, FileFormat::MessagePack)
+            .save(&original)
+            .is_ok());
+
+        //Without an extension the file would otherwise be read, and saved again, as JSON.
+        let mut loaded: Wallet = Wallet::new();
+        assert!(loaded.load(filename).is_ok());
+        assert_eq!(loaded, original);
+        assert!(loaded.save(filename).is_ok());
+        assert!(fs::read(&path).unwrap().starts_with(format::MSGPACK_MAGIC));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut store: Box<dyn WalletStore> = open("mem://test-lock", true, None).unwrap();
+        let lock: Lock = store.lock().unwrap();
+        let result = store.lock();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::WouldBlock);
+        drop(lock);
+        assert!(store.lock().is_ok());
+
+        let path = std::env::temp_dir().join(format!("371pass-lock-{}.json", std::process::id()));
+        let filename: String = path.to_string_lossy().to_string();
+        let mut store: Box<dyn WalletStore> = open(&filename, true, None).unwrap();
+        let lock: Lock = store.lock().unwrap();
+        assert!(Path::new(&format!("{}.lock", filename)).exists());
+        assert!(open(&filename, true, None).unwrap().lock().is_err());
+        drop(lock);
+        assert!(!Path::new(&format!("{}.lock", filename)).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stale_lock() {
+        let path = std::env::temp_dir().join(format!("371pass-stale-{}.json", process::id()));
+        let filename: String = path.to_string_lossy().to_string();
+        let mut store: Box<dyn WalletStore> = open(&filename, true, None).unwrap();
+
+        //A lock file left by a process that has exited is taken over.
+        let mut exited = process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        fs::write(format!("{}.lock", filename), exited.id().to_string()).unwrap();
+        let lock: Lock = store.lock().unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}.lock", filename)).unwrap(),
+            format!("{}\n", process::id())
+        );
+        drop(lock);
+
+        //Whatever PID it names, only a file another process has locked is in use.
+        fs::write(format!("{}.lock", filename), "1").unwrap();
+        let lock: Lock = store.lock().unwrap();
+        let error: Error = open(&filename, true, None).unwrap().lock().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::WouldBlock);
+        assert!(error.to_string().contains(&format!("({})", process::id())));
+        drop(lock);
+        assert!(!Path::new(&format!("{}.lock", filename)).exists());
+    }
+}
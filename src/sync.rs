@@ -0,0 +1,496 @@
+use crate::{
+    category,
+    item::Item,
+    merge::{self, Conflict},
+    reference,
+    store::{self, DirectoryStore, WalletStore},
+    wallet::Wallet,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+    process::{Command, Output},
+};
+
+/// What `sync` did, for the caller to show.
+#[derive(Default)]
+pub(crate) struct Synced {
+    /// The steps taken, such as the commit made and where it was pushed.
+    pub(crate) messages: Vec<String>,
+    /// The changes that could not be merged automatically.
+    pub(crate) conflicts: Vec<Conflict>,
+}
+
+/// Commits local changes to a `dir://` wallet, merges in the remote's changes and pushes.
+///
+/// Commit messages only name the items that were added, updated or deleted,
+/// never their contents. Items changed on both sides are merged entry by entry
+/// with `merge::three_way` rather than left as text conflicts, and the
+/// conflicts it could not resolve automatically are returned.
+///
+/// A wallet's integrity tag is kept next to its directory, outside the repository,
+/// so the remote's changes cannot be checked against the key. If the wallet has
+/// one, `merge_unchecked` must be set for them to be merged in at all.
+///
+/// With `keep_references`, a merge that would break references is undone before
+/// anything is pushed.
+pub(crate) fn sync(
+    directory: &str,
+    remote: &str,
+    merge_unchecked: bool,
+    keep_references: bool,
+) -> Result<Synced, Error> {
+    git(directory, &["rev-parse", "--show-toplevel"]).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Error: {} is not in a git repository.", directory),
+        )
+    })?;
+    let branch: String = git(directory, &["symbolic-ref", "--short", "HEAD"])?;
+
+    let mut synced: Synced = Synced::default();
+    if let Some(subject) = commit_local_changes(directory)? {
+        synced.messages.push(format!("Committed: {}", subject));
+    }
+
+    git(directory, &["fetch", remote])?;
+    let remote_branch: String = format!("{}/{}", remote, branch);
+    if succeeds(
+        directory,
+        &["rev-parse", "--verify", "--quiet", &remote_branch],
+    )? {
+        let incoming: bool = !succeeds(directory, &["rev-parse", "--verify", "--quiet", "HEAD"])?
+            || !git(
+                directory,
+                &["rev-list", &format!("HEAD..{}", remote_branch)],
+            )?
+            .is_empty();
+        if incoming && !merge_unchecked {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Error: the changes on {} cannot be checked against the MAC key. Use --accept-tampered to merge them and sign them with it anyway.",
+                    remote_branch
+                ),
+            ));
+        }
+        let head: Option<String> = git(directory, &["rev-parse", "--verify", "--quiet", "HEAD"])
+            .ok()
+            .filter(|_| keep_references);
+        let before: Option<Wallet> = head.as_ref().map(|_| load(directory)).transpose()?;
+        merge_remote_branch(directory, &remote_branch, &mut synced)?;
+        if let (Some(head), Some(before)) = (head, before) {
+            let checked: Result<(), Error> =
+                load(directory).and_then(|after| reference::check_not_broken(&before, &after));
+            if let Err(error) = checked {
+                git(directory, &["reset", "--quiet", "--keep", &head])?;
+                return Err(error);
+            }
+        }
+    }
+
+    if succeeds(directory, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+        git(directory, &["push", remote, &format!("HEAD:{}", branch)])?;
+        synced
+            .messages
+            .push(format!("Pushed to {}.", remote_branch));
+    }
+    Ok(synced)
+}
+
+/// The wallet as it is in the working tree, unchecked.
+fn load(directory: &str) -> Result<Wallet, Error> {
+    let mut wallet: Wallet = Wallet::new();
+    DirectoryStore::new(directory).load(&mut wallet)?;
+    Ok(wallet)
+}
+
+/// Stages everything under the wallet directory and commits it, returning the subject.
+fn commit_local_changes(directory: &str) -> Result<Option<String>, Error> {
+    git(directory, &["add", "--all", "--", "."])?;
+    let status: String = git(
+        directory,
+        &[
+            "diff",
+            "--cached",
+            "--name-status",
+            "--no-renames",
+            "--relative",
+            "-z",
+        ],
+    )?;
+
+    let fields: Vec<&str> = status
+        .split('\0')
+        .filter(|field| !field.is_empty())
+        .collect();
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let changes: Vec<String> = fields
+        .chunks(2)
+        .filter_map(|change| match change {
+            [status, path] => describe_change(status, path),
+            _ => None,
+        })
+        .collect();
+
+    let subject: String = match changes.as_slice() {
+        [] => String::from("371pass: update IDs and templates"),
+        [change] => format!("371pass: {}", change),
+        changes => format!("371pass: {} changes", changes.len()),
+    };
+    let message: String = format!("{}\n\n{}", subject, changes.join("\n"));
+    git(directory, &["commit", "--quiet", "--message", &message])?;
+    Ok(Some(subject))
+}
+
+/// Names the item a changed file holds, e.g. `update Websites/Google`.
+fn describe_change(status: &str, path: &str) -> Option<String> {
+    let action: &str = match status {
+        "A" => "add",
+        "D" => "delete",
+        _ => "update",
+    };
+    let (category, item) = item_path(path)?;
+    Some(format!("{} {}/{}", action, category, item))
+}
+
+/// The category path and item identifier of a `<category>/<item>.json` path,
+/// where the category may be a sub-category such as `Work/AWS`. Files starting
+/// with `.`, such as `.template.json`, are never items.
+fn item_path(path: &str) -> Option<(String, String)> {
+    let (directories, file) = path.rsplit_once('/')?;
+    if file.starts_with('.') {
+        return None;
+    }
+    let item: String = store::json_file_stem(Path::new(file))?;
+    let category: String = directories.split('/').fold(String::new(), |parent, name| {
+        category::join_path(&parent, &store::decode_file_name(name))
+    });
+    Some((category, item))
+}
+
+fn merge_remote_branch(
+    directory: &str,
+    remote_branch: &str,
+    synced: &mut Synced,
+) -> Result<(), Error> {
+    let merge: Output = run_git(
+        directory,
+        &[
+            "merge",
+            "--quiet",
+            "--no-edit",
+            "--allow-unrelated-histories",
+            remote_branch,
+        ],
+    )?;
+    if merge.status.success() {
+        return Ok(());
+    }
+    if git(directory, &["ls-files", "--unmerged"])?.is_empty() {
+        return Err(Error::other(format!(
+            "Error: git merge failed: {}",
+            String::from_utf8_lossy(&merge.stderr).trim()
+        )));
+    }
+
+    let resolved = resolve_conflicts(directory).and_then(|conflicts| {
+        git(directory, &["commit", "--quiet", "--no-edit"])?;
+        Ok(conflicts)
+    });
+    match resolved {
+        Ok(conflicts) => {
+            synced.messages.push(format!("Merged {}.", remote_branch));
+            synced.conflicts = conflicts;
+            Ok(())
+        }
+        Err(error) => {
+            let _ = git(directory, &["merge", "--abort"]);
+            Err(error)
+        }
+    }
+}
+
+/// Merges each conflicting item file from its base, our and their versions. When
+/// both sides gave a category an ID, as when both added it, ours is kept.
+fn resolve_conflicts(directory: &str) -> Result<Vec<Conflict>, Error> {
+    //Each line is "<mode> <object> <stage>\t<path>" for the base (1), ours (2) and theirs (3).
+    let unmerged: String = git(directory, &["ls-files", "--unmerged", "-z"])?;
+    let mut stages: BTreeMap<String, [Option<String>; 3]> = BTreeMap::new();
+    for line in unmerged.split('\0').filter(|line| !line.is_empty()) {
+        let (info, path) = line.split_once('\t').unwrap_or_default();
+        let info: Vec<&str> = info.split(' ').collect();
+        if let (Some(object), Some(stage)) = (info.get(1), info.get(2)) {
+            let stage: usize = stage.parse::<usize>().unwrap_or_default();
+            if (1..=3).contains(&stage) {
+                stages.entry(String::from(path)).or_default()[stage - 1] =
+                    Some(String::from(*object));
+            }
+        }
+    }
+
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    for (path, [base, ours, theirs]) in stages {
+        let file: Option<&str> = path.rsplit('/').next();
+        if file == Some(store::ID_FILENAME) || file == Some(store::LEGACY_IDS_FILENAME) {
+            let contents: String = match ours.as_ref().or(theirs.as_ref()) {
+                Some(object) => git(directory, &["cat-file", "blob", object])?,
+                None => {
+                    git(directory, &["rm", "--quiet", "--force", "--", &path])?;
+                    continue;
+                }
+            };
+            fs::write(
+                Path::new(directory).join(&path),
+                format!("{}\n", contents.trim_end()),
+            )?;
+            git(directory, &["add", "--", &path])?;
+            continue;
+        }
+
+        let (category, item) = item_path(&path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Error: cannot merge {}, it is not an item file.", path),
+            )
+        })?;
+
+        let side = |object: &Option<String>| -> Result<Wallet, Error> {
+            let mut wallet: Wallet = Wallet::new();
+            let mut contents: Item = match object {
+                Some(object) => {
+                    let contents: String = git(directory, &["cat-file", "blob", object])?;
+                    serde_json::from_str(&contents)
+                        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?
+                }
+                None => return Ok(wallet),
+            };
+            contents.set_ident(&item);
+            wallet.new_category(&category).add_item(&contents);
+            Ok(wallet)
+        };
+        let (merged, item_conflicts) =
+            merge::three_way(&side(&base)?, &side(&ours)?, &side(&theirs)?);
+        conflicts.extend(item_conflicts);
+
+        match merged
+            .get(&category)
+            .and_then(|merged_category| merged_category.get(&item))
+        {
+            Some(merged_item) => {
+                let contents: String = store::item_file_contents(merged_item)?;
+                fs::write(Path::new(directory).join(&path), contents)?;
+                git(directory, &["add", "--", &path])?;
+            }
+            None => {
+                git(directory, &["rm", "--quiet", "--force", "--", &path])?;
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Runs git in `directory`, returning its standard output or an error with its standard error.
+fn git(directory: &str, args: &[&str]) -> Result<String, Error> {
+    let output: Output = run_git(directory, args)?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "Error: git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn succeeds(directory: &str, args: &[&str]) -> Result<bool, Error> {
+    Ok(run_git(directory, args)?.status.success())
+}
+
+fn run_git(directory: &str, args: &[&str]) -> Result<Output, Error> {
+    Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|error| Error::new(error.kind(), format!("Error: could not run git: {}", error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{_371pass::app, integrity::Integrity};
+
+    fn git_ok(directory: &Path, args: &[&str]) -> String {
+        let output: Output = run_git(&directory.to_string_lossy(), args).unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn clone(root: &Path, name: &str) -> String {
+        git_ok(root, &["clone", "--quiet", "remote.git", name]);
+        let clone = root.join(name);
+        git_ok(&clone, &["config", "user.name", "371pass"]);
+        git_ok(&clone, &["config", "user.email", "371pass@example.com"]);
+        git_ok(&clone, &["config", "commit.gpgsign", "false"]);
+        git_ok(&clone, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+        format!("dir://{}", clone.to_string_lossy())
+    }
+
+    fn save(database: &str, json: &str) {
+        let mut wallet: Wallet = Wallet::new();
+        wallet.load_json(json).unwrap();
+        wallet.save(database).unwrap();
+    }
+
+    fn run_sync(database: &str) {
+        let args = app::Args {
+            database: String::from(database),
+            action: Some(String::from("sync")),
+            ..Default::default()
+        };
+        assert!(app::run(&args).is_ok());
+    }
+
+    #[test]
+    fn test_sync_with_local_remote() {
+        let root = std::env::temp_dir().join(format!("371pass-sync-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        git_ok(
+            &root,
+            &[
+                "init",
+                "--quiet",
+                "--bare",
+                "--initial-branch=main",
+                "remote.git",
+            ],
+        );
+
+        let a: String = clone(&root, "a");
+        save(&a, r#"{"Websites":{"Google":{"password":"pass1234"}}}"#);
+        run_sync(&a);
+        let b: String = clone(&root, "b");
+
+        //Both sides add an entry to the same item, which git alone cannot merge.
+        save(
+            &a,
+            r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/"}}}"#,
+        );
+        save(
+            &b,
+            r#"{"Websites":{"Google":{"password":"pass1234","username":"example@gmail.com"}}}"#,
+        );
+        run_sync(&a);
+        run_sync(&b);
+        run_sync(&a);
+
+        let mut expected: Wallet = Wallet::new();
+        expected
+            .load_json(
+                r#"{"Websites":{"Google":{"password":"pass1234","url":"https://www.google.com/","username":"example@gmail.com"}}}"#,
+            )
+            .unwrap();
+        for database in [&a, &b] {
+            let mut synced: Wallet = Wallet::new();
+            assert!(synced.load(database).is_ok());
+            assert_eq!(synced, expected);
+        }
+
+        //Commit messages name the item but never its contents.
+        let log: String = git_ok(&root.join("a"), &["log", "--format=%B"]);
+        assert!(log.contains("371pass: update Websites/Google"));
+        assert!(!log.contains("pass1234") && !log.contains("example@gmail.com"));
+
+        //Merging in a deletion that breaks a reference is undone unless forced.
+        let mut referring: Wallet = Wallet::new();
+        assert!(referring.load(&a).is_ok());
+        referring
+            .new_category("Dashboards")
+            .new_item("Grafana")
+            .add_entry("password", "{ref:Websites/Google/password}");
+        referring.save(&a).unwrap();
+        save(&b, r#"{"Websites":{}}"#);
+        run_sync(&b);
+        let mut args = app::Args {
+            database: a.clone(),
+            action: Some(String::from("sync")),
+            ..Default::default()
+        };
+        let error: Error = app::run(&args).unwrap_err();
+        assert!(error.to_string().contains("Dashboards/Grafana/password"));
+        let mut kept: Wallet = Wallet::new();
+        assert!(kept.load(&a).is_ok());
+        assert_eq!(kept.entry_at("Websites/Google/password"), Some("pass1234"));
+        args.force = true;
+        assert!(app::run(&args).is_ok());
+        let mut synced: Wallet = Wallet::new();
+        assert!(synced.load(&a).is_ok());
+        assert!(synced.get("Websites").unwrap().get("Google").is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sync_with_mac_key() {
+        let root = std::env::temp_dir().join(format!("371pass-sync-mac-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        git_ok(
+            &root,
+            &[
+                "init",
+                "--quiet",
+                "--bare",
+                "--initial-branch=main",
+                "remote.git",
+            ],
+        );
+        let key: String = root.join("key").to_string_lossy().to_string();
+        fs::write(&key, "secret").unwrap();
+        let sync_signed = |database: &str, accept_tampered: bool| {
+            app::run(&app::Args {
+                database: String::from(database),
+                action: Some(String::from("sync")),
+                mac_key: Some(key.clone()),
+                accept_tampered,
+                ..Default::default()
+            })
+        };
+
+        let a: String = clone(&root, "a");
+        let mut signed: Wallet = Wallet::new();
+        signed
+            .load_json(r#"{"Websites":{"Google":{"password":"pass1234"}}}"#)
+            .unwrap();
+        signed.set_integrity(Integrity::new(b"secret"));
+        signed.save(&a).unwrap();
+        assert!(sync_signed(&a, false).is_ok());
+
+        let b: String = clone(&root, "b");
+        save(&b, r#"{"Websites":{"Google":{"password":"changed"}}}"#);
+        run_sync(&b);
+
+        //Their changes are unsigned, so they are only merged in and signed when accepted.
+        let error: Error = sync_signed(&a, false).unwrap_err();
+        assert!(error.to_string().contains("--accept-tampered"));
+        let mut loaded: Wallet = Wallet::new();
+        loaded.set_integrity(Integrity::new(b"secret"));
+        assert!(loaded.load(&a).is_ok());
+        assert_eq!(loaded, signed);
+
+        assert!(sync_signed(&a, true).is_ok());
+        let mut loaded: Wallet = Wallet::new();
+        loaded.set_integrity(Integrity::new(b"secret"));
+        assert!(loaded.load(&a).is_ok());
+        assert_eq!(loaded.entry_at("Websites/Google/password"), Some("changed"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{entry::EntryKind, item::Item};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The fields every item in a category is expected to have.
+///
+/// New items in the category start with every field, set to its default or
+/// generated value, or left empty for the user to fill in.
+///
+/// ```
+/// use csc371_remake::{EntryKind, Field, Template, Wallet};
+///
+/// let template: Template = Template::new()
+///     .field(Field::new("Account Number").kind(EntryKind::Number).required())
+///     .field(Field::new("Sort Code").required())
+///     .field(Field::new("Name").default_value("Mr John Doe"));
+///
+/// let mut wallet: Wallet = Wallet::new();
+/// let category = wallet.new_category("Bank Accounts");
+/// category.set_template(Some(template.clone()));
+/// let item = category.new_item("Starling");
+/// assert_eq!(item.get("Name"), Some("Mr John Doe"));
+/// assert_eq!(item.kind("Account Number"), Some(EntryKind::Number));
+///
+/// let missing: Vec<String> = template.violations(item).iter().map(|v| v.to_string()).collect();
+/// assert_eq!(missing, ["Account Number is required", "Sort Code is required"]);
+/// ```
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    fields: Vec<Field>,
+}
+
+/// One field of a template, named by the entry key it describes.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    name: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    kind: Option<EntryKind>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generate: Option<Generator>,
+}
+
+/// Fills in a field of a new item when the template has no fixed default for it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generator {
+    /// A random password of `PASSWORD_LENGTH` letters, digits and symbols.
+    Password,
+    /// The date the item was created, as `YYYY-MM-DD`.
+    Today,
+}
+
+const PASSWORD_LENGTH: usize = 20;
+const PASSWORD_CHARACTERS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!#$%&*+-=?@^_~";
+
+/// A way in which an item does not match its category's template.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Violation {
+    pub field: String,
+    pub problem: Problem,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Problem {
+    /// The field is required, but the item has no entry for it or the entry is empty.
+    Missing,
+    /// The entry has a different kind from the one the template gives the field.
+    WrongKind(EntryKind),
+    /// The value is not well formed for the field's kind.
+    Invalid(EntryKind),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problem {
+            Problem::Missing => write!(f, "{} is required", self.field),
+            Problem::WrongKind(kind) => write!(f, "{} should be a {} entry", self.field, kind),
+            Problem::Invalid(kind) => write!(f, "{} is not a valid {}", self.field, kind),
+        }
+    }
+}
+
+impl Template {
+    pub fn new() -> Template {
+        Template::default()
+    }
+
+    /// Adds a field, replacing any earlier field with the same name.
+    pub fn field(mut self, field: Field) -> Template {
+        self.fields.retain(|existing| existing.name != field.name);
+        self.fields.push(field);
+        self
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &Field> + '_ {
+        self.fields.iter()
+    }
+
+    /// Gives a new item every field, with its kind and its default or generated value.
+    pub(crate) fn apply(&self, item: &mut Item) {
+        for field in self.fields.iter() {
+            let value: String = match (&field.default, field.generate) {
+                (Some(default), _) => default.clone(),
+                (None, Some(generator)) => generator.generate(),
+                (None, None) => String::new(),
+            };
+            item.add_entry(&field.name, &value);
+            item.set_kind(&field.name, field.kind);
+        }
+    }
+
+    /// The ways `item` does not match this template, in field order. Entries the
+    /// template does not mention are allowed, and empty optional fields are not checked.
+    pub fn violations(&self, item: &Item) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = Vec::new();
+        for field in self.fields.iter() {
+            let problem: Option<Problem> = match (item.get(&field.name), field.kind) {
+                (None, _) | (Some(""), _) if field.required => Some(Problem::Missing),
+                (None, _) | (Some(""), _) | (Some(_), None) => None,
+                (Some(_), Some(kind)) if item.kind(&field.name) != Some(kind) => {
+                    Some(Problem::WrongKind(kind))
+                }
+                (Some(value), Some(kind)) => {
+                    kind.validate(value).err().map(|_| Problem::Invalid(kind))
+                }
+            };
+            if let Some(problem) = problem {
+                violations.push(Violation {
+                    field: field.name.clone(),
+                    problem,
+                });
+            }
+        }
+        violations
+    }
+}
+
+impl Field {
+    pub fn new(name: &str) -> Field {
+        Field {
+            name: String::from(name),
+            kind: None,
+            required: false,
+            default: None,
+            generate: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(mut self, kind: EntryKind) -> Field {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn required(mut self) -> Field {
+        self.required = true;
+        self
+    }
+
+    pub fn default_value(mut self, value: &str) -> Field {
+        self.default = Some(String::from(value));
+        self
+    }
+
+    pub fn generate(mut self, generator: Generator) -> Field {
+        self.generate = Some(generator);
+        self
+    }
+}
+
+impl Generator {
+    fn generate(&self) -> String {
+        match self {
+            Generator::Password => (0..PASSWORD_LENGTH)
+                .map(|_| {
+                    char::from(
+                        PASSWORD_CHARACTERS[rand::random_range(0..PASSWORD_CHARACTERS.len())],
+                    )
+                })
+                .collect(),
+            Generator::Today => {
+                let (year, month, day) = crate::entry::today();
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_documents() {
+        let template: Template = serde_json::from_str(
+            r#"{"fields":[{"name":"password","type":"secret","required":true,"generate":"password"},{"name":"url","type":"url"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template,
+            Template::new()
+                .field(
+                    Field::new("password")
+                        .kind(EntryKind::Secret)
+                        .required()
+                        .generate(Generator::Password)
+                )
+                .field(Field::new("url").kind(EntryKind::Url))
+        );
+        assert!(serde_json::from_str::<Template>(r#"{"fields":[{"name":"a","typo":1}]}"#).is_err());
+
+        //Generated values differ between items.
+        let mut first: Item = Item::new(String::from("Google"));
+        let mut second: Item = Item::new(String::from("Twitter"));
+        template.apply(&mut first);
+        template.apply(&mut second);
+        assert_eq!(first.get("password").unwrap().len(), PASSWORD_LENGTH);
+        assert_ne!(first.get("password"), second.get("password"));
+        assert_eq!(first.get("url"), Some(""));
+        assert!(template.violations(&first).is_empty());
+
+        //Optional fields are only checked once they have a value.
+        first.add_entry("url", "www.google");
+        assert_eq!(
+            template.violations(&first),
+            [Violation {
+                field: String::from("url"),
+                problem: Problem::Invalid(EntryKind::Url)
+            }]
+        );
+        first.set_kind("url", None);
+        assert_eq!(
+            template.violations(&first)[0].problem,
+            Problem::WrongKind(EntryKind::Url)
+        );
+        assert!(first.delete_entry("password"));
+        assert_eq!(
+            template.violations(&first)[0].to_string(),
+            "password is required"
+        );
+    }
+}
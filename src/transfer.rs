@@ -0,0 +1,425 @@
+use crate::{
+    category::{self, Category},
+    item::Item,
+    wallet::Wallet,
+};
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+use uuid::Uuid;
+
+/// What `mv` and `cp` do when the destination already has an item or category
+/// with the same name.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub(crate) enum Collision {
+    /// Leave the wallet as it is and return an error.
+    #[default]
+    Fail,
+    /// Replace what is at the destination.
+    Overwrite,
+    /// Merge into what is at the destination, the incoming entries winning.
+    Merge,
+    /// Add ` (2)`, ` (3)` and so on to the name until it is free.
+    Suffix,
+}
+
+impl FromStr for Collision {
+    type Err = Error;
+
+    fn from_str(collision: &str) -> Result<Collision, Error> {
+        match collision {
+            "fail" => Ok(Collision::Fail),
+            "overwrite" => Ok(Collision::Overwrite),
+            "merge" => Ok(Collision::Merge),
+            "suffix" => Ok(Collision::Suffix),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Error: unknown collision handling \"{}\", expected fail, overwrite, merge or suffix.",
+                    collision
+                ),
+            )),
+        }
+    }
+}
+
+/// Moves, or with `copy` copies, an item into the category at `to_cat_path`,
+/// creating the category if there is none. Returns the item's new path. A copy
+/// gets a new ID.
+///
+/// On any error the wallet is left untouched.
+pub(crate) fn transfer_item(
+    wallet: &mut Wallet,
+    cat_path: &str,
+    item_ident: &str,
+    to_cat_path: &str,
+    collision: Collision,
+    copy: bool,
+) -> Result<String, Error> {
+    let cat_path: String = wallet.resolve_path(cat_path);
+    let to_cat_path: String = wallet.resolve_path(to_cat_path);
+    let (cat_path, to_cat_path): (&str, &str) = (&cat_path, &to_cat_path);
+    let mut item: Item = wallet
+        .get(cat_path)
+        .ok_or_else(|| not_found("category", cat_path))?
+        .get(item_ident)
+        .ok_or_else(|| not_found("item", &category::join_path(cat_path, item_ident)))?
+        .clone();
+    let item_ident: &str = &item.get_ident().clone();
+    //Moving an item onto itself changes nothing.
+    if cat_path == to_cat_path && !copy {
+        return Ok(category::join_path(cat_path, item_ident));
+    }
+
+    let destination: Option<&Category> = wallet.get(to_cat_path);
+    let taken = |name: &str| destination.is_some_and(|category| category.get(name).is_some());
+    let name: String = match collision {
+        Collision::Fail if taken(item_ident) => {
+            return Err(already_exists(&category::join_path(
+                to_cat_path,
+                item_ident,
+            )))
+        }
+        Collision::Suffix => free_name(item_ident, taken),
+        _ => String::from(item_ident),
+    };
+
+    if !copy {
+        wallet
+            .get_mut(cat_path)
+            .map(|category| category.delete_item(item_ident));
+    }
+    item.set_ident(&name);
+    if copy {
+        item.set_id(Uuid::new_v4());
+    }
+    let destination: &mut Category = wallet.new_category(to_cat_path);
+    match (collision, destination.get_mut(&name)) {
+        (Collision::Merge, Some(existing)) => existing.merge_entries(&mut item),
+        _ => {
+            destination.delete_item(&name);
+            destination.add_item(&item);
+        }
+    }
+    Ok(category::join_path(to_cat_path, &name))
+}
+
+/// Moves, or with `copy` copies, the category at `cat_path` to `to_path`, with
+/// its items and sub-categories, adding any missing parents. Returns the path it
+/// ended up at. A copy and everything in it get new IDs.
+///
+/// On any error the wallet is left untouched.
+pub(crate) fn transfer_category(
+    wallet: &mut Wallet,
+    cat_path: &str,
+    to_path: &str,
+    collision: Collision,
+    copy: bool,
+) -> Result<String, Error> {
+    let cat_path: String = wallet.resolve_path(cat_path);
+    let to_path: String = wallet.resolve_path(to_path);
+    let (cat_path, to_path): (&str, &str) = (&cat_path, &to_path);
+    let mut moved: Category = wallet
+        .get(cat_path)
+        .ok_or_else(|| not_found("category", cat_path))?
+        .clone();
+    if copy {
+        moved.renew_ids();
+    }
+    if cat_path == to_path && !copy {
+        return Ok(String::from(cat_path));
+    }
+    if !copy && to_path.starts_with(&format!("{}/", cat_path)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Error: a category cannot be moved into one of its own sub-categories.",
+        ));
+    }
+
+    let to_path: String = match collision {
+        Collision::Fail if wallet.get(to_path).is_some() => {
+            return Err(already_exists(to_path));
+        }
+        Collision::Suffix => {
+            let (parent, name) = match to_path.rsplit_once('/') {
+                Some((parent, name)) => (parent, name),
+                None => ("", to_path),
+            };
+            let name: String = category::unescape_name(name);
+            let free: String = free_name(&name, |name| {
+                wallet.get(&category::join_path(parent, name)).is_some()
+            });
+            category::join_path(parent, &free)
+        }
+        _ => String::from(to_path),
+    };
+
+    if !copy {
+        wallet.remove_category(cat_path);
+    }
+    match (collision, wallet.get_mut(&to_path)) {
+        (Collision::Merge, Some(existing)) => existing.merge_items(&mut moved),
+        _ => {
+            wallet.insert_category(&to_path, moved);
+        }
+    }
+    Ok(to_path)
+}
+
+/// `name` itself if it is free, or else the first of `name (2)`, `name (3)`, ... that is.
+fn free_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    (1..)
+        .map(|number| match number {
+            1 => String::from(name),
+            number => format!("{} ({})", name, number),
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+fn not_found(what: &str, path: &str) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("Error: {} {} not found.", what, path),
+    )
+}
+
+fn already_exists(path: &str) -> Error {
+    Error::new(
+        ErrorKind::AlreadyExists,
+        format!(
+            "Error: {} already exists. Choose how to handle it with --on-collision.",
+            path
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet() -> Wallet {
+        Wallet::builder()
+            .entry("Websites", "Google", "password", "pass1234")
+            .entry("Websites", "Google", "url", "https://www.google.com/")
+            .entry("Personal", "Google", "password", "oldpass")
+            .entry("Personal", "Google", "username", "example@gmail.com")
+            .entry("Work/AWS", "root-account", "password", "awspass")
+            .build()
+    }
+
+    #[test]
+    fn test_item_collisions() {
+        //By default nothing is overwritten, and the wallet is left as it was.
+        let mut w: Wallet = wallet();
+        let error: Error = transfer_item(
+            &mut w,
+            "Websites",
+            "Google",
+            "Personal",
+            Collision::Fail,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(w, wallet());
+
+        let mut w: Wallet = wallet();
+        let path: String = transfer_item(
+            &mut w,
+            "Websites",
+            "Google",
+            "Personal",
+            Collision::Overwrite,
+            false,
+        )
+        .unwrap();
+        assert_eq!(path, "Personal/Google");
+        assert!(w.get("Websites").unwrap().empty());
+        assert_eq!(w.get("Personal").unwrap().get("Google").unwrap().size(), 2);
+        assert_eq!(w.entry_at("Personal/Google/username"), None);
+
+        let mut w: Wallet = wallet();
+        transfer_item(
+            &mut w,
+            "Websites",
+            "Google",
+            "Personal",
+            Collision::Merge,
+            true,
+        )
+        .unwrap();
+        assert_eq!(w.entry_at("Websites/Google/password"), Some("pass1234"));
+        assert_eq!(w.entry_at("Personal/Google/password"), Some("pass1234"));
+        assert_eq!(
+            w.entry_at("Personal/Google/username"),
+            Some("example@gmail.com")
+        );
+
+        let mut w: Wallet = wallet();
+        for expected in ["Personal/Google (2)", "Personal/Google (3)"] {
+            let path: String = transfer_item(
+                &mut w,
+                "Websites",
+                "Google",
+                "Personal",
+                Collision::Suffix,
+                true,
+            )
+            .unwrap();
+            assert_eq!(path, expected);
+        }
+        assert_eq!(w.get("Personal").unwrap().size(), 3);
+
+        //Copying an item into its own category needs a new name.
+        let mut w: Wallet = wallet();
+        let path: String = transfer_item(
+            &mut w,
+            "Websites",
+            "Google",
+            "Websites",
+            Collision::Suffix,
+            true,
+        )
+        .unwrap();
+        assert_eq!(path, "Websites/Google (2)");
+        assert_eq!(
+            transfer_item(
+                &mut w,
+                "Websites",
+                "Google",
+                "Websites",
+                Collision::Fail,
+                false
+            )
+            .unwrap(),
+            "Websites/Google"
+        );
+    }
+
+    #[test]
+    fn test_moving_to_new_categories() {
+        let mut w: Wallet = wallet();
+        transfer_item(
+            &mut w,
+            "Work/AWS",
+            "root-account",
+            "Cloud/Amazon",
+            Collision::Fail,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            w.entry_at("Cloud/Amazon/root-account/password"),
+            Some("awspass")
+        );
+        assert!(w.get("Work/AWS").unwrap().empty());
+
+        assert_eq!(
+            transfer_item(
+                &mut w,
+                "Nowhere",
+                "Google",
+                "Personal",
+                Collision::Fail,
+                false
+            )
+            .unwrap_err()
+            .kind(),
+            ErrorKind::NotFound
+        );
+        assert!(transfer_item(
+            &mut w,
+            "Websites",
+            "Twitter",
+            "Personal",
+            Collision::Fail,
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_category_collisions() {
+        let mut w: Wallet = wallet();
+        assert!(transfer_category(&mut w, "Websites", "Personal", Collision::Fail, false).is_err());
+        assert!(transfer_category(&mut w, "Work", "Work/Old", Collision::Fail, false).is_err());
+        assert_eq!(w, wallet());
+
+        let path: String =
+            transfer_category(&mut w, "Websites", "Personal", Collision::Suffix, false).unwrap();
+        assert_eq!(path, "Personal (2)");
+        assert_eq!(w.get("Personal (2)").unwrap().get_ident(), "Personal (2)");
+        assert!(w.get("Websites").is_none());
+
+        let mut w: Wallet = wallet();
+        transfer_category(&mut w, "Personal", "Websites", Collision::Merge, false).unwrap();
+        assert_eq!(w.entry_at("Websites/Google/password"), Some("oldpass"));
+        assert_eq!(
+            w.entry_at("Websites/Google/url"),
+            Some("https://www.google.com/")
+        );
+
+        let mut w: Wallet = wallet();
+        transfer_category(&mut w, "Websites", "Personal", Collision::Overwrite, true).unwrap();
+        assert_eq!(w.get("Personal").unwrap(), w.get("Websites").unwrap());
+
+        //Copies are snapshots, so a category can be copied into itself.
+        let mut w: Wallet = wallet();
+        transfer_category(&mut w, "Work", "Work/Backup", Collision::Fail, true).unwrap();
+        assert_eq!(
+            w.entry_at("Work/Backup/AWS/root-account/password"),
+            Some("awspass")
+        );
+        assert!(w.get("Work/Backup/Backup").is_none());
+    }
+
+    #[test]
+    fn test_ids_of_moves_and_copies() {
+        let mut w: Wallet = wallet();
+        let aws: Uuid = w.get("Work/AWS").unwrap().id();
+        let root: Uuid = w.get("Work/AWS").unwrap().get("root-account").unwrap().id();
+
+        //Moving keeps the IDs, and either end can be given by ID.
+        let path: String = transfer_category(
+            &mut w,
+            &format!("@{}", aws),
+            "Cloud",
+            Collision::Fail,
+            false,
+        )
+        .unwrap();
+        assert_eq!(path, "Cloud");
+        assert_eq!(w.get("Cloud").unwrap().id(), aws);
+        let path: String = transfer_item(
+            &mut w,
+            "Cloud",
+            &format!("@{}", root),
+            &format!("@{}", aws),
+            Collision::Fail,
+            false,
+        )
+        .unwrap();
+        assert_eq!(path, "Cloud/root-account");
+
+        //Copies get IDs of their own, so that the original can still be found by its ID.
+        transfer_category(&mut w, "Cloud", "Backup", Collision::Fail, true).unwrap();
+        let backup: &Category = w.get("Backup").unwrap();
+        assert_ne!(backup.id(), aws);
+        assert_ne!(backup.get("root-account").unwrap().id(), root);
+        transfer_item(
+            &mut w,
+            "Cloud",
+            "root-account",
+            "Cloud",
+            Collision::Suffix,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            w.find_item(&format!("@{}", root)).unwrap().1.get_ident(),
+            "root-account"
+        );
+    }
+}
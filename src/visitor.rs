@@ -0,0 +1,34 @@
+/// Visits every category, item and entry of a wallet in order, with mutable
+/// access to entry values. Passed to `Wallet::accept`. Categories are named by
+/// their path, so sub-categories are told apart from top-level ones.
+///
+/// Identifiers are read-only because they are also the keys the model is
+/// stored under.
+///
+/// ```
+/// use csc371_remake::{Visitor, Wallet};
+///
+/// struct Trim;
+///
+/// impl Visitor for Trim {
+///     fn visit_entry(&mut self, _category: &str, _item: &str, _key: &str, value: &mut String) {
+///         *value = value.trim().to_string();
+///     }
+/// }
+///
+/// let mut wallet: Wallet = Wallet::builder()
+///     .entry("Websites", "Google", "password", " pass1234\n")
+///     .build();
+/// wallet.accept(&mut Trim);
+/// assert_eq!(
+///     wallet.walk().collect::<Vec<_>>(),
+///     [(String::from("Websites"), "Google", "password", "pass1234")]
+/// );
+/// ```
+pub trait Visitor {
+    fn visit_category(&mut self, _category: &str) {}
+
+    fn visit_item(&mut self, _category: &str, _item: &str) {}
+
+    fn visit_entry(&mut self, category: &str, item: &str, key: &str, value: &mut String);
+}
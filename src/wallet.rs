@@ -1,77 +1,506 @@
-use crate::category::Category;
-use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
-use serde_json::Value;
-use std::{collections::BTreeMap, fmt, fs};
-use unescape::unescape;
-
-#[derive(Clone, Eq, Debug, Deserialize)]
-pub(crate) struct Wallet {
+use crate::{
+    category::{self, Category},
+    error,
+    integrity::Integrity,
+    item::{Item, WithoutIds},
+    merge::{self, Conflict},
+    reference, store,
+    template::Template,
+    visitor::Visitor,
+};
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, fmt};
+use uuid::Uuid;
+
+/// Serialized as a map of its categories, so any serde format round-trips it.
+///
+/// Categories can hold sub-categories as well as items. A category is addressed
+/// by its path, the names from the top level down joined by `/`, e.g.
+/// `Work/AWS/Prod`, with any `/` or `%` in a name written as `%2F` or `%25`.
+///
+/// Any name in a path can instead be `@` and the start of the category's ID, which
+/// stays the same when it is renamed or moved. A path starting with one can name
+/// a category anywhere in the wallet, e.g. `@3f2a/Prod`.
+///
+/// ```
+/// use csc371_remake::Wallet;
+///
+/// let wallet: Wallet = Wallet::builder()
+///     .entry("Work/AWS/Prod", "root-account", "password", "pass1234")
+///     .entry("Work", "Slack", "password", "hunter2")
+///     .build();
+/// assert_eq!(wallet.size(), 1);
+/// let work = wallet.get("Work").unwrap();
+/// assert_eq!(work.size(), 1);
+/// assert!(work.get_category("AWS").is_some());
+/// assert_eq!(
+///     wallet.get("Work/AWS/Prod").and_then(|prod| prod.get("root-account")),
+///     wallet.get("Work/AWS").and_then(|aws| aws.get_category("Prod")?.get("root-account"))
+/// );
+/// ```
+#[derive(Clone, Eq, Debug)]
+pub struct Wallet {
     categories: BTreeMap<String, Category>,
+    integrity: Option<Integrity>,
+    /// Problems found while loading that the caller chose to accept, for it to show.
+    warnings: Vec<String>,
 }
 
 impl Wallet {
-    pub(crate) fn new() -> Wallet {
+    pub fn new() -> Wallet {
         Wallet {
             categories: BTreeMap::new(),
+            integrity: None,
+            warnings: Vec::new(),
         }
     }
 
-    pub(crate) fn size(&self) -> usize {
+    pub(crate) fn set_integrity(&mut self, integrity: Integrity) {
+        self.integrity = Some(integrity);
+    }
+
+    pub(crate) fn get_integrity(&self) -> Option<&Integrity> {
+        self.integrity.as_ref()
+    }
+
+    pub(crate) fn add_warning(&mut self, warning: Option<String>) {
+        self.warnings.extend(warning);
+    }
+
+    /// Warnings from loading, such as a failed integrity check loaded anyway.
+    pub(crate) fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn size(&self) -> usize {
         self.categories.len()
     }
 
-    pub(crate) fn empty(&self) -> bool {
+    pub fn empty(&self) -> bool {
         self.categories.is_empty()
     }
 
-    pub(crate) fn new_category(&mut self, category_identifier: &String) -> &mut Category {
-        if self.categories.contains_key(category_identifier) {
-            return self.categories.get_mut(category_identifier).unwrap();
+    /// Starts a wallet from nested category, item and entry calls.
+    pub fn builder() -> WalletBuilder {
+        WalletBuilder {
+            wallet: Wallet::new(),
         }
-        self.categories.insert(
-            category_identifier.clone(),
-            Category::new(category_identifier.clone()),
-        );
-        self.categories.get_mut(category_identifier).unwrap()
     }
 
-    pub(crate) fn add_category(&mut self, category: Category) -> bool {
+    /// The top-level categories, ordered by identifier.
+    pub fn categories(&self) -> impl Iterator<Item = &Category> + '_ {
+        self.categories.values()
+    }
+
+    /// Every category with its path, each followed by its sub-categories.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .category("Work/AWS/Prod")
+    ///     .category("Personal")
+    ///     .build();
+    /// let paths: Vec<String> = wallet.all_categories().map(|(path, _)| path).collect();
+    /// assert_eq!(paths, ["Personal", "Work", "Work/AWS", "Work/AWS/Prod"]);
+    /// ```
+    pub fn all_categories(&self) -> impl Iterator<Item = (String, &Category)> + '_ {
+        let mut paths: Vec<(String, &Category)> = Vec::new();
+        for category in self.categories.values() {
+            category.collect_paths(category::escape_name(category.get_ident()), &mut paths);
+        }
+        paths.into_iter()
+    }
+
+    /// The templates of the categories that have one, by category path.
+    pub(crate) fn templates(&self) -> BTreeMap<String, &Template> {
+        self.all_categories()
+            .filter_map(|(path, category)| category.template().map(|template| (path, template)))
+            .collect()
+    }
+
+    /// The ID of every category and item, keyed by the category's path followed by
+    /// `/`, or by the item's path.
+    pub(crate) fn ids(&self) -> BTreeMap<String, Uuid> {
+        let mut ids: BTreeMap<String, Uuid> = BTreeMap::new();
+        for (path, category) in self.all_categories() {
+            for item in category.items() {
+                ids.insert(category::join_path(&path, item.get_ident()), item.id());
+            }
+            ids.insert(format!("{}/", path), category.id());
+        }
+        ids
+    }
+
+    /// Sets the IDs of the categories and items from `ids`, keyed as by `ids`, as
+    /// for a wallet read from a store that keeps them by path. Those with no ID
+    /// there keep the one they have, marked as new. Returns false if any had none.
+    pub(crate) fn set_ids(&mut self, ids: &BTreeMap<String, Uuid>) -> bool {
+        self.set_ids_where(ids, false)
+    }
+
+    /// Like `set_ids`, but only for the categories and items that were read
+    /// without an ID, leaving the IDs read with the rest alone.
+    pub(crate) fn fill_ids(&mut self, ids: &BTreeMap<String, Uuid>) {
+        self.set_ids_where(ids, true);
+    }
+
+    fn set_ids_where(&mut self, ids: &BTreeMap<String, Uuid>, only_new: bool) -> bool {
+        let mut complete: bool = true;
+        for category in self.categories.values_mut() {
+            let path: String = category::escape_name(category.get_ident());
+            complete = category.set_ids(&path, ids, only_new) && complete;
+        }
+        complete
+    }
+
+    /// Whether any category or item was read without an ID, so that the one it
+    /// was given only lasts if the wallet is saved.
+    pub(crate) fn has_new_ids(&self) -> bool {
+        self.categories.values().any(Category::has_new_ids)
+    }
+
+    /// Every entry as a `(category path, item, key, value)` path, in order. Empty
+    /// categories and items have no entries, so they are skipped.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .item("Bank Accounts", "Starling")
+    ///     .entry("Websites", "Google", "password", "pass1234")
+    ///     .entry("Websites", "Google", "url", "https://www.google.com/")
+    ///     .build();
+    /// assert_eq!(
+    ///     wallet.walk().collect::<Vec<_>>(),
+    ///     [
+    ///         (String::from("Websites"), "Google", "password", "pass1234"),
+    ///         (String::from("Websites"), "Google", "url", "https://www.google.com/"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn walk(&self) -> impl Iterator<Item = (String, &str, &str, &str)> + '_ {
+        self.all_categories().flat_map(|(path, category)| {
+            category.items().flat_map(move |item| {
+                let path: String = path.clone();
+                item.entries()
+                    .map(move |(key, value)| (path.clone(), item.get_ident().as_str(), key, value))
+            })
+        })
+    }
+
+    /// Passes every category, item and entry to `visitor`, in order, each
+    /// category followed by its sub-categories.
+    pub fn accept(&mut self, visitor: &mut impl Visitor) {
+        for category in self.categories.values_mut() {
+            let path: String = category::escape_name(category.get_ident());
+            category.accept(&path, visitor);
+        }
+    }
+
+    /// The names of the categories along `category_path`, with `@` IDs resolved,
+    /// and the category at its end. Names that match nothing are kept as they are,
+    /// in which case there is no category.
+    fn resolve(&self, category_path: &str) -> (Vec<String>, Option<&Category>) {
+        let segments: Vec<String> = category::split_path(category_path);
+        let top_level: Option<&Category> = self.categories.get(&segments[0]);
+        let (mut names, mut category) = match top_level {
+            None if segments[0].starts_with('@') => category::find_by_id(
+                &segments[0],
+                self.all_categories()
+                    .map(|(path, category)| (category.id(), (path, category))),
+            )
+            .map(|(path, category)| (category::split_path(&path), Some(category)))
+            .unwrap_or_else(|| (vec![segments[0].clone()], None)),
+            top_level => (vec![segments[0].clone()], top_level),
+        };
+        for segment in &segments[1..] {
+            let name: &str = category
+                .and_then(|parent| parent.category_name(segment))
+                .unwrap_or(segment);
+            names.push(String::from(name));
+            category = category.and_then(|parent| parent.get_category(name));
+        }
+        (names, category)
+    }
+
+    /// `category_path` with any `@` IDs in it replaced by the names they resolve to.
+    pub(crate) fn resolve_path(&self, category_path: &str) -> String {
+        self.resolve(category_path)
+            .0
+            .iter()
+            .fold(String::new(), |path, name| category::join_path(&path, name))
+    }
+
+    /// Returns the category at `category_path`, adding it and any missing
+    /// parents first if there is none.
+    pub fn new_category(&mut self, category_path: &str) -> &mut Category {
+        let names: Vec<String> = self.resolve(category_path).0;
+        let mut category: &mut Category = self
+            .categories
+            .entry(names[0].clone())
+            .or_insert_with(|| Category::new(names[0].clone()));
+        for name in &names[1..] {
+            category = category.new_category(name);
+        }
+        category
+    }
+
+    /// Adds a top-level category. Sub-categories are added with `Category::add_category`.
+    pub fn add_category(&mut self, category: Category) -> bool {
         self.categories
-            .insert(category.get_ident().clone(), category.clone())
+            .insert(category.get_ident().clone(), category)
             .is_none()
     }
 
-    pub(crate) fn get_category(&mut self, category_identifier: &String) -> Option<&mut Category> {
-        self.categories.get_mut(category_identifier)
+    /// Looks a category up without needing a mutable wallet; `get_mut` is for changing it.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .entry("Websites", "Google", "password", "pass1234")
+    ///     .build();
+    /// let password: Option<&str> = wallet
+    ///     .get("Websites")
+    ///     .and_then(|category| category.get("Google"))
+    ///     .and_then(|item| item.get("password"));
+    /// assert_eq!(password, Some("pass1234"));
+    /// ```
+    pub fn get(&self, category_path: &str) -> Option<&Category> {
+        self.resolve(category_path).1
+    }
+
+    pub fn get_mut(&mut self, category_path: &str) -> Option<&mut Category> {
+        let (names, category) = self.resolve(category_path);
+        category?;
+        names[1..]
+            .iter()
+            .try_fold(self.categories.get_mut(&names[0])?, |category, name| {
+                category.get_category_mut(name)
+            })
+    }
+
+    /// The value of the entry at a full path: the category path, then the item and
+    /// the entry key, escaped in the same way as category names. The item can also
+    /// be given by its ID alone, as in `@3f2a/password`.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .entry("Work/AWS/Prod", "root-account", "password", "pass1234")
+    ///     .build();
+    /// assert_eq!(wallet.entry_at("Work/AWS/Prod/root-account/password"), Some("pass1234"));
+    /// assert_eq!(wallet.entry_at("Work/AWS/root-account/password"), None);
+    /// ```
+    pub fn entry_at(&self, entry_path: &str) -> Option<&str> {
+        let (item_path, key) = entry_path.rsplit_once('/')?;
+        let item: &Item = match item_path.rsplit_once('/') {
+            Some((category_path, item)) => self
+                .get(category_path)?
+                .get(&category::unescape_name(item))?,
+            None => self.find_item(item_path)?.1,
+        };
+        item.get(&category::unescape_name(key))
     }
 
-    pub(crate) fn delete_category(&mut self, category_identifier: &String) -> bool {
-        self.categories.remove(category_identifier).is_some()
+    /// Like `entry_at`, but with every `{ref:<entry path>}` in the value replaced by
+    /// the value of the entry it refers to, so that items can share a credential.
+    /// Fails if a reference is to no entry or leads back to one it came from.
+    ///
+    /// ```
+    /// use csc371_remake::Wallet;
+    ///
+    /// let wallet: Wallet = Wallet::builder()
+    ///     .entry("Websites", "Google", "password", "pass1234")
+    ///     .entry("Dashboards", "Grafana", "password", "{ref:Websites/Google/password}")
+    ///     .build();
+    /// assert_eq!(
+    ///     wallet.entry_at("Dashboards/Grafana/password"),
+    ///     Some("{ref:Websites/Google/password}")
+    /// );
+    /// assert_eq!(
+    ///     wallet.resolved_entry_at("Dashboards/Grafana/password")?,
+    ///     Some(String::from("pass1234"))
+    /// );
+    /// # Ok::<(), csc371_remake::Error>(())
+    /// ```
+    pub fn resolved_entry_at(&self, entry_path: &str) -> error::Result<Option<String>> {
+        Ok(self
+            .entry_at(entry_path)
+            .map(|value| reference::resolve(self, entry_path, value))
+            .transpose()?)
     }
 
-    pub(crate) fn load(&mut self, filename: &String) -> bool {
-        let file_contents: String = fs::read_to_string(filename).unwrap();
-        let wallet_values: Value = serde_json::from_str(&file_contents).unwrap();
+    /// The item anywhere in the wallet that `@` and the start of its ID names,
+    /// with the path of its category.
+    pub(crate) fn find_item(&self, address: &str) -> Option<(String, &Item)> {
+        if !address.starts_with('@') {
+            return None;
+        }
+        category::find_by_id(
+            address,
+            self.all_categories().flat_map(|(path, category)| {
+                category
+                    .items()
+                    .map(move |item| (item.id(), (path.clone(), item)))
+            }),
+        )
+    }
 
-        for (cat_ident, category) in wallet_values.as_object().unwrap() {
-            let new_category = self.new_category(cat_ident);
+    /// Takes the category at `category_path` out of the wallet, with everything in it.
+    pub(crate) fn remove_category(&mut self, category_path: &str) -> Option<Category> {
+        let (names, category) = self.resolve(category_path);
+        category?;
+        let (name, parents) = names.split_last()?;
+        match parents {
+            [] => self.categories.remove(name),
+            [top_level, rest @ ..] => rest
+                .iter()
+                .try_fold(self.categories.get_mut(top_level)?, |category, name| {
+                    category.get_category_mut(name)
+                })?
+                .remove_category(name),
+        }
+    }
 
-            for (item_ident, item) in category.as_object().unwrap() {
-                let new_item = new_category.new_item(item_ident);
+    /// Puts `category` at `category_path`, renaming it to the last name in the path
+    /// and adding any missing parents. Returns false if it replaced a category.
+    pub(crate) fn insert_category(&mut self, category_path: &str, mut category: Category) -> bool {
+        let names: Vec<String> = category::split_path(category_path);
+        category.set_ident(&names[names.len() - 1]);
+        match category_path.rsplit_once('/') {
+            Some((parent, _)) => self.new_category(parent).add_category(category),
+            None => self.add_category(category),
+        }
+    }
 
-                for (entry_ident, entry_val) in item.as_object().unwrap() {
-                    let entry_val = unescape(entry_val.as_str().unwrap());
-                    new_item.add_entry(entry_ident, &entry_val.unwrap());
-                }
+    /// Renames the category at `category_path`, keeping its ID and its sub-categories.
+    /// The new name is a path, so renaming can also move it. Replaces any category
+    /// already there, and returns false if there is no such category.
+    pub fn rename_category(&mut self, category_path: &str, new_path: &str) -> bool {
+        match self.remove_category(category_path) {
+            Some(category) => {
+                self.insert_category(new_path, category);
+                true
             }
+            None => false,
         }
-        true
     }
 
-    pub(crate) fn save(&self, filename: &String) -> bool {
-        let json_val: String = serde_json::to_string(&self).unwrap();
-        fs::write(filename, json_val).expect("Unable to write file");
-        true
+    /// Deletes the category at `category_path`, along with its sub-categories.
+    pub fn delete_category(&mut self, category_path: &str) -> bool {
+        self.remove_category(category_path).is_some()
+    }
+
+    /// Replaces the values of sensitive entries with a mask, for output.
+    pub(crate) fn mask_sensitive(&mut self) {
+        for category in self.categories.values_mut() {
+            category.mask_sensitive();
+        }
+    }
+
+    pub(crate) fn merge_categories(&mut self, other: &mut Wallet) {
+        for (key, value) in other.categories.iter_mut() {
+            if self.categories.contains_key(key) {
+                self.categories.get_mut(key).unwrap().merge_items(value);
+            } else {
+                self.categories.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Merges in the changes `theirs` made since `base`, see `merge::three_way`.
+    pub(crate) fn merge_categories_three_way(
+        &mut self,
+        base: &Wallet,
+        theirs: &Wallet,
+    ) -> Vec<Conflict> {
+        merge::merge_children(
+            &mut self.categories,
+            Some(&base.categories),
+            &theirs.categories,
+            category::escape_name,
+            Category::merge_items_three_way,
+        )
+    }
+
+    /// Merges in the wallet at `filename`, which may be a path or a file://,
+    /// sqlite://, dir:// or mem:// URI.
+    pub fn load(&mut self, filename: &str) -> error::Result<()> {
+        Ok(store::open(filename, false, None)?.load(self)?)
+    }
+
+    /// Merges the categories of a JSON wallet into this one.
+    pub fn load_json(&mut self, file_contents: &str) -> error::Result<()> {
+        let mut loaded: Wallet = serde_json::from_str(file_contents).map_err(|error| {
+            error::Error::InvalidData(format!("Error: malformed wallet file: {}", error))
+        })?;
+        self.merge_categories(&mut loaded);
+        Ok(())
+    }
+
+    /// Saves to `filename`, a path or URI as for `load`, replacing what was there.
+    pub fn save(&self, filename: &str) -> error::Result<()> {
+        Ok(store::open(filename, true, None)?.save(self)?)
+    }
+}
+
+impl Default for Wallet {
+    fn default() -> Wallet {
+        Wallet::new()
+    }
+}
+
+/// Builds a wallet in one expression, creating categories and items as they are
+/// named. Categories are named by path, so `Work/AWS` is the `AWS` sub-category of `Work`.
+///
+/// ```
+/// use csc371_remake::Wallet;
+///
+/// let wallet: Wallet = Wallet::builder()
+///     .category("Bank Accounts")
+///     .item("Websites", "Twitter")
+///     .entry("Websites", "Google", "password", "pass1234")
+///     .build();
+/// assert_eq!(wallet.size(), 2);
+/// assert_eq!(wallet.get("Websites").unwrap().size(), 2);
+/// ```
+#[derive(Debug)]
+pub struct WalletBuilder {
+    wallet: Wallet,
+}
+
+impl WalletBuilder {
+    pub fn category(mut self, category_identifier: &str) -> WalletBuilder {
+        self.wallet.new_category(category_identifier);
+        self
+    }
+
+    pub fn item(mut self, category_identifier: &str, item_identifier: &str) -> WalletBuilder {
+        self.wallet
+            .new_category(category_identifier)
+            .new_item(item_identifier);
+        self
+    }
+
+    pub fn entry(
+        mut self,
+        category_identifier: &str,
+        item_identifier: &str,
+        key: &str,
+        value: &str,
+    ) -> WalletBuilder {
+        self.wallet
+            .new_category(category_identifier)
+            .new_item(item_identifier)
+            .add_entry(key, value);
+        self
+    }
+
+    pub fn build(self) -> Wallet {
+        self.wallet
     }
 }
 
@@ -80,7 +509,7 @@ impl fmt::Display for Wallet {
         for category in self.categories.iter() {
             write!(f, "{}", serde_json::to_string(&category).unwrap())?;
         }
-        write!(f, "{}", "")
+        Ok(())
     }
 }
 
@@ -92,6 +521,24 @@ impl PartialEq<Self> for Wallet {
 
 impl Serialize for Wallet {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.serialize_map(serializer, true)
+    }
+}
+
+impl Serialize for WithoutIds<'_, Wallet> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_map(serializer, false)
+    }
+}
+
+impl Wallet {
+    fn serialize_map<S>(&self, serializer: S, with_ids: bool) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
@@ -99,16 +546,43 @@ impl Serialize for Wallet {
             serializer.serialize_map(Some(self.categories.len()))?;
 
         for (category_identifier, category_contents) in &self.categories {
-            map.serialize_entry(&category_identifier, &category_contents)?;
+            let key: String = category::escape_name(category_identifier);
+            if with_ids {
+                map.serialize_entry(&key, category_contents)?;
+            } else {
+                map.serialize_entry(&key, &WithoutIds(category_contents))?;
+            }
         }
         map.end()
     }
 }
 
+impl<'de> Deserialize<'de> for Wallet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let categories: BTreeMap<String, Category> = BTreeMap::deserialize(deserializer)?;
+        Ok(Wallet {
+            categories: categories
+                .into_iter()
+                .map(|(category_key, mut category)| {
+                    let category_identifier: String = category::unescape_name(&category_key);
+                    category.set_ident(&category_identifier);
+                    (category_identifier, category)
+                })
+                .collect(),
+            integrity: None,
+            warnings: Vec::new(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::item::Item;
+    use proptest::prelude::*;
     use std::{fs, io::Write, path::Path};
 
     #[test]
@@ -129,10 +603,7 @@ mod tests {
         assert!(wallet.add_category(first_category.clone()));
         assert_eq!(wallet.size(), 1);
         assert!(!wallet.empty());
-        assert_eq!(
-            wallet.get_category(&first_cat_ident).unwrap(),
-            &first_category
-        );
+        assert_eq!(wallet.get(&first_cat_ident).unwrap(), &first_category);
 
         let second_category: Category = Category::new(first_cat_ident.clone());
         assert!(second_category.empty());
@@ -146,10 +617,7 @@ mod tests {
         assert!(wallet.add_category(third_category.clone()));
         assert_eq!(wallet.size(), 2);
         assert!(!wallet.empty());
-        assert_eq!(
-            wallet.get_category(&third_cat_ident).unwrap(),
-            &third_category
-        );
+        assert_eq!(wallet.get(&third_cat_ident).unwrap(), &third_category);
     }
 
     #[test]
@@ -162,10 +630,7 @@ mod tests {
         assert!(wallet.add_category(first_category.clone()));
         assert_eq!(wallet.size(), 1);
         assert!(!wallet.empty());
-        assert_eq!(
-            wallet.get_category(&first_cat_ident).unwrap(),
-            &first_category
-        );
+        assert_eq!(wallet.get(&first_cat_ident).unwrap(), &first_category);
         assert!(wallet.delete_category(&first_cat_ident));
         //add exception check here
         assert_eq!(wallet.size(), 0);
@@ -215,197 +680,149 @@ mod tests {
         let mut wallet: Wallet = Wallet::new();
         assert!(wallet.empty());
         //do error checking here instead of boolean checks.
-        assert!(wallet.load(&file_path));
+        assert!(wallet.load(&file_path).is_ok());
         assert_eq!(wallet.size(), 2);
 
         let web: String = String::from("Websites");
-        assert!(wallet.get_category(&web).is_some());
-        assert_eq!(wallet.get_category(&web).unwrap().size(), 3);
+        assert!(wallet.get(&web).is_some());
+        assert_eq!(wallet.get(&web).unwrap().size(), 3);
 
         let google: String = String::from("Google");
-        assert!(wallet
-            .get_category(&web)
-            .unwrap()
-            .get_item(&google)
-            .is_some());
+        assert!(wallet.get(&web).unwrap().get(&google).is_some());
+        assert_eq!(wallet.get(&web).unwrap().get(&google).unwrap().size(), 3);
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&google)
+                .get(&google)
                 .unwrap()
-                .size(),
-            3
-        );
-        assert_eq!(
-            wallet
-                .get_category(&web)
-                .unwrap()
-                .get_item(&google)
-                .unwrap()
-                .get_entry(&String::from("url"))
+                .get(&String::from("url"))
                 .unwrap(),
             &String::from("https://www.google.com/")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&google)
+                .get(&google)
                 .unwrap()
-                .get_entry(&String::from("username"))
+                .get(&String::from("username"))
                 .unwrap(),
             &String::from("example@gmail.com")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&google)
+                .get(&google)
                 .unwrap()
-                .get_entry(&String::from("password"))
+                .get(&String::from("password"))
                 .unwrap(),
             &String::from("pass1234")
         );
 
         let facebook: String = String::from("Facebook");
-        assert!(wallet
-            .get_category(&web)
-            .unwrap()
-            .get_item(&facebook)
-            .is_some());
+        assert!(wallet.get(&web).unwrap().get(&facebook).is_some());
+        assert_eq!(wallet.get(&web).unwrap().get(&facebook).unwrap().size(), 3);
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&facebook)
+                .get(&facebook)
                 .unwrap()
-                .size(),
-            3
-        );
-        assert_eq!(
-            wallet
-                .get_category(&web)
-                .unwrap()
-                .get_item(&facebook)
-                .unwrap()
-                .get_entry(&String::from("url"))
+                .get(&String::from("url"))
                 .unwrap(),
             &String::from("https://www.facebook.com/")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&facebook)
+                .get(&facebook)
                 .unwrap()
-                .get_entry(&String::from("username"))
+                .get(&String::from("username"))
                 .unwrap(),
             &String::from("example@gmail.com")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&facebook)
+                .get(&facebook)
                 .unwrap()
-                .get_entry(&String::from("password"))
+                .get(&String::from("password"))
                 .unwrap(),
             &String::from("pass1234fb")
         );
 
         let twitter: String = String::from("Twitter");
-        assert!(wallet
-            .get_category(&web)
-            .unwrap()
-            .get_item(&twitter)
-            .is_some());
+        assert!(wallet.get(&web).unwrap().get(&twitter).is_some());
+        assert_eq!(wallet.get(&web).unwrap().get(&twitter).unwrap().size(), 3);
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&twitter)
+                .get(&twitter)
                 .unwrap()
-                .size(),
-            3
-        );
-        assert_eq!(
-            wallet
-                .get_category(&web)
-                .unwrap()
-                .get_item(&twitter)
-                .unwrap()
-                .get_entry(&String::from("url"))
+                .get(&String::from("url"))
                 .unwrap(),
             &String::from("https://www.twitter.com/")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&twitter)
+                .get(&twitter)
                 .unwrap()
-                .get_entry(&String::from("username"))
+                .get(&String::from("username"))
                 .unwrap(),
             &String::from("example@gmail.com")
         );
         assert_eq!(
             wallet
-                .get_category(&web)
+                .get(&web)
                 .unwrap()
-                .get_item(&twitter)
+                .get(&twitter)
                 .unwrap()
-                .get_entry(&String::from("password"))
+                .get(&String::from("password"))
                 .unwrap(),
             &String::from("r43rfsffdsfdsf")
         );
 
         let bank: String = String::from("Bank Accounts");
-        assert!(wallet.get_category(&bank).is_some());
-        assert_eq!(wallet.get_category(&bank).unwrap().size(), 1);
+        assert!(wallet.get(&bank).is_some());
+        assert_eq!(wallet.get(&bank).unwrap().size(), 1);
 
         let starling: String = String::from("Starling");
-        assert!(wallet
-            .get_category(&bank)
-            .unwrap()
-            .get_item(&starling)
-            .is_some());
-        assert_eq!(
-            wallet
-                .get_category(&bank)
-                .unwrap()
-                .get_item(&starling)
-                .unwrap()
-                .size(),
-            3
-        );
+        assert!(wallet.get(&bank).unwrap().get(&starling).is_some());
+        assert_eq!(wallet.get(&bank).unwrap().get(&starling).unwrap().size(), 3);
         assert_eq!(
             wallet
-                .get_category(&bank)
+                .get(&bank)
                 .unwrap()
-                .get_item(&starling)
+                .get(&starling)
                 .unwrap()
-                .get_entry(&String::from("Name"))
+                .get(&String::from("Name"))
                 .unwrap(),
             &String::from("Mr John Doe")
         );
         assert_eq!(
             wallet
-                .get_category(&bank)
+                .get(&bank)
                 .unwrap()
-                .get_item(&starling)
+                .get(&starling)
                 .unwrap()
-                .get_entry(&String::from("Account Number"))
+                .get(&String::from("Account Number"))
                 .unwrap(),
             &String::from("12345678")
         );
         assert_eq!(
             wallet
-                .get_category(&bank)
+                .get(&bank)
                 .unwrap()
-                .get_item(&starling)
+                .get(&starling)
                 .unwrap()
-                .get_entry(&String::from("Sort Code"))
+                .get(&String::from("Sort Code"))
                 .unwrap(),
             &String::from("12-34-56")
         );
@@ -413,8 +830,11 @@ mod tests {
 
     #[test]
     fn test_save_json_file() {
-        let file_path: String = String::from("./tests/testsave.json");
-        assert!(Path::new(&file_path).exists());
+        //A copy, so that the fixture is left as it is.
+        let path =
+            std::env::temp_dir().join(format!("371pass-testsave-{}.json", std::process::id()));
+        fs::copy("./tests/testsave.json", &path).unwrap();
+        let file_path: String = path.to_string_lossy().to_string();
         let mut file: fs::File = fs::OpenOptions::new()
             .write(true)
             .truncate(true)
@@ -456,9 +876,340 @@ mod tests {
         wallet.add_category(cat_2);
 
         assert_eq!(wallet.size(), 2);
-        assert!(wallet.save(&file_path));
+
+        //IDs are random, so known ones are set before saving.
+        let ids: BTreeMap<String, Uuid> = wallet
+            .ids()
+            .into_keys()
+            .zip(1..)
+            .map(|(path, number)| (path, Uuid::from_u128(number)))
+            .collect();
+        assert!(wallet.set_ids(&ids));
+
+        assert!(wallet.save(&file_path).is_ok());
         let file_contents: String = fs::read_to_string(&file_path).expect("Unable to read file");
-        let expected_contents: &str = r#"{"ident_1":{"ident_1":{"key_1":"value_1","key_2":"value_2"},"ident_2":{"key_1":"value_1"}},"ident_2":{"ident_1":{"key_1":"value_1","key_2":"value_2"}}}"#;
+        let expected_contents: &str = r#"{"format":"371pass","version":7,"categories":{"ident_1":{"@id":"00000000-0000-0000-0000-000000000001","ident_1":{"@id":"00000000-0000-0000-0000-000000000002","key_1":"value_1","key_2":"value_2"},"ident_2":{"@id":"00000000-0000-0000-0000-000000000003","key_1":"value_1"}},"ident_2":{"@id":"00000000-0000-0000-0000-000000000004","ident_1":{"@id":"00000000-0000-0000-0000-000000000005","key_1":"value_1","key_2":"value_2"}}}}"#;
         assert_eq!(file_contents, expected_contents);
+
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        let path =
+            std::env::temp_dir().join(format!("371pass-integrity-{}.json", std::process::id()));
+        let file_path: String = path.to_string_lossy().to_string();
+        let integrity: Integrity = Integrity::new(b"integrity key");
+
+        let mut wallet: Wallet = Wallet::new();
+        wallet.set_integrity(integrity.clone());
+        wallet
+            .new_category("Websites")
+            .new_item("Google")
+            .add_entry("password", "pass1234");
+        assert!(wallet.save(&file_path).is_ok());
+
+        let mut loaded: Wallet = Wallet::new();
+        loaded.set_integrity(integrity.clone());
+        assert!(loaded.load(&file_path).is_ok());
+        assert_eq!(loaded, wallet);
+
+        //Hand-edit the file.
+        let edited: String = fs::read_to_string(&file_path)
+            .unwrap()
+            .replace("pass1234", "hunter2");
+        fs::write(&file_path, edited).unwrap();
+
+        let mut tampered: Wallet = Wallet::new();
+        tampered.set_integrity(integrity.clone());
+        let result = tampered.load(&file_path);
+        assert!(matches!(result, Err(error::Error::InvalidData(_))));
+
+        //The escape hatch loads it anyway.
+        let mut accepted: Wallet = Wallet::new();
+        accepted.set_integrity(integrity.accept_tampered(true));
+        assert!(accepted.load(&file_path).is_ok());
+        assert_eq!(accepted.size(), 1);
+        assert_eq!(accepted.warnings().len(), 1);
+        assert!(accepted.warnings()[0].contains("integrity check"));
+        assert!(loaded.warnings().is_empty());
+
+        //Without a key nothing is checked.
+        assert!(Wallet::new().load(&file_path).is_ok());
+
+        fs::remove_file(Integrity::tag_filename(&file_path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    type Items = BTreeMap<String, BTreeMap<String, String>>;
+
+    fn arbitrary_category(identifier: String, items: Items) -> Category {
+        let mut category: Category = Category::new(identifier);
+        for (item_ident, entries) in items {
+            let item: &mut Item = category.new_item(&item_ident);
+            for (key, value) in entries {
+                item.add_entry(&key, &value);
+            }
+        }
+        category
+    }
+
+    //Names are arbitrary, so categories are added by name rather than by path.
+    fn arbitrary_wallet() -> impl Strategy<Value = Wallet> {
+        let entries = prop::collection::btree_map(any::<String>(), any::<String>(), 0..4);
+        let items = prop::collection::btree_map(any::<String>(), entries, 0..4);
+        let sub_categories = prop::collection::btree_map(any::<String>(), items.clone(), 0..3);
+        prop::collection::btree_map(any::<String>(), (items, sub_categories), 0..4).prop_map(
+            |categories| {
+                let mut wallet: Wallet = Wallet::new();
+                for (cat_ident, (items, sub_categories)) in categories {
+                    let mut category: Category = arbitrary_category(cat_ident, items);
+                    for (sub_cat_ident, items) in sub_categories {
+                        category.add_category(arbitrary_category(sub_cat_ident, items));
+                    }
+                    wallet.add_category(category);
+                }
+                wallet
+            },
+        )
+    }
+
+    //Category equality ignores identifiers, so they are checked separately.
+    fn assert_identifiers(wallet: &Wallet) {
+        for (cat_ident, category) in wallet.categories.iter() {
+            assert_eq!(category.get_ident(), cat_ident);
+            for sub_category in category.categories() {
+                assert_eq!(
+                    category.get_category(sub_category.get_ident()),
+                    Some(sub_category)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_categories() {
+        let mut wallet: Wallet = Wallet::builder()
+            .entry("Work/AWS/Prod", "root-account", "password", "pass1234")
+            .item("Work", "Slack")
+            .item("a%2Fb", "c")
+            .build();
+
+        //Parents are created along the way, and escaped names stay one category.
+        assert_eq!(
+            wallet
+                .all_categories()
+                .map(|(path, category)| (path, category.get_ident().as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (String::from("Work"), "Work"),
+                (String::from("Work/AWS"), "AWS"),
+                (String::from("Work/AWS/Prod"), "Prod"),
+                (String::from("a%2Fb"), "a/b"),
+            ]
+        );
+        assert_eq!(wallet.get("Work").unwrap().size(), 1);
+        assert!(!wallet.get("Work/AWS").unwrap().empty());
+        assert!(wallet.get("Work/Prod").is_none());
+        assert!(wallet.get("a").is_none());
+
+        //Moving a category takes its sub-categories with it.
+        let aws: Category = wallet.remove_category("Work/AWS").unwrap();
+        assert!(wallet.insert_category("Cloud/Amazon", aws));
+        assert!(wallet.get("Work/AWS").is_none());
+        let id: Uuid = wallet.get("Work").unwrap().id();
+        assert!(wallet.rename_category("Work", "Office"));
+        assert_eq!(wallet.get("Office").unwrap().id(), id);
+        assert_eq!(wallet.get("Office").unwrap().get_ident(), "Office");
+        assert!(!wallet.rename_category("Work", "Office"));
+        assert_eq!(
+            wallet.entry_at("Cloud/Amazon/Prod/root-account/password"),
+            Some("pass1234")
+        );
+        assert_eq!(wallet.get("Cloud/Amazon").unwrap().get_ident(), "Amazon");
+
+        //Merging recurses into sub-categories.
+        let mut other: Wallet = Wallet::builder()
+            .entry("Cloud/Amazon/Prod", "root-account", "username", "root")
+            .entry("Cloud/Amazon/Dev", "root-account", "password", "dev")
+            .build();
+        wallet.merge_categories(&mut other);
+        assert_eq!(
+            wallet
+                .get("Cloud/Amazon/Prod")
+                .unwrap()
+                .get("root-account")
+                .unwrap()
+                .size(),
+            2
+        );
+        assert!(wallet.get("Cloud/Amazon/Dev").is_some());
+
+        assert!(wallet.delete_category("Cloud/Amazon"));
+        assert!(wallet.get("Cloud").unwrap().empty());
+        assert!(!wallet.delete_category("Cloud/Amazon"));
+    }
+
+    #[test]
+    fn test_nested_serialization() {
+        let wallet: Wallet = Wallet::builder()
+            .entry("Work", "Slack", "password", "hunter2")
+            .entry("Work/AWS", "root/admin", "password", "pass1234")
+            .item("Work/AWS%2FGCP", "100%")
+            .build();
+        assert_eq!(
+            serde_json::to_string(&WithoutIds(&wallet)).unwrap(),
+            r#"{"Work":{"Slack":{"password":"hunter2"},"AWS/":{"root%2Fadmin":{"password":"pass1234"}},"AWS%2FGCP/":{"100%25":{}}}}"#
+        );
+
+        //Each category and item is saved with its ID.
+        let json: String = serde_json::to_string(&wallet).unwrap();
+        let work: &Category = wallet.get("Work").unwrap();
+        assert!(json.starts_with(&format!(
+            r#"{{"Work":{{"@id":"{}","Slack":{{"@id":"{}","password":"hunter2"}}"#,
+            work.id(),
+            work.get("Slack").unwrap().id()
+        )));
+        let loaded: Wallet = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, wallet);
+        assert_eq!(loaded.ids(), wallet.ids());
+        assert!(!loaded.has_new_ids());
+        assert_eq!(loaded.get("Work/AWS%2FGCP").unwrap().get_ident(), "AWS/GCP");
+    }
+
+    #[test]
+    fn test_addressing_by_id() {
+        let mut wallet: Wallet = Wallet::builder()
+            .entry("Work/AWS/Prod", "root-account", "password", "pass1234")
+            .entry("Work", "Slack", "password", "hunter2")
+            .build();
+        let aws: String = format!("@{}", wallet.get("Work/AWS").unwrap().id());
+        let slack: Uuid = wallet.get("Work").unwrap().get("Slack").unwrap().id();
+
+        //A leading ID names a category anywhere, and later ones a sub-category.
+        assert_eq!(wallet.get(&aws).unwrap().get_ident(), "AWS");
+        assert_eq!(
+            wallet.get(&format!("{}/Prod", aws)),
+            wallet.get("Work/AWS/Prod")
+        );
+        assert_eq!(wallet.get(&format!("Work/{}", &aws[..9])), wallet.get(&aws));
+        assert_eq!(
+            wallet.resolve_path(&format!("{}/Prod", aws)),
+            "Work/AWS/Prod"
+        );
+        assert_eq!(
+            wallet
+                .get("Work")
+                .unwrap()
+                .get(&format!("@{}", slack.simple())),
+            wallet.get("Work").unwrap().get("Slack")
+        );
+        assert_eq!(
+            wallet.entry_at(&format!("@{}/password", slack)),
+            Some("hunter2")
+        );
+        assert!(wallet.get("@").is_none());
+        assert!(wallet.get("@zz").is_none());
+
+        //Renaming keeps the ID, so the same address still works.
+        let moved: Category = wallet.remove_category(&aws).unwrap();
+        wallet.insert_category("Cloud", moved);
+        assert_eq!(wallet.resolve_path(&aws), "Cloud");
+        assert!(wallet
+            .get_mut("Work")
+            .unwrap()
+            .rename_item("Slack", "Slack (work)"));
+        assert_eq!(
+            wallet.entry_at(&format!("@{}/password", slack)),
+            Some("hunter2")
+        );
+        assert!(wallet.delete_category(&aws));
+        assert!(wallet.get("Cloud").is_none());
+    }
+
+    #[test]
+    fn test_model_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Wallet>();
+        assert_send_sync::<Category>();
+        assert_send_sync::<Item>();
+    }
+
+    #[test]
+    fn test_visitor_sees_empty_categories_and_items() {
+        #[derive(Default)]
+        struct Paths(Vec<String>);
+
+        impl Visitor for Paths {
+            fn visit_category(&mut self, category: &str) {
+                self.0.push(String::from(category));
+            }
+
+            fn visit_item(&mut self, category: &str, item: &str) {
+                self.0.push(format!("{}/{}", category, item));
+            }
+
+            fn visit_entry(&mut self, category: &str, item: &str, key: &str, value: &mut String) {
+                self.0.push(format!("{}/{}/{}", category, item, key));
+                value.clear();
+            }
+        }
+
+        let mut wallet: Wallet = Wallet::builder()
+            .category("Bank Accounts")
+            .item("Websites", "Twitter")
+            .entry("Websites", "Google", "password", "pass1234")
+            .build();
+        let mut paths: Paths = Paths::default();
+        wallet.accept(&mut paths);
+        assert_eq!(
+            paths.0,
+            [
+                "Bank Accounts",
+                "Websites",
+                "Websites/Google",
+                "Websites/Google/password",
+                "Websites/Twitter"
+            ]
+        );
+        assert_eq!(
+            wallet.walk().collect::<Vec<_>>(),
+            [(String::from("Websites"), "Google", "password", "")]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_serde_round_trip(wallet in arbitrary_wallet()) {
+            let json: Wallet = serde_json::from_str(&serde_json::to_string(&wallet).unwrap()).unwrap();
+            assert_identifiers(&json);
+            prop_assert_eq!(&json, &wallet);
+            prop_assert_eq!(json.ids(), wallet.ids());
+
+            let yaml: Wallet = serde_yaml::from_str(&serde_yaml::to_string(&wallet).unwrap()).unwrap();
+            prop_assert_eq!(&yaml, &wallet);
+            prop_assert_eq!(yaml.ids(), wallet.ids());
+
+            let toml: Wallet = toml::from_str(&toml::to_string(&wallet).unwrap()).unwrap();
+            prop_assert_eq!(&toml, &wallet);
+            prop_assert_eq!(toml.ids(), wallet.ids());
+
+            let msgpack: Wallet = rmp_serde::from_slice(&rmp_serde::to_vec(&wallet).unwrap()).unwrap();
+            prop_assert_eq!(&msgpack, &wallet);
+            prop_assert_eq!(msgpack.ids(), wallet.ids());
+
+            let mut cbor: Vec<u8> = Vec::new();
+            ciborium::into_writer(&wallet, &mut cbor).unwrap();
+            let cbor: Wallet = ciborium::from_reader(cbor.as_slice()).unwrap();
+            prop_assert_eq!(&cbor, &wallet);
+            prop_assert_eq!(cbor.ids(), wallet.ids());
+        }
+
+        #[test]
+        fn test_load_json_matches_save(wallet in arbitrary_wallet()) {
+            let mut loaded: Wallet = Wallet::new();
+            prop_assert!(loaded.load_json(&serde_json::to_string(&wallet).unwrap()).is_ok());
+            prop_assert_eq!(loaded, wallet);
+        }
     }
 }